@@ -0,0 +1,312 @@
+use crate::db::column_families::DbColumnFamily;
+use crate::db::RocksDB;
+use crate::store::{hub_error_to_js_throw, HubError};
+use crate::THREAD_POOL;
+use neon::context::{Context, FunctionContext};
+use neon::handle::Handle;
+use neon::object::Object;
+use neon::result::JsResult;
+use neon::types::buffer::TypedArray;
+use neon::types::{Finalize, JsArray, JsBox, JsBuffer, JsPromise, JsValue};
+use rocksdb::SstFileWriter;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/** The keys of a column family are written to a new SST file once they add up to this many bytes */
+const SST_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/** Tells the temporary directories of the bulk loaders of a process apart */
+static NEXT_LOADER_ID: AtomicU64 = AtomicU64::new(0);
+
+/** What a bulk load wrote */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BulkLoadReport {
+    pub keys: u64,
+    pub bytes: u64,
+    pub files: u64,
+}
+
+/** The keys of a column family that haven't been written to an SST file yet */
+#[derive(Default)]
+struct PendingFile {
+    keys: Vec<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+}
+
+/**
+ * Loads a stream of keys into a RocksDB by writing them to SST files, which are then ingested
+ * into the DB in one go. This skips the memtables, the WAL and most of the compactions, so it is
+ * much faster than committing the keys, but the keys have to be added in strictly increasing
+ * order, and the DB has to be loading (see RocksDB::begin_loading) until the load is finished.
+ * Keys are routed to their column families like any other write, so both the main DB and the
+ * trie DB can be loaded this way.
+ */
+pub struct BulkLoader {
+    db: Arc<RocksDB>,
+    dir: PathBuf,
+    pending: HashMap<DbColumnFamily, PendingFile>,
+    files: Vec<(DbColumnFamily, PathBuf)>,
+    last_key: Option<Vec<u8>>,
+    report: BulkLoadReport,
+}
+
+/** Needed so the loader can be passed to JS in a JsBox */
+impl Finalize for BulkLoader {}
+
+impl BulkLoader {
+    pub(crate) fn new(db: Arc<RocksDB>) -> Self {
+        let id = NEXT_LOADER_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = PathBuf::from(format!(
+            "{}.bulk_load.{}.{}",
+            db.location(),
+            std::process::id(),
+            id
+        ));
+
+        BulkLoader {
+            db,
+            dir,
+            pending: HashMap::new(),
+            files: vec![],
+            last_key: None,
+            report: BulkLoadReport::default(),
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<(), HubError> {
+        if self
+            .last_key
+            .as_ref()
+            .is_some_and(|last_key| key <= last_key.as_slice())
+        {
+            return Err(HubError {
+                code: "db.bulk_load_unsorted".to_string(),
+                message: format!("keys must be added in increasing order: {:x?}", key),
+            });
+        }
+        self.last_key = Some(key.to_vec());
+
+        let cf = DbColumnFamily::for_key(key);
+        let pending = self.pending.entry(cf).or_default();
+        pending.keys.push((key.to_vec(), value.to_vec()));
+        pending.size += key.len() + value.len();
+
+        self.report.keys += 1;
+        self.report.bytes += (key.len() + value.len()) as u64;
+
+        if pending.size >= SST_FILE_SIZE {
+            self.write_file(cf)?;
+        }
+
+        Ok(())
+    }
+
+    /** Write the pending keys of `cf` to a new SST file */
+    fn write_file(&mut self, cf: DbColumnFamily) -> Result<(), HubError> {
+        let keys = match self.pending.remove(&cf) {
+            Some(pending) if !pending.keys.is_empty() => pending.keys,
+            _ => return Ok(()),
+        };
+
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self
+            .dir
+            .join(format!("{}-{:06}.sst", cf.name(), self.files.len()));
+
+        // The files get the column family's own options, so they have the same bloom filters
+        // and compression as the files RocksDB writes itself
+        let opts = self.db.cf_options(cf);
+        let mut writer = SstFileWriter::create(&opts);
+        writer.open(&path)?;
+        for (key, value) in keys {
+            writer.put(key, value)?;
+        }
+        writer.finish()?;
+
+        self.files.push((cf, path));
+        self.report.files += 1;
+        Ok(())
+    }
+
+    /** Write the remaining keys and ingest all the files into the DB */
+    pub fn finish(mut self) -> Result<BulkLoadReport, HubError> {
+        let column_families = self.pending.keys().copied().collect::<Vec<_>>();
+        for cf in column_families {
+            self.write_file(cf)?;
+        }
+
+        let mut files_by_cf = HashMap::<DbColumnFamily, Vec<PathBuf>>::new();
+        for (cf, path) in self.files.drain(..) {
+            files_by_cf.entry(cf).or_default().push(path);
+        }
+        self.db.ingest_files(files_by_cf)?;
+
+        Ok(self.report.clone())
+    }
+}
+
+impl Drop for BulkLoader {
+    fn drop(&mut self) {
+        // Ingested files were moved into the DB, anything left over was abandoned
+        if self.dir.exists() {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/** How a loader is handed to JS. It is taken out once it is finished */
+pub type SharedBulkLoader = Arc<Mutex<Option<BulkLoader>>>;
+
+fn take_loader(cx: &mut FunctionContext) -> Result<(SharedBulkLoader, bool), neon::result::Throw> {
+    let loader = cx.argument::<JsBox<SharedBulkLoader>>(0)?;
+    let loader = (**loader).clone();
+    let is_finished = loader.lock().unwrap().is_none();
+    Ok((loader, is_finished))
+}
+
+fn finished_error() -> HubError {
+    HubError::invalid_parameter("bulk loader is already finished")
+}
+
+impl BulkLoader {
+    /** Add keys[i] with values[i], the keys must be sorted and greater than the keys added before */
+    pub fn js_add(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (loader, is_finished) = take_loader(&mut cx)?;
+        if is_finished {
+            return hub_error_to_js_throw(&mut cx, finished_error());
+        }
+
+        let js_keys = cx.argument::<JsArray>(1)?.to_vec(&mut cx)?;
+        let js_values = cx.argument::<JsArray>(2)?.to_vec(&mut cx)?;
+        if js_keys.len() != js_values.len() {
+            return hub_error_to_js_throw(
+                &mut cx,
+                HubError::invalid_parameter("keys and values must have the same length"),
+            );
+        }
+
+        let to_vec = |cx: &mut FunctionContext, value: Handle<JsValue>| {
+            value
+                .downcast_or_throw::<JsBuffer, _>(cx)
+                .map(|buffer| buffer.as_slice(cx).to_vec())
+        };
+        let mut pairs = Vec::with_capacity(js_keys.len());
+        for (key, value) in js_keys.into_iter().zip(js_values) {
+            pairs.push((to_vec(&mut cx, key)?, to_vec(&mut cx, value)?));
+        }
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+        THREAD_POOL.lock().unwrap().execute(move || {
+            let result = match loader.lock().unwrap().as_mut() {
+                Some(loader) => pairs
+                    .iter()
+                    .try_for_each(|(key, value)| loader.add(key, value)),
+                None => Err(finished_error()),
+            };
+
+            deferred.settle_with(&channel, move |mut cx| match result {
+                Ok(()) => Ok(cx.undefined()),
+                Err(e) => hub_error_to_js_throw(&mut cx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+
+    /** Ingest everything that was added. Resolves to the number of keys, bytes and files */
+    pub fn js_finish(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let (loader, _) = take_loader(&mut cx)?;
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // Ingesting can take a while, so don't hold up the thread pool with it
+        std::thread::spawn(move || {
+            let result = match loader.lock().unwrap().take() {
+                Some(loader) => loader.finish(),
+                None => Err(finished_error()),
+            };
+
+            deferred.settle_with(&channel, move |mut cx| {
+                let report = match result {
+                    Ok(report) => report,
+                    Err(e) => return hub_error_to_js_throw(&mut cx, e),
+                };
+
+                let js_report = cx.empty_object();
+                let value = cx.number(report.keys as f64);
+                js_report.set(&mut cx, "keys", value)?;
+                let value = cx.number(report.bytes as f64);
+                js_report.set(&mut cx, "bytes", value)?;
+                let value = cx.number(report.files as f64);
+                js_report.set(&mut cx, "files", value)?;
+
+                Ok(js_report)
+            });
+        });
+
+        Ok(promise)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RootPrefix;
+
+    #[test]
+    fn test_bulk_loader() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(RocksDB::new(tmp_dir.path().to_str().unwrap()).unwrap());
+        db.open().unwrap();
+        db.put(b"existing", b"value").unwrap();
+
+        // Only a loading DB can be bulk loaded
+        assert_eq!(db.bulk_loader().err().unwrap().code, "db.not_loading");
+        db.begin_loading().unwrap();
+
+        let user_key = |i: u8| vec![RootPrefix::User as u8, 0, 0, 0, 1, 1, i];
+        let trie_key = |i: u8| vec![RootPrefix::SyncMerkleTrieNode as u8, i];
+
+        let mut loader = db.bulk_loader().unwrap();
+        for i in 0..10 {
+            loader.add(&user_key(i), &[i]).unwrap();
+        }
+        for i in 0..5 {
+            loader.add(&trie_key(i), &[i]).unwrap();
+        }
+
+        // Keys have to be sorted
+        let err = loader.add(&user_key(20), &[0]).unwrap_err();
+        assert_eq!(err.code, "db.bulk_load_unsorted");
+        let err = loader.add(&trie_key(4), &[0]).unwrap_err();
+        assert_eq!(err.code, "db.bulk_load_unsorted");
+
+        // Nothing is visible until the loader is finished
+        assert_eq!(db.get(&user_key(0)).unwrap(), None);
+        let dir = loader.dir.clone();
+        let report = loader.finish().unwrap();
+        assert_eq!(report.keys, 15);
+        assert_eq!(report.files, 2);
+        assert!(!dir.exists());
+
+        for i in 0..10 {
+            assert_eq!(db.get(&user_key(i)).unwrap(), Some(vec![i]));
+        }
+        for i in 0..5 {
+            assert_eq!(db.get(&trie_key(i)).unwrap(), Some(vec![i]));
+        }
+        assert_eq!(db.get(b"existing").unwrap(), Some(b"value".to_vec()));
+
+        // The key counters are rebuilt once loading is done
+        db.end_loading().unwrap();
+        assert_eq!(db.count_keys_at_prefix(&user_key(0)[..6]).unwrap(), 10);
+        assert_eq!(db.bulk_loader().err().unwrap().code, "db.not_loading");
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use crate::db::event_expiry::HubEventExpiryFilterFactory;
+use crate::db::RocksDbOptions;
+use crate::store::{increment_vec_u8, RootPrefix, FID_BYTES};
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamilyDescriptor, DBCompressionType, Options, SliceTransform,
+};
+
+/** User keys start with the RootPrefix byte and the 4-byte fid, so all keys of a fid share this prefix */
+pub const USER_KEY_PREFIX_LENGTH: usize = 1 + FID_BYTES;
+
+/**
+ * The column families the hub keyspace is split into. Keys are not rewritten when they are stored
+ * in a column family, they keep their RootPrefix byte, and are routed to a column family using
+ * that first byte. This means callers never need to know which column family a key lives in.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DbColumnFamily {
+    /* Everything that doesn't have a column family of its own (HubState, OnChainEvents, etc...) */
+    Default = 0,
+    /* Messages and the per-fid indexes, keyed by RootPrefix::User */
+    User = 1,
+    /* The secondary indexes that are keyed by something other than the fid */
+    Indexes = 2,
+    /* The event log */
+    HubEvents = 3,
+    /* The sync merkle trie nodes */
+    SyncTrie = 4,
+    /* Per-(fid, postfix) key counts. Not part of the keyspace, no key is ever routed here */
+    KeyCounters = 5,
+}
+
+pub const ALL_COLUMN_FAMILIES: [DbColumnFamily; 5] = [
+    DbColumnFamily::Default,
+    DbColumnFamily::User,
+    DbColumnFamily::Indexes,
+    DbColumnFamily::HubEvents,
+    DbColumnFamily::SyncTrie,
+];
+
+/** The RootPrefixes that are stored outside of the default column family */
+const ROUTED_PREFIXES: [(u8, DbColumnFamily); 8] = [
+    (RootPrefix::User as u8, DbColumnFamily::User),
+    (RootPrefix::CastsByParent as u8, DbColumnFamily::Indexes),
+    (RootPrefix::CastsByMention as u8, DbColumnFamily::Indexes),
+    (RootPrefix::LinksByTarget as u8, DbColumnFamily::Indexes),
+    (RootPrefix::ReactionsByTarget as u8, DbColumnFamily::Indexes),
+    (
+        RootPrefix::VerificationByAddress as u8,
+        DbColumnFamily::Indexes,
+    ),
+    (RootPrefix::HubEvents as u8, DbColumnFamily::HubEvents),
+    (
+        RootPrefix::SyncMerkleTrieNode as u8,
+        DbColumnFamily::SyncTrie,
+    ),
+];
+
+/** A [lower, upper) key range that lies entirely within one column family */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamilyRange {
+    pub cf: DbColumnFamily,
+    pub lower: Vec<u8>,
+    pub upper: Vec<u8>,
+}
+
+impl DbColumnFamily {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DbColumnFamily::Default => rocksdb::DEFAULT_COLUMN_FAMILY_NAME,
+            DbColumnFamily::User => "user",
+            DbColumnFamily::Indexes => "indexes",
+            DbColumnFamily::HubEvents => "hub_events",
+            DbColumnFamily::SyncTrie => "sync_trie",
+            DbColumnFamily::KeyCounters => "key_counters",
+        }
+    }
+
+    pub fn for_prefix_byte(prefix: u8) -> DbColumnFamily {
+        ROUTED_PREFIXES
+            .iter()
+            .find(|(p, _)| *p == prefix)
+            .map(|(_, cf)| *cf)
+            .unwrap_or(DbColumnFamily::Default)
+    }
+
+    pub fn for_key(key: &[u8]) -> DbColumnFamily {
+        match key.first() {
+            Some(prefix) => DbColumnFamily::for_prefix_byte(*prefix),
+            None => DbColumnFamily::Default,
+        }
+    }
+
+    /** The RootPrefix bytes that are routed to this column family */
+    pub fn prefix_bytes(&self) -> Vec<u8> {
+        ROUTED_PREFIXES
+            .iter()
+            .filter(|(_, cf)| cf == self)
+            .map(|(p, _)| *p)
+            .collect()
+    }
+
+    /** The length of the fixed-length prefix extractor configured for this column family */
+    pub fn prefix_length(&self) -> Option<usize> {
+        match self {
+            DbColumnFamily::User => Some(USER_KEY_PREFIX_LENGTH),
+            _ => None,
+        }
+    }
+
+    /**
+     * Whether all keys in [lower, upper) share the same fixed-length prefix. Only then is it safe
+     * to iterate with prefix_same_as_start, which lets RocksDB use the prefix bloom filters to
+     * skip SST files that don't have the prefix.
+     */
+    pub fn is_single_prefix_range(&self, lower: &[u8], upper: &[u8]) -> bool {
+        match self.prefix_length() {
+            Some(length) if lower.len() >= length => {
+                upper <= increment_vec_u8(&lower[..length].to_vec()).as_slice()
+            }
+            _ => false,
+        }
+    }
+
+    /**
+     * The options for this column family. The tuning passed in to dbOpen takes precedence over
+     * the per-family defaults here.
+     */
+    pub fn options(&self, db_options: &RocksDbOptions, cache: Option<&Cache>) -> Options {
+        let mut opts = Options::default();
+        let mut block_opts = BlockBasedOptions::default();
+        let mut default_bloom_bits = None;
+
+        match self {
+            DbColumnFamily::Default => {}
+            DbColumnFamily::User => {
+                // Almost all reads are either point lookups or scans over a single fid's keys.
+                // The prefix bloom filters let those scans skip the SST files without the fid.
+                default_bloom_bits = Some(10.0);
+                block_opts.set_cache_index_and_filter_blocks(true);
+                opts.set_prefix_extractor(SliceTransform::create_fixed_prefix(
+                    USER_KEY_PREFIX_LENGTH,
+                ));
+                opts.set_memtable_prefix_bloom_ratio(0.1);
+                opts.set_level_compaction_dynamic_level_bytes(true);
+            }
+            DbColumnFamily::Indexes | DbColumnFamily::SyncTrie | DbColumnFamily::KeyCounters => {
+                // Almost all reads here are point lookups (get_message, trie nodes, key counters,
+                // index checks during merge), so a bloom filter saves us a disk read for most misses
+                default_bloom_bits = Some(10.0);
+                block_opts.set_cache_index_and_filter_blocks(true);
+                opts.set_level_compaction_dynamic_level_bytes(true);
+            }
+            DbColumnFamily::HubEvents => {
+                // Events are written once in order, read back by id range and pruned after a few
+                // days, so keep the memtables small and the compression cheap.
+                opts.set_write_buffer_size(16 * 1024 * 1024);
+                opts.set_compression_type(DBCompressionType::Lz4);
+                opts.set_periodic_compaction_seconds(24 * 60 * 60);
+                if let Some(retention) = db_options.hub_events_retention() {
+                    opts.set_compaction_filter_factory(HubEventExpiryFilterFactory::new(retention));
+                }
+            }
+        }
+
+        db_options.apply_to_block_based(&mut block_opts, cache, default_bloom_bits);
+        opts.set_block_based_table_factory(&block_opts);
+        db_options.apply_to_cf(&mut opts);
+
+        opts
+    }
+
+    pub fn descriptors(
+        db_options: &RocksDbOptions,
+        cache: Option<&Cache>,
+    ) -> Vec<ColumnFamilyDescriptor> {
+        ALL_COLUMN_FAMILIES
+            .iter()
+            .chain(std::iter::once(&DbColumnFamily::KeyCounters))
+            .map(|cf| ColumnFamilyDescriptor::new(cf.name(), cf.options(db_options, cache)))
+            .collect()
+    }
+}
+
+/**
+ * Split the [lower, upper) key range into sub-ranges that each lie in a single column family.
+ * The sub-ranges are returned in key order, so iterating them one after the other visits keys in
+ * the same order as iterating the whole range in a single keyspace would.
+ */
+pub fn split_range(lower: &[u8], upper: &[u8]) -> Vec<ColumnFamilyRange> {
+    let mut ranges = vec![];
+    if upper.is_empty() || lower >= upper {
+        return ranges;
+    }
+
+    // The first byte of the keys that can be in the range. If the upper bound is a single byte,
+    // keys starting with that byte are all >= upper and excluded.
+    let first = lower.first().copied().unwrap_or(0);
+    let last = if upper.len() > 1 {
+        upper[0]
+    } else if upper[0] == 0 {
+        return ranges;
+    } else {
+        upper[0] - 1
+    };
+
+    let mut start = first;
+    loop {
+        // Extend the run as long as the next prefix byte maps to the same column family
+        let cf = DbColumnFamily::for_prefix_byte(start);
+        let mut end = start;
+        while end < last && DbColumnFamily::for_prefix_byte(end + 1) == cf {
+            end += 1;
+        }
+
+        // Only the first and last runs are clipped by the bounds, the runs in between cover
+        // every key that starts with their prefix bytes
+        let sub_lower = if start == first {
+            lower.to_vec()
+        } else {
+            vec![start]
+        };
+        let sub_upper = if end >= last {
+            upper.to_vec()
+        } else {
+            vec![end + 1]
+        };
+
+        if sub_lower < sub_upper {
+            ranges.push(ColumnFamilyRange {
+                cf,
+                lower: sub_lower,
+                upper: sub_upper,
+            });
+        }
+
+        if end >= last {
+            break;
+        }
+        start = end + 1;
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_range, DbColumnFamily};
+    use crate::store::RootPrefix;
+
+    #[test]
+    fn test_keys_are_routed_by_root_prefix() {
+        assert_eq!(
+            DbColumnFamily::for_key(&[RootPrefix::User as u8, 0, 0, 0, 1]),
+            DbColumnFamily::User
+        );
+        assert_eq!(
+            DbColumnFamily::for_key(&[RootPrefix::VerificationByAddress as u8, 1]),
+            DbColumnFamily::Indexes
+        );
+        assert_eq!(
+            DbColumnFamily::for_key(&[RootPrefix::HubEvents as u8]),
+            DbColumnFamily::HubEvents
+        );
+        assert_eq!(
+            DbColumnFamily::for_key(&[RootPrefix::SyncMerkleTrieNode as u8, 0]),
+            DbColumnFamily::SyncTrie
+        );
+        assert_eq!(
+            DbColumnFamily::for_key(&[RootPrefix::HubState as u8]),
+            DbColumnFamily::Default
+        );
+        assert_eq!(DbColumnFamily::for_key(&[]), DbColumnFamily::Default);
+        assert_eq!(DbColumnFamily::for_key(b"key100"), DbColumnFamily::Default);
+    }
+
+    #[test]
+    fn test_is_single_prefix_range() {
+        let cf = DbColumnFamily::User;
+
+        // All of a fid's keys, or a subset of them
+        assert!(cf.is_single_prefix_range(&[1, 0, 0, 0, 5], &[1, 0, 0, 0, 6]));
+        assert!(cf.is_single_prefix_range(&[1, 0, 0, 0, 5, 87], &[1, 0, 0, 0, 5, 88]));
+        assert!(cf.is_single_prefix_range(&[1, 0, 0, 0, 255], &[1, 0, 0, 1, 0]));
+
+        // Ranges that span more than one fid, or don't have a full prefix
+        assert!(!cf.is_single_prefix_range(&[1, 0, 0, 0, 5], &[1, 0, 0, 0, 6, 0]));
+        assert!(!cf.is_single_prefix_range(&[1, 0, 0, 0], &[1, 0, 0, 1]));
+        assert!(!cf.is_single_prefix_range(&[1], &[2]));
+
+        // Column families without a prefix extractor
+        assert!(!DbColumnFamily::Indexes.is_single_prefix_range(&[2, 0, 0, 0, 5], &[2, 0, 0, 0, 6]));
+    }
+
+    #[test]
+    fn test_split_range() {
+        // A range within one prefix stays in one column family
+        let ranges = split_range(&[1, 0, 0, 0, 5], &[1, 0, 0, 0, 6]);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].cf, DbColumnFamily::User);
+        assert_eq!(ranges[0].lower, vec![1, 0, 0, 0, 5]);
+        assert_eq!(ranges[0].upper, vec![1, 0, 0, 0, 6]);
+
+        // The full keyspace visits every column family, in key order
+        let ranges = split_range(&[], &[255u8; 32]);
+        let cfs = ranges.iter().map(|r| r.cf).collect::<Vec<_>>();
+        assert_eq!(
+            cfs,
+            vec![
+                DbColumnFamily::Default,   // 0
+                DbColumnFamily::User,      // 1
+                DbColumnFamily::Indexes,   // 2-5
+                DbColumnFamily::Default,   // 6-10
+                DbColumnFamily::SyncTrie,  // 11
+                DbColumnFamily::Default,   // 12-14
+                DbColumnFamily::HubEvents, // 15
+                DbColumnFamily::Default,   // 16-24
+                DbColumnFamily::Indexes,   // 25
+                DbColumnFamily::Default,   // 26-255
+            ]
+        );
+        assert_eq!(ranges[0].lower, Vec::<u8>::new());
+        assert_eq!(ranges[2].lower, vec![2]);
+        assert_eq!(ranges[2].upper, vec![6]);
+        assert_eq!(ranges[9].upper, vec![255u8; 32]);
+
+        // Adjacent ranges must not overlap or leave gaps
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].upper, pair[1].lower);
+        }
+
+        // A single byte upper bound excludes all keys starting with that byte
+        let ranges = split_range(&[14, 1], &[15]);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].cf, DbColumnFamily::Default);
+        assert_eq!(ranges[0].upper, vec![15]);
+
+        // Empty ranges
+        assert_eq!(split_range(&[5], &[5]).len(), 0);
+        assert_eq!(split_range(&[6], &[5]).len(), 0);
+        assert_eq!(split_range(&[], &[0]).len(), 0);
+    }
+}
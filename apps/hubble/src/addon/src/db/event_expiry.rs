@@ -0,0 +1,131 @@
+use crate::store::{first_event_id_at, RootPrefix, FARCASTER_EPOCH};
+use rocksdb::compaction_filter::{CompactionFilter, Decision};
+use rocksdb::compaction_filter_factory::{CompactionFilterContext, CompactionFilterFactory};
+use std::ffi::CStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/** HubEvents keys are the RootPrefix byte followed by the 8 byte big endian event id */
+const EVENT_KEY_LENGTH: usize = 1 + 8;
+
+/** The event id in a HubEvents key, if it is one */
+pub fn event_id_from_key(key: &[u8]) -> Option<u64> {
+    if key.len() == EVENT_KEY_LENGTH && key[0] == RootPrefix::HubEvents as u8 {
+        Some(u64::from_be_bytes(key[1..].try_into().unwrap()))
+    } else {
+        None
+    }
+}
+
+/**
+ * The lowest event id that is still retained when events are kept for `retention`. Event ids
+ * start with the time they were generated at, so every older event has a lower id.
+ */
+pub fn retained_event_id_cutoff(retention: Duration) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64;
+    let cutoff = now
+        .saturating_sub(FARCASTER_EPOCH)
+        .saturating_sub(retention.as_millis() as u64);
+
+    first_event_id_at(cutoff)
+}
+
+/**
+ * Creates a HubEventExpiryFilter for every compaction of the HubEvents column family, so expired
+ * events are dropped as their SST files are rewritten, without any writes from the hub itself.
+ */
+pub struct HubEventExpiryFilterFactory {
+    retention: Duration,
+}
+
+impl HubEventExpiryFilterFactory {
+    pub fn new(retention: Duration) -> Self {
+        HubEventExpiryFilterFactory { retention }
+    }
+}
+
+impl CompactionFilterFactory for HubEventExpiryFilterFactory {
+    type Filter = HubEventExpiryFilter;
+
+    fn create(&mut self, _context: CompactionFilterContext) -> Self::Filter {
+        // The cutoff is fixed for the duration of a compaction, so the clock is read once
+        HubEventExpiryFilter {
+            cutoff: retained_event_id_cutoff(self.retention),
+        }
+    }
+
+    fn name(&self) -> &CStr {
+        c"HubEventExpiryFilterFactory"
+    }
+}
+
+/** Removes the events with an id below `cutoff`. Keys that aren't events are always kept */
+pub struct HubEventExpiryFilter {
+    cutoff: u64,
+}
+
+impl CompactionFilter for HubEventExpiryFilter {
+    fn filter(&mut self, _level: u32, key: &[u8], _value: &[u8]) -> Decision {
+        match event_id_from_key(key) {
+            Some(event_id) if event_id < self.cutoff => Decision::Remove,
+            _ => Decision::Keep,
+        }
+    }
+
+    fn name(&self) -> &CStr {
+        c"HubEventExpiryFilter"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event_key(event_id: u64) -> Vec<u8> {
+        let mut key = vec![RootPrefix::HubEvents as u8];
+        key.extend_from_slice(&event_id.to_be_bytes());
+        key
+    }
+
+    #[test]
+    fn test_hub_event_expiry_filter() {
+        let hour = 60 * 60 * 1000;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+            - FARCASTER_EPOCH;
+
+        let cutoff = retained_event_id_cutoff(Duration::from_millis(hour));
+        assert!(cutoff <= first_event_id_at(now - hour + 1000));
+        assert!(cutoff >= first_event_id_at(now - hour - 1000));
+        let mut filter = HubEventExpiryFilter { cutoff };
+
+        // An event from two hours ago is removed, one from now is kept
+        let old_event = first_event_id_at(now - 2 * hour) + 5;
+        assert!(matches!(
+            filter.filter(0, &event_key(old_event), &[]),
+            Decision::Remove
+        ));
+        assert!(matches!(
+            filter.filter(0, &event_key(cutoff), &[]),
+            Decision::Keep
+        ));
+        let new_event = first_event_id_at(now);
+        assert!(matches!(
+            filter.filter(0, &event_key(new_event), &[]),
+            Decision::Keep
+        ));
+
+        // Anything that doesn't look like an event is kept
+        assert!(matches!(
+            filter.filter(0, &[RootPrefix::HubEvents as u8, 0, 1], &[]),
+            Decision::Keep
+        ));
+        let mut user_key = event_key(old_event);
+        user_key[0] = RootPrefix::User as u8;
+        assert!(matches!(filter.filter(0, &user_key, &[]), Decision::Keep));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::db::column_families::{DbColumnFamily, USER_KEY_PREFIX_LENGTH};
+use crate::store::{increment_vec_u8, HubError, RootPrefix};
+use rocksdb::{BoundColumnFamily, WriteBatch, DB};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/** Keys under RootPrefix::User are counted per (fid, postfix), which are their first 6 bytes */
+pub const COUNTED_PREFIX_LENGTH: usize = USER_KEY_PREFIX_LENGTH + 1;
+
+/**
+ * Written once the counters cover every key in the DB, either because the DB was created with
+ * them or because they were rebuilt by a repair. Until then the counters are not used.
+ */
+const COMPLETE_MARKER_KEY: &[u8] = &[];
+
+/** Counter keys are the counted prefix without the RootPrefix byte, so they are all 5 bytes */
+const COUNTER_KEYS_LOWER: &[u8] = &[0];
+const COUNTER_KEYS_UPPER: &[u8] = &[255; COUNTED_PREFIX_LENGTH];
+
+/** The (fid, postfix) prefix that `key` is counted under, if it is counted at all */
+pub fn counted_prefix(key: &[u8]) -> Option<&[u8]> {
+    if key.len() >= COUNTED_PREFIX_LENGTH && key[0] == RootPrefix::User as u8 {
+        Some(&key[..COUNTED_PREFIX_LENGTH])
+    } else {
+        None
+    }
+}
+
+/** Whether the number of keys at `prefix` can be read from a single counter */
+pub fn is_counted_prefix(prefix: &[u8]) -> bool {
+    prefix.len() == COUNTED_PREFIX_LENGTH && prefix[0] == RootPrefix::User as u8
+}
+
+fn counter_key(prefix: &[u8]) -> &[u8] {
+    &prefix[1..]
+}
+
+fn decode_count(value: &[u8]) -> u64 {
+    value.try_into().map(u64::from_be_bytes).unwrap_or_default()
+}
+
+/**
+ * The per-(fid, postfix) key counts, kept in their own column family. The counters are updated
+ * in the same WriteBatch as the keys they count, so they are always in sync with the keys.
+ * Callers must serialize the batches that touch counted keys, otherwise two batches could both
+ * count the same new key.
+ */
+pub struct KeyCounters<'a> {
+    db: &'a DB,
+    cf: Arc<BoundColumnFamily<'a>>,
+}
+
+impl<'a> KeyCounters<'a> {
+    pub fn new(db: &'a DB) -> Self {
+        let cf = db.cf_handle(DbColumnFamily::KeyCounters.name()).unwrap();
+        KeyCounters { db, cf }
+    }
+
+    pub fn is_complete(&self) -> Result<bool, HubError> {
+        Ok(self.db.get_cf(&self.cf, COMPLETE_MARKER_KEY)?.is_some())
+    }
+
+    /** The number of keys at a prefix for which is_counted_prefix is true */
+    pub fn get(&self, prefix: &[u8]) -> Result<u64, HubError> {
+        let value = self.db.get_cf(&self.cf, counter_key(prefix))?;
+        Ok(value.map_or(0, |value| decode_count(&value)))
+    }
+
+    /** Add deleting all the counters, and marking them complete again, to `write_batch` */
+    pub fn reset(&self, write_batch: &mut WriteBatch) {
+        write_batch.delete_range_cf(&self.cf, COUNTER_KEYS_LOWER, COUNTER_KEYS_UPPER);
+        self.mark_complete(write_batch);
+    }
+
+    pub fn mark_complete(&self, write_batch: &mut WriteBatch) {
+        write_batch.put_cf(&self.cf, COMPLETE_MARKER_KEY, []);
+    }
+
+    /** For writes that bypass the counters, until the counters are reset */
+    pub fn mark_incomplete(&self, write_batch: &mut WriteBatch) {
+        write_batch.delete_cf(&self.cf, COMPLETE_MARKER_KEY);
+    }
+
+    /** Add setting the counter for `prefix` to `count` to `write_batch` */
+    pub fn set(&self, write_batch: &mut WriteBatch, prefix: &[u8], count: u64) {
+        if count == 0 {
+            write_batch.delete_cf(&self.cf, counter_key(prefix));
+        } else {
+            write_batch.put_cf(&self.cf, counter_key(prefix), count.to_be_bytes());
+        }
+    }
+
+    /** Add the changes to the counters, keyed by counted prefix, to `write_batch` */
+    pub fn add(
+        &self,
+        write_batch: &mut WriteBatch,
+        deltas: HashMap<Vec<u8>, i64>,
+    ) -> Result<(), HubError> {
+        let deltas = deltas
+            .into_iter()
+            .filter(|(_, delta)| *delta != 0)
+            .collect::<Vec<_>>();
+        let counts = self.db.multi_get_cf(
+            deltas
+                .iter()
+                .map(|(prefix, _)| (&self.cf, counter_key(prefix))),
+        );
+
+        for ((prefix, delta), count) in deltas.iter().zip(counts) {
+            let count = count?.map_or(0, |value| decode_count(&value));
+            self.set(write_batch, prefix, count.saturating_add_signed(*delta));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * The counter changes for a batch that puts (true) or deletes (false) each of its keys, which
+     * must all be different. A put only counts if the key doesn't exist yet, and a delete only if
+     * it does.
+     */
+    pub fn batch_deltas<'k, I>(&self, batch: I) -> Result<HashMap<Vec<u8>, i64>, HubError>
+    where
+        I: IntoIterator<Item = (&'k [u8], bool)>,
+    {
+        let user_cf = self.db.cf_handle(DbColumnFamily::User.name()).unwrap();
+        let counted = batch
+            .into_iter()
+            .filter(|(key, _)| counted_prefix(key).is_some())
+            .collect::<Vec<_>>();
+        let existing = self
+            .db
+            .multi_get_cf(counted.iter().map(|(key, _)| (&user_cf, key)));
+
+        let mut deltas = HashMap::new();
+        for ((key, is_put), existing) in counted.into_iter().zip(existing) {
+            let delta = match (is_put, existing?.is_some()) {
+                (true, false) => 1,
+                (false, true) => -1,
+                _ => continue,
+            };
+            *deltas
+                .entry(key[..COUNTED_PREFIX_LENGTH].to_vec())
+                .or_insert(0) += delta;
+        }
+
+        Ok(deltas)
+    }
+
+    /**
+     * Add taking the keys in [lower, upper) of the User column family off the counters to
+     * `write_batch`. The counters of the prefixes that lie entirely in the range are dropped with
+     * a single range delete, only the keys of the prefixes the range starts or ends in the middle
+     * of are read.
+     */
+    pub fn delete_range(
+        &self,
+        write_batch: &mut WriteBatch,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<(), HubError> {
+        if lower >= upper {
+            return Ok(());
+        }
+
+        // The prefixes in [full_lower, full_upper) lie entirely in the range
+        let mut partial_ranges = vec![];
+        let full_lower = if lower.len() > COUNTED_PREFIX_LENGTH {
+            let next_prefix = increment_vec_u8(&lower[..COUNTED_PREFIX_LENGTH].to_vec());
+            partial_ranges.push((lower.to_vec(), next_prefix.as_slice().min(upper).to_vec()));
+            next_prefix
+        } else {
+            let mut full_lower = lower.to_vec();
+            full_lower.resize(COUNTED_PREFIX_LENGTH, 0);
+            full_lower
+        };
+        let full_upper = if upper.len() > COUNTED_PREFIX_LENGTH {
+            let prefix = upper[..COUNTED_PREFIX_LENGTH].to_vec();
+            // Unless the range starts and ends in the same prefix, which was read above
+            if prefix.as_slice() > lower {
+                partial_ranges.push((prefix.clone(), upper.to_vec()));
+            }
+            prefix
+        } else {
+            upper.to_vec()
+        };
+
+        if full_lower < full_upper {
+            write_batch.delete_range_cf(
+                &self.cf,
+                Self::counter_bound(&full_lower),
+                Self::counter_bound(&full_upper),
+            );
+        }
+
+        let mut deltas = HashMap::new();
+        for (lower, upper) in partial_ranges {
+            self.count_range(&lower, &upper, |prefix, count| {
+                *deltas.entry(prefix.to_vec()).or_insert(0) -= count as i64;
+            })?;
+        }
+        self.add(write_batch, deltas)
+    }
+
+    /** The bound of the counter keys that corresponds to the `bound` of the user keys */
+    fn counter_bound(bound: &[u8]) -> &[u8] {
+        if bound[0] == RootPrefix::User as u8 {
+            &bound[1..]
+        } else {
+            COUNTER_KEYS_UPPER
+        }
+    }
+
+    /**
+     * Count the keys in [lower, upper) of the User column family, per counted prefix. This reads
+     * every key in the range, and calls `f` once for each prefix, in key order.
+     */
+    pub fn count_range<F>(&self, lower: &[u8], upper: &[u8], mut f: F) -> Result<(), HubError>
+    where
+        F: FnMut(&[u8], u64),
+    {
+        let user_cf = self.db.cf_handle(DbColumnFamily::User.name()).unwrap();
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_total_order_seek(true);
+        opts.set_iterate_lower_bound(lower);
+        opts.set_iterate_upper_bound(upper);
+
+        let mut iter = self.db.raw_iterator_cf_opt(&user_cf, opts);
+        iter.seek_to_first();
+
+        let mut current: Option<(Vec<u8>, u64)> = None;
+        while let Some(key) = iter.key() {
+            if let Some(prefix) = counted_prefix(key) {
+                match current.as_mut() {
+                    Some((current_prefix, count)) if current_prefix.as_slice() == prefix => {
+                        *count += 1
+                    }
+                    _ => {
+                        if let Some((current_prefix, count)) = current.take() {
+                            f(&current_prefix, count);
+                        }
+                        current = Some((prefix.to_vec(), 1));
+                    }
+                }
+            }
+            iter.next();
+        }
+        iter.status()?;
+
+        if let Some((current_prefix, count)) = current {
+            f(&current_prefix, count);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counted_prefix() {
+        let user = RootPrefix::User as u8;
+
+        assert_eq!(
+            counted_prefix(&[user, 0, 0, 0, 1, 87, 9, 9]),
+            Some(&[user, 0, 0, 0, 1, 87][..])
+        );
+        assert_eq!(
+            counted_prefix(&[user, 0, 0, 0, 1, 87]),
+            Some(&[user, 0, 0, 0, 1, 87][..])
+        );
+        assert_eq!(counted_prefix(&[user, 0, 0, 0, 1]), None);
+        assert_eq!(
+            counted_prefix(&[RootPrefix::HubEvents as u8, 0, 0, 0, 1, 87]),
+            None
+        );
+
+        assert!(is_counted_prefix(&[user, 0, 0, 0, 1, 87]));
+        assert!(!is_counted_prefix(&[user, 0, 0, 0, 1]));
+        assert!(!is_counted_prefix(&[user, 0, 0, 0, 1, 87, 9]));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::db::{RocksDB, RocksDbTransactionBatch};
+use crate::logger::LOGGER;
+use crate::statsd::statsd;
+use crate::store::{HubError, RootPrefix};
+use slog::{info, o};
+use std::time::Instant;
+
+/** The schema version of the DB, as a 4 byte big endian number. Shared with the JS migrations */
+const SCHEMA_VERSION_KEY: &[u8] = &[RootPrefix::DBSchemaVersion as u8];
+
+/**
+ * Where an unfinished migration left off: the version it migrates to, as a 4 byte big endian
+ * number, followed by the checkpoint it returned for its last committed batch
+ */
+const MIGRATION_PROGRESS_KEY: &[u8] = &[RootPrefix::DBSchemaVersion as u8, 1];
+
+/**
+ * A migration that brings the DB to `version()` from the version before it. Migrations run in
+ * batches, and each batch is committed together with the checkpoint it returns, so a migration
+ * that is interrupted resumes at the first batch that wasn't committed.
+ */
+pub trait Migration: Send + Sync {
+    /** The schema version of the DB once this migration has run */
+    fn version(&self) -> u32;
+
+    fn name(&self) -> &'static str;
+
+    /**
+     * Add the writes of the next batch to `txn`, starting after `checkpoint`, or at the start if
+     * there is no checkpoint yet. Returns the checkpoint to start the next batch from, or None
+     * once the migration is done. Batches must be idempotent, so migrating keys that have
+     * already been migrated must leave them as they are.
+     */
+    fn migrate_batch(
+        &self,
+        db: &RocksDB,
+        checkpoint: Option<&[u8]>,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<Option<Vec<u8>>, HubError>;
+}
+
+/** What a migration did, or would do in a dry run */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationReport {
+    pub version: u32,
+    pub name: &'static str,
+    pub batches: u64,
+    pub writes: u64,
+}
+
+fn migration_error(message: String) -> HubError {
+    HubError {
+        code: "db.migration_failed".to_string(),
+        message,
+    }
+}
+
+/** The schema version of `db`, 0 if it was never set */
+pub fn schema_version(db: &RocksDB) -> Result<u32, HubError> {
+    match db.get(SCHEMA_VERSION_KEY)? {
+        Some(value) => value
+            .as_slice()
+            .try_into()
+            .map(u32::from_be_bytes)
+            .map_err(|_| migration_error("invalid schema version".to_string())),
+        None => Ok(0),
+    }
+}
+
+/** The checkpoint the migration to `version` left off at, if it was interrupted */
+fn checkpoint(db: &RocksDB, version: u32) -> Result<Option<Vec<u8>>, HubError> {
+    Ok(db
+        .get(MIGRATION_PROGRESS_KEY)?
+        .filter(|progress| progress.len() >= 4 && progress[..4] == version.to_be_bytes())
+        .map(|progress| progress[4..].to_vec()))
+}
+
+/**
+ * Run the `migrations` that bring `db` from its current schema version to `to_version`, in order.
+ * The schema version is bumped in the same commit as the last batch of each migration. With
+ * `dry_run` nothing is committed, and the reports tell what the migrations would have written.
+ */
+pub fn run_migrations(
+    db: &RocksDB,
+    migrations: &[Box<dyn Migration>],
+    to_version: u32,
+    dry_run: bool,
+) -> Result<Vec<MigrationReport>, HubError> {
+    let logger = LOGGER.new(o!("component" => "Migrations"));
+    let mut version = schema_version(db)?;
+    let mut reports = vec![];
+
+    while version < to_version {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.version() == version + 1)
+            .ok_or_else(|| {
+                migration_error(format!("no migration from schema version {}", version))
+            })?;
+        let metric = format!("rust.db.migration.{}", migration.version());
+
+        let mut checkpoint = checkpoint(db, migration.version())?;
+        info!(logger, "Running migration";
+            "version" => migration.version(), "name" => migration.name(),
+            "resumed" => checkpoint.is_some(), "dry_run" => dry_run);
+
+        let mut report = MigrationReport {
+            version: migration.version(),
+            name: migration.name(),
+            batches: 0,
+            writes: 0,
+        };
+        loop {
+            let start = Instant::now();
+            let mut txn = db.txn();
+            let next = migration.migrate_batch(db, checkpoint.as_deref(), &mut txn)?;
+
+            report.batches += 1;
+            report.writes += txn.len() as u64;
+
+            match &next {
+                Some(next) => {
+                    let mut progress = migration.version().to_be_bytes().to_vec();
+                    progress.extend_from_slice(next);
+                    txn.put(MIGRATION_PROGRESS_KEY.to_vec(), progress);
+                }
+                None => {
+                    txn.delete(MIGRATION_PROGRESS_KEY.to_vec());
+                    txn.put(
+                        SCHEMA_VERSION_KEY.to_vec(),
+                        migration.version().to_be_bytes().to_vec(),
+                    );
+                }
+            }
+
+            if !dry_run {
+                db.commit(txn)?;
+                statsd().time(
+                    &format!("{}.batch", metric),
+                    start.elapsed().as_millis() as u64,
+                );
+            }
+
+            checkpoint = next;
+            if checkpoint.is_none() {
+                break;
+            }
+        }
+
+        if !dry_run {
+            statsd().count(&format!("{}.writes", metric), report.writes as i64);
+            statsd().gauge("rust.db.schema_version", migration.version() as u64);
+        }
+        info!(logger, "Finished migration";
+            "version" => migration.version(), "name" => migration.name(),
+            "batches" => report.batches, "writes" => report.writes, "dry_run" => dry_run);
+
+        version = migration.version();
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** Writes keys 0..count, two per batch, with the next key as the checkpoint */
+    struct CountingMigration {
+        version: u32,
+        count: u8,
+        fail_at: Option<u8>,
+    }
+
+    impl Migration for CountingMigration {
+        fn version(&self) -> u32 {
+            self.version
+        }
+
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn migrate_batch(
+            &self,
+            _db: &RocksDB,
+            checkpoint: Option<&[u8]>,
+            txn: &mut RocksDbTransactionBatch,
+        ) -> Result<Option<Vec<u8>>, HubError> {
+            let start = checkpoint.map_or(0, |checkpoint| checkpoint[0]);
+            let end = (start + 2).min(self.count);
+            for i in start..end {
+                if self.fail_at == Some(i) {
+                    return Err(migration_error("failed".to_string()));
+                }
+                txn.put(vec![RootPrefix::User as u8, self.version as u8, i], vec![i]);
+            }
+
+            Ok((end < self.count).then(|| vec![end]))
+        }
+    }
+
+    #[test]
+    fn test_run_migrations() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDB::new(tmp_dir.path().to_str().unwrap()).unwrap();
+        db.open().unwrap();
+        db.put(SCHEMA_VERSION_KEY, &1u32.to_be_bytes()).unwrap();
+
+        let key = |version: u8, i: u8| vec![RootPrefix::User as u8, version, i];
+        let mut migrations: Vec<Box<dyn Migration>> = vec![
+            Box::new(CountingMigration {
+                version: 2,
+                count: 5,
+                fail_at: None,
+            }),
+            Box::new(CountingMigration {
+                version: 3,
+                count: 3,
+                fail_at: Some(2),
+            }),
+        ];
+
+        // A dry run reports the writes, but doesn't make them
+        let reports = run_migrations(&db, &migrations[..1], 2, true).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].batches, 3);
+        assert_eq!(reports[0].writes, 5);
+        assert_eq!(schema_version(&db).unwrap(), 1);
+        assert_eq!(db.get(&key(2, 0)).unwrap(), None);
+
+        // There is no migration to version 4
+        let err = run_migrations(&db, &migrations, 4, true).unwrap_err();
+        assert_eq!(err.code, "db.migration_failed");
+
+        // The second migration fails in its second batch, after the first has finished
+        let err = run_migrations(&db, &migrations, 3, false).unwrap_err();
+        assert_eq!(err.code, "db.migration_failed");
+        assert_eq!(schema_version(&db).unwrap(), 2);
+        for i in 0..5 {
+            assert_eq!(db.get(&key(2, i)).unwrap(), Some(vec![i]));
+        }
+        assert_eq!(db.get(&key(3, 1)).unwrap(), Some(vec![1]));
+        assert_eq!(checkpoint(&db, 3).unwrap(), Some(vec![2]));
+        assert_eq!(checkpoint(&db, 4).unwrap(), None);
+
+        // Once fixed, it resumes where it left off
+        migrations[1] = Box::new(CountingMigration {
+            version: 3,
+            count: 3,
+            fail_at: None,
+        });
+        let reports = run_migrations(&db, &migrations, 3, false).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].batches, 1);
+        assert_eq!(reports[0].writes, 1);
+        assert_eq!(schema_version(&db).unwrap(), 3);
+        assert_eq!(db.get(&key(3, 2)).unwrap(), Some(vec![2]));
+        assert_eq!(db.get(MIGRATION_PROGRESS_KEY).unwrap(), None);
+
+        // Nothing left to run
+        assert!(run_migrations(&db, &migrations, 3, false)
+            .unwrap()
+            .is_empty());
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+}
@@ -1,4 +1,26 @@
+pub use self::bulk_loader::BulkLoader;
+pub use self::migration::{run_migrations, schema_version, Migration, MigrationReport};
+pub use self::options::*;
+pub use self::read_snapshot::ReadSnapshot;
 pub use self::rocksdb::*;
+pub use self::snapshot_progress::*;
+pub use self::write_batch::OrderedWriteBatch;
+pub use self::write_stall::{WritePriority, WriteStallState};
 
+mod bulk_loader;
+mod column_families;
+mod event_expiry;
+mod key_counters;
+mod migration;
+mod multi_chunk_reader;
 mod multi_chunk_writer;
+mod options;
+mod read_snapshot;
 mod rocksdb;
+mod snapshot_manifest;
+mod snapshot_progress;
+mod snapshot_restore;
+mod snapshot_retention;
+mod stats_reporter;
+mod write_batch;
+mod write_stall;
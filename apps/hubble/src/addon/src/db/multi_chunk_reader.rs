@@ -0,0 +1,187 @@
+use flate2::read::MultiGzDecoder;
+use slog::{info, o};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Result};
+use std::path::{Path, PathBuf};
+
+use crate::db::multi_chunk_writer::ChunkCodec;
+use crate::logger::LOGGER;
+
+/**
+* A reader for the output of MultiChunkWriter. It reads the chunk_XXXX.<ext> files in `base_path`
+* in part number order, decompresses each of them according to its extension, and returns the
+* data as one continuous stream.
+*/
+pub(crate) struct MultiChunkReader {
+    chunks: Vec<PathBuf>,
+    next_chunk: usize,
+    decoder: Option<Box<dyn Read + Send>>,
+    logger: slog::Logger,
+}
+
+impl MultiChunkReader {
+    pub fn new(base_path: PathBuf) -> Result<Self> {
+        let chunks = Self::list_chunks(&base_path)?;
+        if chunks.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no chunks found in {}", base_path.display()),
+            ));
+        }
+
+        Ok(Self {
+            chunks,
+            next_chunk: 0,
+            decoder: None,
+            logger: LOGGER.new(o! ("module" => "snapshot_reader")),
+        })
+    }
+
+    /** The chunk files in `base_path`, sorted by part number */
+    pub fn list_chunks<P: AsRef<Path>>(base_path: P) -> Result<Vec<PathBuf>> {
+        let mut chunks = vec![];
+        for entry in fs::read_dir(base_path)? {
+            let path = entry?.path();
+            if Self::chunk_codec(&path).is_none() {
+                continue;
+            }
+            let part = path
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("chunk_"))
+                .and_then(|part| part.parse::<usize>().ok());
+            if let Some(part) = part {
+                chunks.push((part, path));
+            }
+        }
+
+        // Sort by the parsed part number, so we don't depend on the zero padding
+        chunks.sort();
+        Ok(chunks.into_iter().map(|(_, path)| path).collect())
+    }
+
+    fn chunk_codec(path: &Path) -> Option<ChunkCodec> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ChunkCodec::from_extension)
+    }
+
+    fn next_part(&mut self) -> Result<bool> {
+        self.decoder = None;
+        if self.next_chunk >= self.chunks.len() {
+            return Ok(false);
+        }
+
+        let file_name = &self.chunks[self.next_chunk];
+        info!(self.logger, "Reading chunk"; "chunk" => file_name.display().to_string());
+
+        let file = BufReader::new(File::open(file_name)?);
+        self.decoder = Some(match Self::chunk_codec(file_name) {
+            // The parallel compressor writes each block as its own gzip member
+            Some(ChunkCodec::Gzip) => Box::new(MultiGzDecoder::new(file)),
+            Some(ChunkCodec::Zstd(_)) => Box::new(zstd::Decoder::with_buffer(file)?),
+            Some(ChunkCodec::None) | None => Box::new(file),
+        });
+        self.next_chunk += 1;
+        Ok(true)
+    }
+}
+
+impl Read for MultiChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.decoder.is_none() && !self.next_part()? {
+                return Ok(0); // All chunks have been read
+            }
+
+            if let Some(decoder) = self.decoder.as_mut() {
+                let size = decoder.read(buf)?;
+                if size > 0 {
+                    return Ok(size);
+                }
+            }
+
+            // The current chunk is done, move on to the next one
+            self.decoder = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::multi_chunk_writer::MultiChunkWriter;
+    use std::io::Write as _;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_across_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = MultiChunkWriter::new(temp_dir.path().to_path_buf(), 5);
+
+        writer.write_all(b"12345").unwrap();
+        writer.write_all(b"67890").unwrap(); // This should trigger a new part
+        writer.write_all(b"abc").unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = MultiChunkReader::new(temp_dir.path().to_path_buf()).unwrap();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+
+        assert_eq!(contents, "1234567890abc");
+    }
+
+    #[test]
+    fn test_chunks_are_read_in_part_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = MultiChunkWriter::new(temp_dir.path().to_path_buf(), 1);
+
+        let data = (0..12u8).collect::<Vec<_>>();
+        for byte in &data {
+            writer.write_all(&[*byte]).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // Ignore anything that isn't a chunk
+        std::fs::write(temp_dir.path().join("manifest.txt"), b"not a chunk").unwrap();
+
+        let mut reader = MultiChunkReader::new(temp_dir.path().to_path_buf()).unwrap();
+        let mut contents = vec![];
+        reader.read_to_end(&mut contents).unwrap();
+
+        assert_eq!(contents, data);
+    }
+
+    #[test]
+    fn test_read_codecs() {
+        use crate::db::multi_chunk_writer::ChunkCodec;
+
+        for codec in [ChunkCodec::Gzip, ChunkCodec::Zstd(19), ChunkCodec::None] {
+            let temp_dir = TempDir::new().unwrap();
+            let mut writer = MultiChunkWriter::new(temp_dir.path().to_path_buf(), 5);
+            writer.set_codec(codec, 2);
+
+            writer.write_all(b"12345").unwrap();
+            writer.write_all(b"67890").unwrap();
+            writer.finish().unwrap();
+
+            let mut reader = MultiChunkReader::new(temp_dir.path().to_path_buf()).unwrap();
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).unwrap();
+
+            assert_eq!(contents, "1234567890", "codec {:?}", codec);
+        }
+    }
+
+    #[test]
+    fn test_no_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let result = MultiChunkReader::new(temp_dir.path().to_path_buf());
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -3,25 +3,129 @@ use gzp::par::compress::{ParCompress, ParCompressBuilder};
 use gzp::ZWriter as _;
 use slog::{info, o};
 use std::fs::{self, File};
-use std::io::{self, Result, Write};
+use std::io::{self, BufWriter, Result, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use crate::db::snapshot_progress::{SnapshotHandle, SnapshotProgress};
 use crate::logger::LOGGER;
 
+// How many uncompressed bytes are written to a chunk between progress reports
+const PROGRESS_INTERVAL_BYTES: usize = 64 * 1024 * 1024;
+
+pub const DEFAULT_CHUNK_THREADS: usize = 4;
+
+/**
+* How the chunks are compressed. The codec is recorded in the chunk's file extension, so readers
+* can pick the right decoder for each chunk.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkCodec {
+    /** Parallel gzip, in .bin files. This is what the snapshot download in hubble.ts expects */
+    Gzip,
+    /** Multi-threaded zstd at the given level, in .zst files */
+    Zstd(i32),
+    /** Uncompressed, in .tar files */
+    None,
+}
+
+impl ChunkCodec {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ChunkCodec::Gzip => "bin",
+            ChunkCodec::Zstd(_) => "zst",
+            ChunkCodec::None => "tar",
+        }
+    }
+
+    /** The codec of a chunk file. The level of zstd chunks doesn't matter for reading them */
+    pub fn from_extension(extension: &str) -> Option<ChunkCodec> {
+        match extension {
+            "bin" => Some(ChunkCodec::Gzip),
+            "zst" => Some(ChunkCodec::Zstd(0)),
+            "tar" => Some(ChunkCodec::None),
+            _ => None,
+        }
+    }
+}
+
+enum ChunkEncoder {
+    Gzip(ParCompress<Gzip>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+    None(BufWriter<File>),
+}
+
+impl ChunkEncoder {
+    fn new(file: File, codec: ChunkCodec, num_threads: usize) -> Result<Self> {
+        let encoder = match codec {
+            ChunkCodec::Gzip => ChunkEncoder::Gzip(
+                ParCompressBuilder::new()
+                    .num_threads(num_threads)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+                    .from_writer(file),
+            ),
+            ChunkCodec::Zstd(level) => {
+                let mut encoder = zstd::Encoder::new(BufWriter::new(file), level)?;
+                encoder.multithread(num_threads as u32)?;
+                encoder.include_checksum(true)?;
+                ChunkEncoder::Zstd(encoder)
+            }
+            ChunkCodec::None => ChunkEncoder::None(BufWriter::new(file)),
+        };
+        Ok(encoder)
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            ChunkEncoder::Gzip(mut encoder) => encoder
+                .finish()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            ChunkEncoder::Zstd(encoder) => encoder.finish()?.flush(),
+            ChunkEncoder::None(mut writer) => writer.flush(),
+        }
+    }
+}
+
+impl Write for ChunkEncoder {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            ChunkEncoder::Gzip(encoder) => encoder.write(buf),
+            ChunkEncoder::Zstd(encoder) => encoder.write(buf),
+            ChunkEncoder::None(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            ChunkEncoder::Gzip(encoder) => encoder.flush(),
+            ChunkEncoder::Zstd(encoder) => encoder.flush(),
+            ChunkEncoder::None(writer) => writer.flush(),
+        }
+    }
+}
+
 /**
 * A writer that will write data to multiple files, creating a new file when the current one
-* reaches the max size. The files are compressed using Gzip and the individual parts are
-* named chunk_XXXX.bin where XXXX is the part number.
+* reaches the max size. The files are compressed with the writer's ChunkCodec (Gzip by default)
+* and the individual parts are named chunk_XXXX.<ext> where XXXX is the part number and ext
+* depends on the codec.
 *
 * The writer will create the `base_path` directory if it does not exist.
+*
+* If a SnapshotHandle is set, the writer reports the bytes written to each chunk through it, and
+* fails every write once the handle is cancelled.
 */
 pub(crate) struct MultiChunkWriter {
     base_path: PathBuf,
     current_part: usize,
     max_size: usize,
     current_size: usize,
-    encoder: Option<ParCompress<Gzip>>,
+    codec: ChunkCodec,
+    num_threads: usize,
+    encoder: Option<ChunkEncoder>,
     logger: slog::Logger,
+    handle: Option<Arc<SnapshotHandle>>,
+    reported_size: usize,
 }
 
 impl MultiChunkWriter {
@@ -31,9 +135,33 @@ impl MultiChunkWriter {
             current_part: 0,
             max_size,
             current_size: 0,
+            codec: ChunkCodec::Gzip,
+            num_threads: DEFAULT_CHUNK_THREADS,
             encoder: None,
             logger: LOGGER.new(o! ("module" => "snapshot_writer")),
+            handle: None,
+            reported_size: 0,
+        }
+    }
+
+    /** Set the codec for the chunks that are started after this call */
+    pub fn set_codec(&mut self, codec: ChunkCodec, num_threads: usize) {
+        self.codec = codec;
+        self.num_threads = num_threads;
+    }
+
+    pub fn set_snapshot_handle(&mut self, handle: Arc<SnapshotHandle>) {
+        self.handle = Some(handle);
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(handle) = &self.handle {
+            handle.report(SnapshotProgress::chunk(
+                self.current_part,
+                self.current_size as u64,
+            ));
         }
+        self.reported_size = self.current_size;
     }
 
     fn ensure_directory_exists<P: AsRef<Path>>(&self, path: P) -> Result<()> {
@@ -50,29 +178,28 @@ impl MultiChunkWriter {
 
         self.current_part += 1;
 
-        let file_name = self
-            .base_path
-            .join(format!("chunk_{:04}.bin", self.current_part));
+        let file_name = self.base_path.join(format!(
+            "chunk_{:04}.{}",
+            self.current_part,
+            self.codec.extension()
+        ));
         let file = File::create(&file_name)?;
 
-        self.encoder = Some(
-            ParCompressBuilder::new()
-                .num_threads(4)
-                .unwrap()
-                .from_writer(file),
-        );
+        self.encoder = Some(ChunkEncoder::new(file, self.codec, self.num_threads)?);
 
         self.current_size = 0;
+        self.reported_size = 0;
         Ok(())
     }
 
     pub fn finish(&mut self) -> Result<()> {
-        if let Some(mut encoder) = self.encoder.take() {
+        if let Some(encoder) = self.encoder.take() {
             info!(self.logger, "Finished writing chunk"; "chunk" => self.current_part);
+            self.report_progress();
 
             encoder.finish().map_err(|e| {
                 info!(self.logger, "Error finishing chunk"; "chunk" => self.current_part, "error" => e.to_string());
-                io::Error::new(io::ErrorKind::InvalidData, e)
+                e
             })?
         }
         Ok(())
@@ -81,6 +208,13 @@ impl MultiChunkWriter {
 
 impl Write for MultiChunkWriter {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.handle.as_ref().map_or(false, |h| h.is_cancelled()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "snapshot backup was cancelled",
+            ));
+        }
+
         if self.encoder.is_none() || (self.current_size + buf.len() > self.max_size) {
             self.next_part()?;
         }
@@ -88,6 +222,9 @@ impl Write for MultiChunkWriter {
         if let Some(encoder) = self.encoder.as_mut() {
             let size = encoder.write(buf)?;
             self.current_size += size;
+            if self.current_size - self.reported_size >= PROGRESS_INTERVAL_BYTES {
+                self.report_progress();
+            }
             Ok(size)
         } else {
             Ok(0) // This should never happen
@@ -169,6 +306,76 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_progress_and_cancellation() {
+        use crate::db::snapshot_progress::SnapshotPhase;
+        use std::sync::Mutex;
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = MultiChunkWriter::new(temp_dir.path().to_path_buf(), 5);
+
+        let reported = Arc::new(Mutex::new(vec![]));
+        let reported_clone = reported.clone();
+        let handle = Arc::new(SnapshotHandle::with_progress(move |progress| {
+            reported_clone.lock().unwrap().push(progress);
+        }));
+        writer.set_snapshot_handle(handle.clone());
+
+        writer.write_all(b"12345").unwrap();
+        writer.write_all(b"678").unwrap(); // Finishes the first chunk
+        writer.finish().unwrap();
+
+        let reported = reported.lock().unwrap().clone();
+        assert_eq!(
+            reported,
+            vec![SnapshotProgress::chunk(1, 5), SnapshotProgress::chunk(2, 3)]
+        );
+        assert!(reported.iter().all(|p| p.phase == SnapshotPhase::Tar));
+
+        handle.cancel();
+        let err = writer.write_all(b"9").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_codecs() {
+        for codec in [ChunkCodec::Gzip, ChunkCodec::Zstd(3), ChunkCodec::None] {
+            let temp_dir = TempDir::new().unwrap();
+            let mut writer = MultiChunkWriter::new(temp_dir.path().to_path_buf(), 5);
+            writer.set_codec(codec, 2);
+
+            writer.write_all(b"12345").unwrap();
+            writer.write_all(b"67890").unwrap(); // This should trigger a new part
+            writer.finish().unwrap();
+
+            let mut files: Vec<_> = std::fs::read_dir(temp_dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect();
+            files.sort();
+            assert_eq!(files.len(), 2);
+
+            for (index, file_path) in files.iter().enumerate() {
+                let extension = file_path.extension().unwrap().to_str().unwrap();
+                assert_eq!(extension, codec.extension());
+
+                let file = File::open(file_path).unwrap();
+                let mut contents = String::new();
+                match codec {
+                    ChunkCodec::Gzip => GzDecoder::new(file).read_to_string(&mut contents),
+                    ChunkCodec::Zstd(_) => zstd::Decoder::new(file)
+                        .unwrap()
+                        .read_to_string(&mut contents),
+                    ChunkCodec::None => { file }.read_to_string(&mut contents),
+                }
+                .unwrap();
+
+                let expected = if index == 0 { "12345" } else { "67890" };
+                assert_eq!(contents, expected, "codec {:?}", codec);
+            }
+        }
+    }
+
     #[test]
     fn test_directory_creation() {
         let temp_dir = TempDir::new().unwrap();
@@ -0,0 +1,559 @@
+use crate::db::multi_chunk_writer::{ChunkCodec, DEFAULT_CHUNK_THREADS};
+use crate::store::HubError;
+use rocksdb::{BlockBasedOptions, Cache, DBCompressionType, Options};
+use std::time::Duration;
+
+// RocksDB's default number of levels, compression_per_level can't have more entries than this
+const NUM_LEVELS: usize = 7;
+
+// How often the rate limiter refills, this is the RocksDB recommended default
+const RATE_LIMITER_REFILL_PERIOD_US: i64 = 100_000;
+
+// Snapshot chunks are uploaded as individual files, so keep them well under the S3 object limit
+const DEFAULT_SNAPSHOT_CHUNK_SIZE: usize = 4 * 1024 * 1024 * 1024; // 4GB
+
+// zstd's own default level, a good trade off between speed and size
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/**
+ * How a RocksDB instance is opened. There can only be one primary per DB, but any number of
+ * read-only and secondary instances can be opened next to it, even from other processes.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum DbOpenMode {
+    #[default]
+    Primary,
+    /** A read-only view of the DB as it was when it was opened */
+    ReadOnly,
+    /**
+     * A read-only instance that follows the primary with RocksDB::try_catch_up_with_primary.
+     * The secondary keeps its own info logs in `secondary_path`.
+     */
+    Secondary { secondary_path: String },
+}
+
+impl DbOpenMode {
+    pub fn is_primary(&self) -> bool {
+        *self == DbOpenMode::Primary
+    }
+}
+
+/**
+ * Tuning options for a RocksDB instance, passed in from JS to dbOpen, or built directly in Rust.
+ * Any option that is not set keeps the RocksDB default (or the per-column family default, see
+ * DbColumnFamily::options).
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RocksDbOptions {
+    /** Size of the LRU block cache shared by all column families, in bytes */
+    pub block_cache_size: Option<usize>,
+    /** Size of a single memtable, in bytes */
+    pub write_buffer_size: Option<usize>,
+    /** Max number of memtables (active + immutable) per column family */
+    pub max_write_buffer_number: Option<i32>,
+    /** Max number of concurrent background flushes and compactions */
+    pub max_background_jobs: Option<i32>,
+    /** Compression type for each level, eg. ["none", "none", "lz4", "lz4", "zstd"] */
+    pub compression_per_level: Option<Vec<String>>,
+    /** Bits per key for the bloom filters, 0 disables the bloom filters */
+    pub bloom_filter_bits_per_key: Option<f64>,
+    /** Limit the bytes per second written by flushes and compactions */
+    pub rate_limit_bytes_per_sec: Option<i64>,
+    /** Number of L0 files at which writes are slowed down */
+    pub level0_slowdown_writes_trigger: Option<i32>,
+    /** Number of L0 files at which writes are stopped */
+    pub level0_stop_writes_trigger: Option<i32>,
+    /**
+     * Collect RocksDB statistics, which the stats reporter needs for the block cache hit rate and
+     * the write stall time. They add a small cost to every read and write.
+     */
+    pub enable_statistics: Option<bool>,
+    /**
+     * How long HubEvents are kept, in ms. Older events are dropped when their SST files are
+     * compacted, so they can linger for a while after they expire. Events are kept forever if
+     * this is not set.
+     */
+    pub hub_events_retention_ms: Option<u64>,
+    /** Open the DB as the primary, or as a read-only or secondary instance */
+    pub mode: DbOpenMode,
+}
+
+impl RocksDbOptions {
+    fn parse_compression_type(name: &str) -> Result<DBCompressionType, HubError> {
+        match name {
+            "none" => Ok(DBCompressionType::None),
+            "snappy" => Ok(DBCompressionType::Snappy),
+            "zlib" => Ok(DBCompressionType::Zlib),
+            "bz2" => Ok(DBCompressionType::Bz2),
+            "lz4" => Ok(DBCompressionType::Lz4),
+            "lz4hc" => Ok(DBCompressionType::Lz4hc),
+            "zstd" => Ok(DBCompressionType::Zstd),
+            _ => Err(HubError::invalid_parameter(&format!(
+                "unknown compression type: {}",
+                name
+            ))),
+        }
+    }
+
+    /**
+     * Check that the options make sense on their own and together. RocksDB silently sanitizes
+     * most bad values, which is not what we want when someone tunes a hub.
+     */
+    pub fn validate(&self) -> Result<(), HubError> {
+        if self.block_cache_size == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "blockCacheSize must be greater than 0",
+            ));
+        }
+
+        // RocksDB won't accept memtables smaller than 64KB
+        if let Some(write_buffer_size) = self.write_buffer_size {
+            if write_buffer_size < 64 * 1024 {
+                return Err(HubError::invalid_parameter(
+                    "writeBufferSize must be at least 64KB",
+                ));
+            }
+        }
+
+        if let Some(max_write_buffer_number) = self.max_write_buffer_number {
+            if max_write_buffer_number < 2 {
+                return Err(HubError::invalid_parameter(
+                    "maxWriteBufferNumber must be at least 2",
+                ));
+            }
+        }
+
+        if let Some(max_background_jobs) = self.max_background_jobs {
+            if max_background_jobs < 1 {
+                return Err(HubError::invalid_parameter(
+                    "maxBackgroundJobs must be at least 1",
+                ));
+            }
+        }
+
+        if let Some(levels) = &self.compression_per_level {
+            if levels.is_empty() || levels.len() > NUM_LEVELS {
+                return Err(HubError::invalid_parameter(&format!(
+                    "compressionPerLevel must have between 1 and {} entries",
+                    NUM_LEVELS
+                )));
+            }
+            for level in levels {
+                Self::parse_compression_type(level)?;
+            }
+        }
+
+        if let Some(bits) = self.bloom_filter_bits_per_key {
+            if !(0.0..=100.0).contains(&bits) {
+                return Err(HubError::invalid_parameter(
+                    "bloomFilterBitsPerKey must be between 0 and 100",
+                ));
+            }
+        }
+
+        if let Some(rate_limit) = self.rate_limit_bytes_per_sec {
+            if rate_limit <= 0 {
+                return Err(HubError::invalid_parameter(
+                    "rateLimitBytesPerSec must be greater than 0",
+                ));
+            }
+        }
+
+        if self.hub_events_retention_ms == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "hubEventsRetentionMs must be greater than 0",
+            ));
+        }
+
+        if let DbOpenMode::Secondary { secondary_path } = &self.mode {
+            if secondary_path.is_empty() {
+                return Err(HubError::invalid_parameter(
+                    "secondaryPath is required to open a secondary DB",
+                ));
+            }
+        }
+
+        let slowdown = self.level0_slowdown_writes_trigger;
+        let stop = self.level0_stop_writes_trigger;
+        if slowdown.map_or(false, |n| n < 1) || stop.map_or(false, |n| n < 1) {
+            return Err(HubError::invalid_parameter(
+                "level0 write triggers must be at least 1",
+            ));
+        }
+        if let (Some(slowdown), Some(stop)) = (slowdown, stop) {
+            if slowdown >= stop {
+                return Err(HubError::invalid_parameter(
+                    "level0SlowdownWritesTrigger must be less than level0StopWritesTrigger",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn hub_events_retention(&self) -> Option<Duration> {
+        self.hub_events_retention_ms.map(Duration::from_millis)
+    }
+
+    /** The block cache to share between all the column families, if one was configured */
+    pub fn block_cache(&self) -> Option<Cache> {
+        self.block_cache_size.map(Cache::new_lru_cache)
+    }
+
+    /** Options that apply to the whole DB. Must be called after validate() */
+    pub fn db_options(&self) -> Options {
+        let mut opts = Options::default();
+        if self.mode.is_primary() {
+            opts.create_if_missing(true); // Creates a database if it does not exist
+            opts.create_missing_column_families(true); // Existing DBs only have the default CF
+        }
+
+        // A secondary can't reopen files that the primary has since deleted, so it has to keep
+        // all of them open, as RocksDB recommends
+        if let DbOpenMode::Secondary { .. } = self.mode {
+            opts.set_max_open_files(-1);
+        }
+
+        if let Some(max_background_jobs) = self.max_background_jobs {
+            opts.set_max_background_jobs(max_background_jobs);
+        }
+
+        if let Some(rate_limit) = self.rate_limit_bytes_per_sec {
+            opts.set_ratelimiter(rate_limit, RATE_LIMITER_REFILL_PERIOD_US, 10);
+        }
+
+        if self.enable_statistics == Some(true) {
+            opts.enable_statistics();
+        }
+
+        opts
+    }
+
+    /** Apply the options that are set per column family. Must be called after validate() */
+    pub fn apply_to_cf(&self, opts: &mut Options) {
+        if let Some(write_buffer_size) = self.write_buffer_size {
+            opts.set_write_buffer_size(write_buffer_size);
+        }
+
+        if let Some(max_write_buffer_number) = self.max_write_buffer_number {
+            opts.set_max_write_buffer_number(max_write_buffer_number);
+        }
+
+        if let Some(levels) = &self.compression_per_level {
+            let levels = levels
+                .iter()
+                .filter_map(|level| Self::parse_compression_type(level).ok())
+                .collect::<Vec<_>>();
+            opts.set_compression_per_level(&levels);
+        }
+
+        if let Some(slowdown) = self.level0_slowdown_writes_trigger {
+            opts.set_level_zero_slowdown_writes_trigger(slowdown);
+        }
+
+        if let Some(stop) = self.level0_stop_writes_trigger {
+            opts.set_level_zero_stop_writes_trigger(stop);
+        }
+    }
+
+    /**
+     * Apply the table options. `default_bloom_bits` is the column family's own bloom filter
+     * setting, which is used unless bloom_filter_bits_per_key is set.
+     */
+    pub fn apply_to_block_based(
+        &self,
+        block_opts: &mut BlockBasedOptions,
+        cache: Option<&Cache>,
+        default_bloom_bits: Option<f64>,
+    ) {
+        if let Some(cache) = cache {
+            block_opts.set_block_cache(cache);
+        }
+
+        match self.bloom_filter_bits_per_key.or(default_bloom_bits) {
+            Some(bits) if bits > 0.0 => block_opts.set_bloom_filter(bits, false),
+            _ => {}
+        }
+    }
+}
+
+/**
+ * Options for the chunked archive written by dbSnapshotBackup. The defaults produce the gzip
+ * chunks that the snapshot download in hubble.ts expects.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotOptions {
+    /** "gzip", "zstd" or "none" */
+    pub codec: Option<String>,
+    /** zstd compression level, between 1 and 22 */
+    pub compression_level: Option<i32>,
+    /** Number of compression threads */
+    pub num_threads: Option<usize>,
+    /** Max number of uncompressed bytes per chunk */
+    pub chunk_size: Option<usize>,
+}
+
+impl SnapshotOptions {
+    pub fn validate(&self) -> Result<(), HubError> {
+        match self.codec.as_deref() {
+            None | Some("gzip") | Some("none") => {
+                if self.compression_level.is_some() {
+                    return Err(HubError::invalid_parameter(
+                        "compressionLevel is only supported by the zstd codec",
+                    ));
+                }
+            }
+            Some("zstd") => {
+                if let Some(level) = self.compression_level {
+                    if !zstd::compression_level_range().contains(&level) || level < 1 {
+                        return Err(HubError::invalid_parameter(
+                            "compressionLevel must be between 1 and 22",
+                        ));
+                    }
+                }
+            }
+            Some(codec) => {
+                return Err(HubError::invalid_parameter(&format!(
+                    "unknown snapshot codec: {}",
+                    codec
+                )));
+            }
+        }
+
+        if self.num_threads == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "numThreads must be greater than 0",
+            ));
+        }
+
+        if self.chunk_size == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "chunkSize must be greater than 0",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /** The codec to write the chunks with. Must be called after validate() */
+    pub fn codec(&self) -> ChunkCodec {
+        match self.codec.as_deref() {
+            Some("zstd") => ChunkCodec::Zstd(self.compression_level.unwrap_or(DEFAULT_ZSTD_LEVEL)),
+            Some("none") => ChunkCodec::None,
+            _ => ChunkCodec::Gzip,
+        }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads.unwrap_or(DEFAULT_CHUNK_THREADS)
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size.unwrap_or(DEFAULT_SNAPSHOT_CHUNK_SIZE)
+    }
+}
+
+/**
+ * Which snapshots to keep when garbage collecting old snapshots. The limits are combined, a
+ * snapshot is only kept if it passes all of the limits that are set. With no limits set only
+ * the leftover checkpoints of finished snapshots are removed.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotRetention {
+    /** Keep at most this many snapshots, newest first */
+    pub keep_last: Option<usize>,
+    /** Keep snapshots that are at most this old, in milliseconds */
+    pub max_age_ms: Option<u64>,
+    /** Keep the newest snapshots that together take up at most this many bytes on disk */
+    pub max_bytes: Option<u64>,
+}
+
+impl SnapshotRetention {
+    pub fn validate(&self) -> Result<(), HubError> {
+        if self.keep_last == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "keepLast must be greater than 0",
+            ));
+        }
+
+        if self.max_age_ms == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "maxAgeMs must be greater than 0",
+            ));
+        }
+
+        if self.max_bytes == Some(0) {
+            return Err(HubError::invalid_parameter(
+                "maxBytes must be greater than 0",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DbOpenMode, RocksDbOptions, SnapshotOptions, SnapshotRetention};
+    use crate::db::multi_chunk_writer::ChunkCodec;
+
+    #[test]
+    fn test_validate_options() {
+        assert!(RocksDbOptions::default().validate().is_ok());
+
+        let opts = RocksDbOptions {
+            block_cache_size: Some(512 * 1024 * 1024),
+            write_buffer_size: Some(64 * 1024 * 1024),
+            max_write_buffer_number: Some(4),
+            max_background_jobs: Some(8),
+            compression_per_level: Some(vec![
+                "none".to_string(),
+                "lz4".to_string(),
+                "zstd".to_string(),
+            ]),
+            bloom_filter_bits_per_key: Some(10.0),
+            rate_limit_bytes_per_sec: Some(100 * 1024 * 1024),
+            level0_slowdown_writes_trigger: Some(20),
+            level0_stop_writes_trigger: Some(36),
+            enable_statistics: Some(true),
+            hub_events_retention_ms: Some(3 * 24 * 60 * 60 * 1000),
+            mode: DbOpenMode::Secondary {
+                secondary_path: "/tmp/secondary".to_string(),
+            },
+        };
+        assert!(opts.validate().is_ok());
+
+        let invalid = vec![
+            RocksDbOptions {
+                block_cache_size: Some(0),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                write_buffer_size: Some(1024),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                max_background_jobs: Some(0),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                compression_per_level: Some(vec!["brotli".to_string()]),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                compression_per_level: Some(vec!["lz4".to_string(); 8]),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                bloom_filter_bits_per_key: Some(-1.0),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                rate_limit_bytes_per_sec: Some(0),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                hub_events_retention_ms: Some(0),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                level0_slowdown_writes_trigger: Some(36),
+                level0_stop_writes_trigger: Some(20),
+                ..Default::default()
+            },
+            RocksDbOptions {
+                mode: DbOpenMode::Secondary {
+                    secondary_path: "".to_string(),
+                },
+                ..Default::default()
+            },
+        ];
+        for opts in invalid {
+            let err = opts.validate().unwrap_err();
+            assert_eq!(err.code, "bad_request.invalid_param");
+        }
+    }
+
+    #[test]
+    fn test_snapshot_options() {
+        let opts = SnapshotOptions::default();
+        assert!(opts.validate().is_ok());
+        assert_eq!(opts.codec(), ChunkCodec::Gzip);
+
+        let opts = SnapshotOptions {
+            codec: Some("zstd".to_string()),
+            ..Default::default()
+        };
+        assert!(opts.validate().is_ok());
+        assert_eq!(opts.codec(), ChunkCodec::Zstd(3));
+
+        let opts = SnapshotOptions {
+            codec: Some("zstd".to_string()),
+            compression_level: Some(19),
+            num_threads: Some(8),
+            chunk_size: Some(1024 * 1024 * 1024),
+        };
+        assert!(opts.validate().is_ok());
+        assert_eq!(opts.codec(), ChunkCodec::Zstd(19));
+        assert_eq!(opts.num_threads(), 8);
+
+        let invalid = vec![
+            SnapshotOptions {
+                codec: Some("brotli".to_string()),
+                ..Default::default()
+            },
+            SnapshotOptions {
+                codec: Some("zstd".to_string()),
+                compression_level: Some(23),
+                ..Default::default()
+            },
+            SnapshotOptions {
+                compression_level: Some(3),
+                ..Default::default()
+            },
+            SnapshotOptions {
+                num_threads: Some(0),
+                ..Default::default()
+            },
+            SnapshotOptions {
+                chunk_size: Some(0),
+                ..Default::default()
+            },
+        ];
+        for opts in invalid {
+            let err = opts.validate().unwrap_err();
+            assert_eq!(err.code, "bad_request.invalid_param");
+        }
+    }
+
+    #[test]
+    fn test_snapshot_retention() {
+        assert!(SnapshotRetention::default().validate().is_ok());
+
+        let retention = SnapshotRetention {
+            keep_last: Some(3),
+            max_age_ms: Some(7 * 24 * 60 * 60 * 1000),
+            max_bytes: Some(500 * 1024 * 1024 * 1024),
+        };
+        assert!(retention.validate().is_ok());
+
+        let invalid = vec![
+            SnapshotRetention {
+                keep_last: Some(0),
+                ..Default::default()
+            },
+            SnapshotRetention {
+                max_age_ms: Some(0),
+                ..Default::default()
+            },
+            SnapshotRetention {
+                max_bytes: Some(0),
+                ..Default::default()
+            },
+        ];
+        for retention in invalid {
+            let err = retention.validate().unwrap_err();
+            assert_eq!(err.code, "bad_request.invalid_param");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::db::RocksDB;
+use crate::store::HubError;
+use neon::types::Finalize;
+use rocksdb::{SnapshotWithThreadMode, DB};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/** How long a read snapshot lives if it is never released */
+pub const DEFAULT_READ_SNAPSHOT_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/** How often the watchdog looks for expired read snapshots */
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) struct SnapshotEntry {
+    pub snapshot: SnapshotWithThreadMode<'static, DB>,
+    created_at: Instant,
+    max_age: Duration,
+}
+
+#[derive(Default)]
+struct Entries {
+    snapshots: HashMap<u64, Arc<SnapshotEntry>>,
+    watchdog_running: bool,
+}
+
+/**
+ * The open read snapshots of a RocksDB. A rocksdb snapshot borrows the DB it was taken from, but
+ * our DB lives behind a RwLock, so the borrow can't be expressed. Instead the snapshots are kept
+ * here, and never handed out beyond a read that holds the DB lock. RocksDB::close releases all of
+ * them before the DB itself is dropped.
+ */
+#[derive(Default)]
+pub(crate) struct ReadSnapshots {
+    entries: Mutex<Entries>,
+    next_id: AtomicU64,
+}
+
+impl ReadSnapshots {
+    /**
+     * Take a snapshot of `db`. Returns its id, and whether the caller has to start a watchdog.
+     *
+     * # Safety
+     * The snapshot borrows `db`, so release_all must be called before `db` is dropped.
+     */
+    pub unsafe fn insert(&self, db: &DB, max_age: Duration) -> (u64, bool) {
+        let snapshot = std::mem::transmute::<
+            SnapshotWithThreadMode<'_, DB>,
+            SnapshotWithThreadMode<'static, DB>,
+        >(db.snapshot());
+
+        // Ids are never reused, not even after the DB is reopened, so a stale handle can't pick
+        // up somebody else's snapshot
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let entry = SnapshotEntry {
+            snapshot,
+            created_at: Instant::now(),
+            max_age,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.snapshots.insert(id, Arc::new(entry));
+        let start_watchdog = !entries.watchdog_running;
+        entries.watchdog_running = true;
+
+        (id, start_watchdog)
+    }
+
+    /** The snapshot with `id`. Must only be called, and the result kept, while holding the DB lock */
+    pub fn get(&self, id: u64) -> Result<Arc<SnapshotEntry>, HubError> {
+        self.entries
+            .lock()
+            .unwrap()
+            .snapshots
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| HubError {
+                code: "db.read_snapshot_released".to_string(),
+                message: "read snapshot was released or has expired".to_string(),
+            })
+    }
+
+    pub fn release(&self, id: u64) -> bool {
+        self.entries.lock().unwrap().snapshots.remove(&id).is_some()
+    }
+
+    pub fn release_all(&self) {
+        self.entries.lock().unwrap().snapshots.clear();
+    }
+
+    /**
+     * Release the snapshots that are older than their max age, returning their ages. Also tells
+     * the watchdog whether to keep running, which it only has to while there are snapshots left.
+     */
+    pub fn release_expired(&self) -> (Vec<Duration>, bool) {
+        let mut entries = self.entries.lock().unwrap();
+
+        let mut expired = vec![];
+        entries.snapshots.retain(|_, entry| {
+            let age = entry.created_at.elapsed();
+            if age > entry.max_age {
+                expired.push(age);
+                return false;
+            }
+            true
+        });
+
+        entries.watchdog_running = !entries.snapshots.is_empty();
+        (expired, entries.watchdog_running)
+    }
+
+    /** Release expired snapshots until the DB is gone or has no snapshots left */
+    pub fn spawn_watchdog(db: Weak<RocksDB>) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(WATCHDOG_INTERVAL);
+            match db.upgrade() {
+                Some(db) if db.release_expired_read_snapshots() => {}
+                _ => break,
+            }
+        });
+    }
+}
+
+/**
+ * A consistent point-in-time view of a RocksDB, for reads that span more than one call, like
+ * paging through a prefix. The snapshot is released when release() is called, when the handle is
+ * dropped, or by the watchdog once it is older than its max age, whichever comes first.
+ */
+pub struct ReadSnapshot {
+    id: u64,
+    db: Arc<RocksDB>,
+}
+
+/** Needed so the snapshot can be passed to JS in a JsBox */
+impl Finalize for ReadSnapshot {}
+
+impl ReadSnapshot {
+    pub(crate) fn new(id: u64, db: Arc<RocksDB>) -> Self {
+        ReadSnapshot { id, db }
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn is_of(&self, db: &RocksDB) -> bool {
+        std::ptr::eq(Arc::as_ptr(&self.db), db)
+    }
+
+    pub fn release(&self) {
+        self.db.release_read_snapshot(self.id);
+    }
+}
+
+impl Drop for ReadSnapshot {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
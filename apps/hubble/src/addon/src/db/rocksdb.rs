@@ -1,11 +1,31 @@
-use crate::db::multi_chunk_writer::MultiChunkWriter;
+use crate::db::bulk_loader::{BulkLoader, SharedBulkLoader};
+use crate::db::column_families::{split_range, DbColumnFamily, ALL_COLUMN_FAMILIES};
+use crate::db::event_expiry::{event_id_from_key, retained_event_id_cutoff};
+use crate::db::key_counters::{self, KeyCounters};
+use crate::db::multi_chunk_reader::MultiChunkReader;
+use crate::db::multi_chunk_writer::{ChunkCodec, MultiChunkWriter};
+use crate::db::read_snapshot::{
+    ReadSnapshot, ReadSnapshots, SnapshotEntry, DEFAULT_READ_SNAPSHOT_MAX_AGE,
+};
+use crate::db::snapshot_manifest::{SnapshotManifest, SNAPSHOT_MANIFEST_FILE};
+use crate::db::snapshot_restore::{RestorePaths, StagingDir};
+use crate::db::snapshot_retention::{self, ActiveSnapshot};
+use crate::db::stats_reporter::{
+    self, StatsCollector, DEFAULT_STATS_PREFIX, DEFAULT_STATS_REPORT_INTERVAL,
+};
+use crate::db::write_stall::WriteStallDetector;
+use crate::db::{
+    DbOpenMode, OrderedWriteBatch, RocksDbOptions, SnapshotHandle, SnapshotOptions, SnapshotPhase,
+    SnapshotProgress, SnapshotRetention, WritePriority, WriteStallState,
+};
 use crate::logger::LOGGER;
 use crate::statsd::statsd;
 use crate::store::{
     self, get_db, get_iterator_options, hub_error_to_js_throw, increment_vec_u8, HubError,
-    PageOptions, PAGE_SIZE_MAX,
+    PageOptions, RootPrefix, PAGE_SIZE_MAX,
 };
 use crate::trie::merkle_trie::TRIE_DBPATH_PREFIX;
+use crate::trie::trie_node::TrieNode;
 use crate::THREAD_POOL;
 use chrono::NaiveDateTime;
 use neon::context::{Context, FunctionContext};
@@ -15,53 +35,142 @@ use neon::result::JsResult;
 use neon::types::buffer::TypedArray;
 use neon::types::{
     Finalize, JsArray, JsBoolean, JsBox, JsBuffer, JsFunction, JsNumber, JsObject, JsPromise,
-    JsString,
+    JsString, JsUndefined, JsValue,
 };
-use rocksdb::{Options, TransactionDB, WriteBatch, WriteOptions, DB};
-use slog::{info, o, Logger};
+use rocksdb::{checkpoint::Checkpoint, BoundColumnFamily, WriteBatch, DB};
+use slog::{debug, info, o, warn, Logger};
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::{self};
+use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
-use tar::Builder;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tar::{Archive, Builder};
 use walkdir::WalkDir;
 
 const DB_DIRECTORY: &str = ".rocks";
 
+// Number of keys moved per transaction when migrating keys out of the default column family
+const CF_MIGRATION_BATCH_SIZE: usize = 10_000;
+
+// How many keys are counted between snapshot progress reports and cancellation checks
+const SNAPSHOT_PROGRESS_INTERVAL_KEYS: u64 = 100_000;
+
+// Number of commit_locks. Keys are mapped to one of them by their counted prefix, see
+// commit_lock_stripe
+const COMMIT_LOCK_STRIPES: usize = 256;
+
+/** What a transaction did since set_savepoint, so that rollback_to_savepoint can undo it */
+#[derive(Default)]
+struct Savepoint {
+    // The entries that keys had in the batch before they were written, None if they had none
+    writes: HashMap<Vec<u8>, Option<Option<Vec<u8>>>>,
+    // The keys that were first read since
+    reads: Vec<Vec<u8>>,
+}
+
 /** Hold a transaction. List of key/value pairs that will be committed together */
 pub struct RocksDbTransactionBatch {
     pub batch: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    // The keys read with RocksDB::get_for_update, and the values they were read with. The commit
+    // fails with a conflict if any of them changed before it.
+    pub reads: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    // The entries the keys written since set_savepoint had in `batch` before, if any
+    savepoint: Option<Savepoint>,
 }
 
 impl RocksDbTransactionBatch {
     pub fn new() -> RocksDbTransactionBatch {
         RocksDbTransactionBatch {
             batch: HashMap::new(),
+            reads: HashMap::new(),
+            savepoint: None,
         }
     }
 
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
-        self.batch.insert(key, Some(value));
+        self.write(key, Some(value));
     }
 
     pub fn delete(&mut self, key: Vec<u8>) {
-        self.batch.insert(key, None);
+        self.write(key, None);
+    }
+
+    fn write(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        let previous = self.batch.insert(key.clone(), value);
+        if let Some(savepoint) = self.savepoint.as_mut() {
+            savepoint.writes.entry(key).or_insert(previous);
+        }
+    }
+
+    /**
+     * Record that `key` was read as `value`, unless it was read before. Keep the earliest read of
+     * each key, later ones may have seen our own writes.
+     */
+    fn read(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        if self.reads.contains_key(&key) {
+            return;
+        }
+        if let Some(savepoint) = self.savepoint.as_mut() {
+            savepoint.reads.push(key.clone());
+        }
+        self.reads.insert(key, value);
+    }
+
+    /**
+     * Mark the current writes and reads, so that the ones made after this can be undone with
+     * rollback_to_savepoint. Replaces the previous savepoint.
+     */
+    pub fn set_savepoint(&mut self) {
+        self.savepoint = Some(Savepoint::default());
+    }
+
+    /**
+     * Undo the writes made since set_savepoint, and forget the reads, so that the commit doesn't
+     * fail with a conflict on keys that only the undone writes depended on
+     */
+    pub fn rollback_to_savepoint(&mut self) {
+        let savepoint = self.savepoint.take().unwrap_or_default();
+        for (key, previous) in savepoint.writes {
+            match previous {
+                Some(value) => self.batch.insert(key, value),
+                None => self.batch.remove(&key),
+            };
+        }
+        for key in savepoint.reads {
+            self.reads.remove(&key);
+        }
     }
 
     pub fn merge(&mut self, other: RocksDbTransactionBatch) {
         for (key, value) in other.batch {
-            self.batch.insert(key, value);
+            self.write(key, value);
+        }
+        for (key, value) in other.reads {
+            self.read(key, value);
         }
     }
 
     pub fn len(&self) -> usize {
         self.batch.len()
     }
+
+    /** The size of the keys and values that are written */
+    pub fn byte_size(&self) -> usize {
+        self.batch
+            .iter()
+            .map(|(key, value)| key.len() + value.as_ref().map_or(0, |value| value.len()))
+            .sum()
+    }
 }
 
+/** A [lower, upper) key range to iterate over. The range may span several column families */
 pub struct IteratorOptions {
-    pub opts: rocksdb::ReadOptions,
+    pub lower: Vec<u8>,
+    pub upper: Vec<u8>,
     pub reverse: bool,
 }
 
@@ -74,8 +183,33 @@ pub struct JsIteratorOptions {
 }
 
 pub struct RocksDB {
-    pub db: RwLock<Option<rocksdb::TransactionDB>>,
+    pub db: RwLock<Option<rocksdb::DB>>,
     pub path: String,
+    // Writes hold this lock shared. Snapshots hold it exclusively while they create their
+    // checkpoints, so that no write can land in between the checkpoints of the main and trie DBs.
+    write_lock: RwLock<()>,
+    // Striped by key, see lock_commit_keys. A commit holds the stripes of the conflict-checked
+    // keys it reads or writes (see is_conflict_checked), so that no write to a key that was read
+    // can land between a commit checking for conflicts and writing, and the counter of a
+    // (fid, postfix) prefix is updated by one write at a time. Commits of unrelated keys don't
+    // wait for each other, and writes that only touch the events and the trie don't wait at all.
+    // Always taken after write_lock.
+    commit_locks: Box<[Mutex<()>]>,
+    // Whether the key counters cover every key, see KeyCounters
+    key_counters_complete: AtomicBool,
+    read_snapshots: ReadSnapshots,
+    // How the DB was last opened. Only a primary can be written to.
+    mode: RwLock<DbOpenMode>,
+    // The options the DB was last opened with
+    options: RwLock<RocksDbOptions>,
+    // Set while the DB is being bulk loaded, see begin_loading
+    loading: AtomicBool,
+    // The options the DB was opened with, if they collect statistics
+    statistics: RwLock<Option<rocksdb::Options>>,
+    // Set to stop the running stats reporter
+    stop_stats_reporter: Mutex<Option<Arc<AtomicBool>>>,
+    // Whether RocksDB is stalling writes, see admit_write
+    write_stall: WriteStallDetector,
     logger: slog::Logger,
 }
 
@@ -97,44 +231,457 @@ impl RocksDB {
         Ok(RocksDB {
             db: RwLock::new(None),
             path: path.to_string(),
+            write_lock: RwLock::new(()),
+            commit_locks: (0..COMMIT_LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            key_counters_complete: AtomicBool::new(false),
+            read_snapshots: ReadSnapshots::default(),
+            mode: RwLock::new(DbOpenMode::Primary),
+            options: RwLock::new(RocksDbOptions::default()),
+            loading: AtomicBool::new(false),
+            statistics: RwLock::new(None),
+            stop_stats_reporter: Mutex::new(None),
+            write_stall: WriteStallDetector::default(),
             logger,
         })
     }
 
     pub fn open(&self) -> Result<(), HubError> {
+        self.open_with_options(&RocksDbOptions::default())
+    }
+
+    pub fn open_with_options(&self, options: &RocksDbOptions) -> Result<(), HubError> {
+        options.validate()?;
+
         let mut db_lock = self.db.write().unwrap();
 
         // Create RocksDB options
-        let mut opts = Options::default();
-        opts.create_if_missing(true); // Creates a database if it does not exist
+        let opts = options.db_options();
+        let cache = options.block_cache();
+
+        // Open the database with multi-threaded support. This is a plain DB rather than a
+        // TransactionDB, because only a DB can be checkpointed (Checkpoint::new takes a DBCommon,
+        // which a TransactionDB isn't) and snapshots are built from checkpoints. The transactions
+        // only ever put and deleted keys, so their locks did nothing but serialize commits that
+        // wrote the same keys, which WriteBatches already are: RocksDB applies every WriteBatch
+        // atomically across column families, one after the other. Without the locks there is no
+        // lock timeout either, so a busy key can no longer fail a commit after 5 seconds.
+        let descriptors = DbColumnFamily::descriptors(options, cache.as_ref());
+        let db = match &options.mode {
+            DbOpenMode::Primary => {
+                // Opening creates a DB if there is none, so first put back a DB that a snapshot
+                // restore moved away before it was interrupted
+                if let Ok(restore_paths) = RestorePaths::new(Path::new(&self.path)) {
+                    restore_paths.recover(&self.logger)?;
+                }
+                rocksdb::DB::open_cf_descriptors(&opts, &self.path, descriptors)?
+            }
+            mode => {
+                Self::check_column_families(&opts, &self.path, &descriptors)?;
+                match mode {
+                    DbOpenMode::Secondary { secondary_path } => {
+                        rocksdb::DB::open_cf_descriptors_as_secondary(
+                            &opts,
+                            Path::new(&self.path),
+                            Path::new(secondary_path),
+                            descriptors,
+                        )?
+                    }
+                    _ => rocksdb::DB::open_cf_descriptors_read_only(
+                        &opts,
+                        &self.path,
+                        descriptors,
+                        false,
+                    )?,
+                }
+            }
+        };
 
-        let mut tx_db_opts = rocksdb::TransactionDBOptions::default();
-        tx_db_opts.set_default_lock_timeout(5000); // 5 seconds
+        let key_counters_complete = if options.mode.is_primary() {
+            // DBs created before column families were introduced have all their keys in the
+            // default column family, so move them to where they are routed to now.
+            let migrated = self.migrate_to_column_families(&db)?;
+            if migrated > 0 {
+                info!(self.logger, "Migrated keys to column families";
+                    "path" => &self.path, "keys" => migrated);
+            }
+
+            self.init_key_counters(&db)?
+        } else {
+            KeyCounters::new(&db).is_complete()?
+        };
+        self.key_counters_complete
+            .store(key_counters_complete, Ordering::Relaxed);
 
-        // Open the database with multi-threaded support
-        let db = rocksdb::TransactionDB::open(&opts, &tx_db_opts, &self.path)?;
         *db_lock = Some(db);
+        *self.mode.write().unwrap() = options.mode.clone();
+        *self.options.write().unwrap() = options.clone();
+        self.loading.store(false, Ordering::Relaxed);
+        self.write_stall.reset();
+        *self.statistics.write().unwrap() =
+            (options.enable_statistics == Some(true)).then_some(opts);
 
         // We put the db in a RwLock to make the compiler happy, but it is strictly not required.
         // We can use unsafe to replace the value directly, and this will work fine, and shave off
         // 100ns per db read/write operation.
         // eg:
         // unsafe {
-        //     let db_ptr = &self.db as *const Option<TransactionDB> as *mut Option<TransactionDB>;
+        //     let db_ptr = &self.db as *const Option<DB> as *mut Option<DB>;
         //     std::ptr::replace(db_ptr, Some(db));
         // }
 
-        info!(self.logger, "Opened database"; "path" => &self.path);
+        info!(self.logger, "Opened database";
+            "path" => &self.path, "options" => format!("{:?}", options));
+
+        Ok(())
+    }
+
+    /**
+     * The id of the oldest event that is still retained. Expired events are only removed once
+     * they are compacted, so they are skipped here, which makes the result the same whether or
+     * not the compaction has run yet. None if there are no retained events.
+     */
+    pub fn oldest_event_id(&self) -> Result<Option<u64>, HubError> {
+        let db = self.db();
+        let db = db.as_ref().unwrap();
+        let cf = db.cf_handle(DbColumnFamily::HubEvents.name()).unwrap();
+
+        let prefix = vec![RootPrefix::HubEvents as u8];
+        let mut lower = prefix.clone();
+        if let Some(retention) = self.options.read().unwrap().hub_events_retention() {
+            lower.extend_from_slice(&retained_event_id_cutoff(retention).to_be_bytes());
+        }
+
+        let mut opts = Self::total_order_read_options();
+        opts.set_iterate_upper_bound(increment_vec_u8(&prefix));
+        let mut iter = db.raw_iterator_cf_opt(&cf, opts);
+        iter.seek(&lower);
+
+        while let Some(key) = iter.key() {
+            if let Some(event_id) = event_id_from_key(key) {
+                return Ok(Some(event_id));
+            }
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(None)
+    }
+
+    /**
+     * Report the RocksDB properties and statistics to statsd every `interval`, as gauges named
+     * `<prefix>.<column family>.<property>` and `<prefix>.<property>`. Replaces the reporter
+     * that is already running, if any. Nothing is reported while the DB is closed.
+     */
+    pub fn start_stats_reporter(self: &Arc<Self>, interval: Duration, prefix: &str) {
+        let stop = Arc::new(AtomicBool::new(false));
+        if let Some(previous) = self
+            .stop_stats_reporter
+            .lock()
+            .unwrap()
+            .replace(stop.clone())
+        {
+            previous.store(true, Ordering::Relaxed);
+        }
+
+        stats_reporter::spawn_stats_reporter(
+            Arc::downgrade(self),
+            interval,
+            prefix.to_string(),
+            stop,
+        );
+    }
+
+    pub(crate) fn collect_stats(&self, collector: &mut StatsCollector) -> Vec<(String, u64)> {
+        let db = self.db();
+        match db.as_ref() {
+            Some(db) => collector.collect(db, self.statistics.read().unwrap().as_ref()),
+            None => vec![],
+        }
+    }
+
+    /**
+     * A read-only or secondary instance can't create column families or move keys into them, so
+     * the primary must have opened the DB with all of its column families at least once.
+     */
+    fn check_column_families(
+        opts: &rocksdb::Options,
+        path: &str,
+        descriptors: &[rocksdb::ColumnFamilyDescriptor],
+    ) -> Result<(), HubError> {
+        let existing = rocksdb::DB::list_cf(opts, path)?;
+        for descriptor in descriptors {
+            if !existing.iter().any(|name| name == descriptor.name()) {
+                return Err(HubError::invalid_parameter(&format!(
+                    "column family {} is missing, open the DB as the primary first",
+                    descriptor.name()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /** Whether RocksDB is currently slowing down or stopping writes */
+    pub fn write_stall_state(&self) -> WriteStallState {
+        match self.db().as_ref() {
+            Some(db) => self.write_stall.state(db),
+            None => WriteStallState::Normal,
+        }
+    }
+
+    /**
+     * Admission control for writes that can wait. High priority writes are always admitted. While
+     * RocksDB stops writes, low priority writes fail with a "db.write_stalled" error instead of
+     * blocking until the stall clears, and the caller can retry them later. Delayed writes are
+     * admitted: RocksDB already throttles them to the delayed write rate, and delays are normal
+     * whenever L0 passes the slowdown trigger, eg. during initial sync.
+     */
+    pub fn admit_write(&self, priority: WritePriority) -> Result<(), HubError> {
+        if priority == WritePriority::High {
+            return Ok(());
+        }
+
+        match self.write_stall_state() {
+            WriteStallState::Normal => Ok(()),
+            WriteStallState::Delayed => {
+                statsd().incr("rust.db.write_stall.delayed");
+                Ok(())
+            }
+            WriteStallState::Stopped => {
+                statsd().incr("rust.db.write_stall.rejected");
+                Err(HubError {
+                    code: "db.write_stalled".to_string(),
+                    message: "writes are stopped until compactions catch up".to_string(),
+                })
+            }
+        }
+    }
+
+    /** Fail with a "db.read_only" error unless the DB was opened as the primary */
+    fn check_writable(&self) -> Result<(), HubError> {
+        if self.mode.read().unwrap().is_primary() {
+            return Ok(());
+        }
+
+        Err(HubError {
+            code: "db.read_only".to_string(),
+            message: format!("{} is opened as a read-only instance", self.path),
+        })
+    }
+
+    /**
+     * Apply the changes the primary has made since the secondary was opened, or last caught up.
+     * Only a secondary instance can catch up, a read-only instance stays as it was opened.
+     */
+    pub fn try_catch_up_with_primary(&self) -> Result<(), HubError> {
+        if !matches!(*self.mode.read().unwrap(), DbOpenMode::Secondary { .. }) {
+            return Err(HubError::invalid_parameter(
+                "only a secondary DB can catch up with its primary",
+            ));
+        }
+
+        let db = self.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+        db.try_catch_up_with_primary()?;
+
+        // The primary may have completed the key counters in the meantime
+        let key_counters_complete = KeyCounters::new(db).is_complete()?;
+        self.key_counters_complete
+            .store(key_counters_complete, Ordering::Relaxed);
 
         Ok(())
     }
 
+    /**
+     * Move all keys in the default column family whose RootPrefix is routed to another column
+     * family. Each batch is moved in a single WriteBatch, so if we are interrupted the keys are
+     * in exactly one of the column families, and calling this again picks up where we left off.
+     * Every batch is logged, so that a large migration can be followed. Returns the number of
+     * keys moved.
+     */
+    fn migrate_to_column_families(&self, db: &DB) -> Result<u64, HubError> {
+        let handles = Self::cf_handles(db);
+        let default_cf = &handles[DbColumnFamily::Default as usize];
+
+        let mut migrated = 0;
+        for cf in ALL_COLUMN_FAMILIES {
+            for prefix in cf.prefix_bytes() {
+                // Each batch starts right after the last key moved by the previous one, instead
+                // of seeking over the tombstones it left behind
+                let mut lower_bound = vec![prefix];
+                let mut moved_in_prefix = 0;
+                loop {
+                    let mut opts = rocksdb::ReadOptions::default();
+                    opts.set_iterate_lower_bound(lower_bound.clone());
+                    opts.set_iterate_upper_bound(vec![prefix + 1]);
+
+                    let mut write_batch = WriteBatch::default();
+                    let mut count = 0;
+
+                    let mut iter = db.raw_iterator_cf_opt(default_cf, opts);
+                    iter.seek_to_first();
+                    while iter.valid() && count < CF_MIGRATION_BATCH_SIZE {
+                        if let Some((key, value)) = iter.item() {
+                            write_batch.put_cf(&handles[cf as usize], key, value);
+                            write_batch.delete_cf(default_cf, key);
+                            count += 1;
+
+                            lower_bound.clear();
+                            lower_bound.extend_from_slice(key);
+                        }
+                        iter.next();
+                    }
+                    iter.status()?;
+                    drop(iter);
+
+                    if count == 0 {
+                        break;
+                    }
+
+                    db.write(write_batch)?;
+                    migrated += count as u64;
+                    moved_in_prefix += count as u64;
+                    // The smallest key after the last one moved
+                    lower_bound.push(0);
+
+                    info!(self.logger, "Migrating keys to column families";
+                        "column_family" => cf.name(), "prefix" => prefix,
+                        "keys" => moved_in_prefix, "total" => migrated);
+                }
+
+                if moved_in_prefix > 0 {
+                    // Drop the tombstones, so that later opens (and a migration that resumes
+                    // after being interrupted) don't have to skip over them again
+                    db.compact_range_cf(default_cf, Some(&[prefix]), Some(&[prefix + 1]));
+                }
+            }
+        }
+
+        Ok(migrated)
+    }
+
+    /**
+     * Start counting keys in a new DB. DBs that already have keys have to rebuild their counters
+     * with repair_key_counters, until then count_keys_at_prefix scans the keys.
+     */
+    fn init_key_counters(&self, db: &DB) -> Result<bool, HubError> {
+        let counters = KeyCounters::new(db);
+        if counters.is_complete()? {
+            return Ok(true);
+        }
+
+        let user_cf = db.cf_handle(DbColumnFamily::User.name()).unwrap();
+        let mut iter = db.raw_iterator_cf_opt(&user_cf, Self::total_order_read_options());
+        iter.seek_to_first();
+        let is_empty = !iter.valid();
+        iter.status()?;
+        drop(iter);
+
+        if !is_empty {
+            info!(self.logger, "Key counters are incomplete, keys will be counted by scanning";
+                "path" => &self.path);
+            return Ok(false);
+        }
+
+        let mut write_batch = WriteBatch::default();
+        counters.mark_complete(&mut write_batch);
+        db.write(write_batch)?;
+        Ok(true)
+    }
+
+    /**
+     * Take a read snapshot of the DB. Reads that are given the snapshot see the DB as it was when
+     * the snapshot was taken, no matter what is written after. Unreleased snapshots are released
+     * by a watchdog once they are older than `max_age`.
+     */
+    pub fn create_read_snapshot(
+        self: &Arc<Self>,
+        max_age: Duration,
+    ) -> Result<ReadSnapshot, HubError> {
+        let db = self.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+
+        // SAFETY: close() releases all the read snapshots before it drops the DB
+        let (id, start_watchdog) = unsafe { self.read_snapshots.insert(db, max_age) };
+        if start_watchdog {
+            ReadSnapshots::spawn_watchdog(Arc::downgrade(self));
+        }
+
+        Ok(ReadSnapshot::new(id, self.clone()))
+    }
+
+    pub fn release_read_snapshot(&self, id: u64) {
+        // Releasing a rocksdb snapshot needs the DB, so hold the lock just like the reads do
+        let _db = self.db();
+        self.read_snapshots.release(id);
+    }
+
+    /** Called by the watchdog. Returns whether there are any snapshots left to watch */
+    pub(crate) fn release_expired_read_snapshots(&self) -> bool {
+        let _db = self.db();
+        let (expired, keep_running) = self.read_snapshots.release_expired();
+        for age in expired {
+            warn!(self.logger, "Released a read snapshot that was never released";
+                "path" => &self.path, "age_ms" => age.as_millis() as u64);
+        }
+
+        keep_running
+    }
+
+    /** The rocksdb snapshot to read from. Must only be called while holding the DB lock */
+    fn read_snapshot_entry(
+        &self,
+        snapshot: Option<&ReadSnapshot>,
+    ) -> Result<Option<Arc<SnapshotEntry>>, HubError> {
+        match snapshot {
+            None => Ok(None),
+            Some(snapshot) if !snapshot.is_of(self) => Err(HubError::invalid_parameter(
+                "read snapshot was taken from a different DB",
+            )),
+            Some(snapshot) => self.read_snapshots.get(snapshot.id()).map(Some),
+        }
+    }
+
+    fn read_options(entry: Option<&SnapshotEntry>) -> rocksdb::ReadOptions {
+        let mut opts = rocksdb::ReadOptions::default();
+        if let Some(entry) = entry {
+            opts.set_snapshot(&entry.snapshot);
+        }
+        opts
+    }
+
+    /**
+     * Read options for iterating over all the keys of a column family. Without total_order_seek,
+     * iterators on a column family with a prefix extractor may stop at the end of the prefix.
+     */
+    fn total_order_read_options() -> rocksdb::ReadOptions {
+        let mut opts = rocksdb::ReadOptions::default();
+        opts.set_total_order_seek(true);
+        opts
+    }
+
+    /** Column family handles, indexed by DbColumnFamily */
+    fn cf_handles(db: &DB) -> Vec<Arc<BoundColumnFamily<'_>>> {
+        ALL_COLUMN_FAMILIES
+            .iter()
+            .map(|cf| db.cf_handle(cf.name()).unwrap())
+            .collect()
+    }
+
     pub fn location(&self) -> String {
         self.path.clone()
     }
 
     pub fn close(&self) -> Result<(), HubError> {
         let mut db_lock = self.db.write().unwrap();
+
+        // The read snapshots borrow the DB, so they have to go first
+        self.read_snapshots.release_all();
+
         if db_lock.is_some() {
             let db = db_lock.take().unwrap();
             drop(db);
@@ -144,7 +691,7 @@ impl RocksDB {
         // to make the compiler happy. We could use unsafe to replace the value directly, like this:
         // if self.db.is_some() {
         //     let db = unsafe {
-        //         let db_ptr = &self.db as *const Option<TransactionDB> as *mut Option<TransactionDB>;
+        //         let db_ptr = &self.db as *const Option<DB> as *mut Option<DB>;
         //         std::ptr::replace(db_ptr, None)
         //     };
 
@@ -156,6 +703,8 @@ impl RocksDB {
     }
 
     pub fn destroy(&self) -> Result<(), HubError> {
+        // The files belong to the primary
+        self.check_writable()?;
         self.close()?;
         let path = Path::new(&self.path);
 
@@ -171,16 +720,20 @@ impl RocksDB {
         result
     }
 
-    pub fn db(&self) -> RwLockReadGuard<'_, Option<TransactionDB>> {
+    pub fn db(&self) -> RwLockReadGuard<'_, Option<DB>> {
         self.db.read().unwrap()
     }
 
-    pub fn keys_exist(&self, keys: &Vec<Vec<u8>>) -> Result<Vec<bool>, HubError> {
+    pub fn keys_exist(&self, keys: &[Vec<u8>]) -> Result<Vec<bool>, HubError> {
         let db = self.db();
         let db = db.as_ref().unwrap();
+        let handles = Self::cf_handles(db);
 
         Ok(db
-            .multi_get(keys)
+            .multi_get_cf(
+                keys.iter()
+                    .map(|key| (&handles[DbColumnFamily::for_key(key) as usize], key)),
+            )
             .into_iter()
             .map(|r| match r {
                 Ok(Some(_)) => true,
@@ -191,14 +744,42 @@ impl RocksDB {
     }
 
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, HubError> {
-        self.db().as_ref().unwrap().get(key).map_err(|e| HubError {
-            code: "db.internal_error".to_string(),
-            message: e.to_string(),
-        })
+        self.get_at(key, None)
+    }
+
+    /** Get a key as it was when `snapshot` was taken, or the latest value without a snapshot */
+    pub fn get_at(
+        &self,
+        key: &[u8],
+        snapshot: Option<&ReadSnapshot>,
+    ) -> Result<Option<Vec<u8>>, HubError> {
+        let db = self.db();
+        let db = db.as_ref().unwrap();
+        let entry = self.read_snapshot_entry(snapshot)?;
+        let cf = db.cf_handle(DbColumnFamily::for_key(key).name()).unwrap();
+
+        db.get_cf_opt(&cf, key, &Self::read_options(entry.as_deref()))
+            .map_err(|e| HubError {
+                code: "db.internal_error".to_string(),
+                message: e.to_string(),
+            })
     }
 
-    pub fn get_many(&self, keys: &Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, HubError> {
-        let results = self.db().as_ref().unwrap().multi_get(keys);
+    pub fn get_many(
+        &self,
+        keys: &[Vec<u8>],
+        snapshot: Option<&ReadSnapshot>,
+    ) -> Result<Vec<Vec<u8>>, HubError> {
+        let db = self.db();
+        let db = db.as_ref().unwrap();
+        let entry = self.read_snapshot_entry(snapshot)?;
+        let handles = Self::cf_handles(db);
+
+        let results = db.multi_get_cf_opt(
+            keys.iter()
+                .map(|key| (&handles[DbColumnFamily::for_key(key) as usize], key)),
+            &Self::read_options(entry.as_deref()),
+        );
 
         // If any of the results are Errors, return an error
         let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
@@ -211,32 +792,122 @@ impl RocksDB {
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), HubError> {
-        self.db()
-            .as_ref()
-            .unwrap()
-            .put(key, value)
-            .map_err(|e| HubError {
-                code: "db.internal_error".to_string(),
-                message: e.to_string(),
-            })
+        let mut batch = OrderedWriteBatch::new();
+        batch.put(key.to_vec(), value.to_vec());
+        self.commit_ordered(batch)
     }
 
     pub fn del(&self, key: &[u8]) -> Result<(), HubError> {
-        self.db()
-            .as_ref()
-            .unwrap()
-            .delete(key)
-            .map_err(|e| HubError {
-                code: "db.internal_error".to_string(),
-                message: e.to_string(),
-            })
+        let mut batch = OrderedWriteBatch::new();
+        batch.delete(key.to_vec());
+        self.commit_ordered(batch)
     }
 
     pub fn txn(&self) -> RocksDbTransactionBatch {
         RocksDbTransactionBatch::new()
     }
 
+    /**
+     * Read a key as part of `txn`. The value that was read is recorded in `txn`, and committing
+     * `txn` fails with a "db.transaction_conflict" error if another write changed the key since.
+     * Keys that `txn` already writes are read from `txn` itself. The events and the trie nodes
+     * can't be read this way, see is_conflict_checked.
+     */
+    pub fn get_for_update(
+        &self,
+        txn: &mut RocksDbTransactionBatch,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, HubError> {
+        debug_assert!(Self::is_conflict_checked(DbColumnFamily::for_key(key)));
+        if let Some(value) = txn.batch.get(key) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = txn.reads.get(key) {
+            return Ok(value.clone());
+        }
+
+        let value = self.get(key)?;
+        txn.read(key.to_vec(), value.clone());
+        Ok(value)
+    }
+
+    /**
+     * Whether the keys of `cf` can be read with get_for_update, so that writes to them have to
+     * hold their commit_locks stripe. The counted keys are all in the User column family, which
+     * is checked.
+     */
+    fn is_conflict_checked(cf: DbColumnFamily) -> bool {
+        !matches!(cf, DbColumnFamily::HubEvents | DbColumnFamily::SyncTrie)
+    }
+
+    /**
+     * The commit_locks stripe of `key`. The keys of a counted (fid, postfix) prefix share their
+     * stripe, as they update the same counter, other keys are mapped on their own.
+     */
+    fn commit_lock_stripe(key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key_counters::counted_prefix(key)
+            .unwrap_or(key)
+            .hash(&mut hasher);
+        (hasher.finish() % COMMIT_LOCK_STRIPES as u64) as usize
+    }
+
+    /**
+     * Lock the commit_locks stripes of the conflict-checked keys in `keys`. They are taken in
+     * stripe order, so commits locking overlapping stripes can't deadlock.
+     */
+    fn lock_commit_keys<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a [u8]>,
+    ) -> Vec<MutexGuard<'_, ()>> {
+        keys.filter(|key| Self::is_conflict_checked(DbColumnFamily::for_key(key)))
+            .map(Self::commit_lock_stripe)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|stripe| self.commit_locks[stripe].lock().unwrap())
+            .collect()
+    }
+
+    /** Lock every commit_locks stripe, for the writes that can't tell which keys they touch */
+    fn lock_all_commits(&self) -> Vec<MutexGuard<'_, ()>> {
+        self.commit_locks
+            .iter()
+            .map(|lock| lock.lock().unwrap())
+            .collect()
+    }
+
+    /**
+     * The first key in `reads` whose value is not what it was read as anymore. Conflicts are
+     * found by comparing values, so a key that was changed and then changed back to the value
+     * that was read (A -> B -> A) is not a conflict. The stores only act on the values they read,
+     * so their commits are still correct in that case, but this can't tell whether a key was
+     * written at all since it was read.
+     */
+    fn find_conflict<'a>(
+        db: &DB,
+        handles: &[Arc<BoundColumnFamily<'_>>],
+        reads: &'a HashMap<Vec<u8>, Option<Vec<u8>>>,
+    ) -> Result<Option<&'a [u8]>, HubError> {
+        let reads = reads.iter().collect::<Vec<_>>();
+        let current = db.multi_get_cf(
+            reads
+                .iter()
+                .map(|(key, _)| (&handles[DbColumnFamily::for_key(key) as usize], key)),
+        );
+
+        for ((key, value), current) in reads.into_iter().zip(current) {
+            if current?.as_ref() != value.as_ref() {
+                return Ok(Some(key));
+            }
+        }
+
+        Ok(None)
+    }
+
     pub fn commit(&self, batch: RocksDbTransactionBatch) -> Result<(), HubError> {
+        self.check_writable()?;
+        let start = Instant::now();
+        let _write_guard = self.write_lock.read().unwrap();
         let db = self.db();
         if db.is_none() {
             return Err(HubError {
@@ -245,51 +916,170 @@ impl RocksDB {
             });
         }
 
-        let txn = db.as_ref().unwrap().transaction();
+        // A WriteBatch is applied atomically across all the column families, so the batch is
+        // still committed atomically even if its keys are routed to different column families
+        let db = db.as_ref().unwrap();
+        let handles = Self::cf_handles(db);
+
+        let _commit_guards = self.lock_commit_keys(
+            batch
+                .batch
+                .keys()
+                .chain(batch.reads.keys())
+                .map(|key| key.as_slice()),
+        );
+
+        if let Some(key) = Self::find_conflict(db, &handles, &batch.reads)? {
+            statsd().incr("rust.db.commit.conflict");
+            return Err(HubError {
+                code: "db.transaction_conflict".to_string(),
+                message: format!("key {:x?} was changed by another write", key),
+            });
+        }
+
+        statsd().histogram("rust.db.commit.keys", batch.len() as u64);
+        statsd().histogram("rust.db.commit.bytes", batch.byte_size() as u64);
+
+        let counted = batch
+            .batch
+            .keys()
+            .any(|key| key_counters::counted_prefix(key).is_some());
+
+        let mut write_batch = WriteBatch::default();
+        if counted && self.key_counters_complete.load(Ordering::Relaxed) {
+            let counters = KeyCounters::new(db);
+            let deltas = counters.batch_deltas(
+                batch
+                    .batch
+                    .iter()
+                    .map(|(key, value)| (key.as_slice(), value.is_some())),
+            )?;
+            counters.add(&mut write_batch, deltas)?;
+        }
+
         for (key, value) in batch.batch {
-            if value.is_none() {
-                txn.delete(key)?;
-            } else {
-                txn.put(key, value.unwrap())?;
+            let cf = &handles[DbColumnFamily::for_key(&key) as usize];
+            match value {
+                Some(value) => write_batch.put_cf(cf, key, value),
+                None => write_batch.delete_cf(cf, key),
             }
         }
 
+        Self::write(db, write_batch, "rust.db.commit", start)
+    }
+
+    /**
+     * Write `write_batch` and time it. `<metric>.write_latency` is the write itself, which is where
+     * RocksDB stalls show up, and `<metric>.latency` includes waiting for the locks before it.
+     */
+    fn write(
+        db: &DB,
+        write_batch: WriteBatch,
+        metric: &str,
+        start: Instant,
+    ) -> Result<(), HubError> {
         statsd().incr("rust.db.commit");
-        txn.commit().map_err(|e| HubError {
+
+        let write_start = Instant::now();
+        let result = db.write(write_batch).map_err(|e| HubError {
             code: "db.internal_error".to_string(),
             message: e.to_string(),
-        })
+        });
+        statsd().time(
+            &format!("{}.write_latency", metric),
+            write_start.elapsed().as_millis() as u64,
+        );
+        statsd().time(
+            &format!("{}.latency", metric),
+            start.elapsed().as_millis() as u64,
+        );
+
+        result
     }
 
-    fn get_iterator_options(prefix: &[u8], page_options: &PageOptions) -> IteratorOptions {
+    /**
+     * Write an OrderedWriteBatch as a single native WriteBatch. The operations are written in the
+     * order they were added, so a later write to a key wins over an earlier one. There are no
+     * reads to check for conflicts, so none of commit's conflict checking is done.
+     */
+    pub fn commit_ordered(&self, batch: OrderedWriteBatch) -> Result<(), HubError> {
+        self.check_writable()?;
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        #[cfg(debug_assertions)]
+        for (key, count) in batch.overwrites() {
+            debug!(self.logger, "Key was written more than once in a batch";
+                "key" => hex::encode(key), "count" => count);
+        }
+
+        let start = Instant::now();
+        let _write_guard = self.write_lock.read().unwrap();
+        let db = self.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+        let handles = Self::cf_handles(db);
+
+        let _commit_guards = self.lock_commit_keys(batch.ops().map(|(key, _)| key));
+
+        let mut write_batch = WriteBatch::default();
+        if self.key_counters_complete.load(Ordering::Relaxed) {
+            let final_ops = batch.final_ops();
+            if final_ops
+                .keys()
+                .any(|key| key_counters::counted_prefix(key).is_some())
+            {
+                let counters = KeyCounters::new(db);
+                let deltas = counters.batch_deltas(final_ops)?;
+                counters.add(&mut write_batch, deltas)?;
+            }
+        }
+
+        statsd().histogram("rust.db.commit_ordered.ops", batch.len() as u64);
+        statsd().histogram("rust.db.commit_ordered.bytes", batch.byte_size() as u64);
+
+        for (key, value) in batch.ops() {
+            let cf = &handles[DbColumnFamily::for_key(key) as usize];
+            match value {
+                Some(value) => write_batch.put_cf(cf, key, value),
+                None => write_batch.delete_cf(cf, key),
+            }
+        }
+
+        Self::write(db, write_batch, "rust.db.commit_ordered", start)
+    }
+
+    fn get_iterator_options(prefix: &[u8], page_options: &PageOptions) -> IteratorOptions {
         // Handle the special case if the prefix is empty, then we want to iterate over the entire database
         if prefix.is_empty() {
-            let mut opts = rocksdb::ReadOptions::default();
+            let lower;
+            let upper;
 
             if page_options.reverse {
                 if page_options.page_token.is_none() {
-                    opts.set_iterate_upper_bound(vec![255u8; 32]);
+                    upper = vec![255u8; 32];
                 } else {
                     // The upper bound is exclusive, so no need to increment the page_token
-                    opts.set_iterate_upper_bound(page_options.page_token.clone().unwrap());
+                    upper = page_options.page_token.clone().unwrap();
                 }
 
-                opts.set_iterate_lower_bound(vec![]);
+                lower = vec![];
             } else {
                 if page_options.page_token.is_none() {
-                    opts.set_iterate_lower_bound(vec![]);
+                    lower = vec![];
                 } else {
                     // lower_bound is always inclusive, so we need to increment the page_token
-                    opts.set_iterate_lower_bound(increment_vec_u8(
-                        &page_options.page_token.clone().unwrap(),
-                    ));
+                    lower = increment_vec_u8(&page_options.page_token.clone().unwrap());
                 }
 
-                opts.set_iterate_upper_bound(vec![255u8; 32]);
+                upper = vec![255u8; 32];
             }
 
             return IteratorOptions {
-                opts,
+                lower,
+                upper,
                 reverse: page_options.reverse,
             };
         }
@@ -320,31 +1110,144 @@ impl RocksDB {
             upper_prefix = prefix_end.to_vec();
         }
 
-        let mut opts = rocksdb::ReadOptions::default();
-        opts.set_iterate_lower_bound(lower_prefix);
-        opts.set_iterate_upper_bound(upper_prefix);
-
         IteratorOptions {
-            opts,
+            lower: lower_prefix,
+            upper: upper_prefix,
             reverse: page_options.reverse,
         }
     }
 
     /**
-     * Count the number of keys with a given prefix.
+     * Iterate over all keys in the [lower, upper) range, across all the column families the range
+     * is routed to. Keys are visited in the same order as if they were all in one keyspace.
+     * The callback function should return true to stop the iteration, or false to continue.
+     * Returns true if the iteration was stopped by the callback.
+     */
+    fn iterate_range<F>(
+        &self,
+        iter_opts: &IteratorOptions,
+        snapshot: Option<&ReadSnapshot>,
+        mut f: F,
+    ) -> Result<bool, HubError>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool, HubError>,
+    {
+        let db = self.db();
+        let db = db.as_ref().unwrap();
+        let entry = self.read_snapshot_entry(snapshot)?;
+
+        let mut ranges = split_range(&iter_opts.lower, &iter_opts.upper);
+        if iter_opts.reverse {
+            ranges.reverse();
+        }
+
+        for range in ranges {
+            let cf = db.cf_handle(range.cf.name()).unwrap();
+
+            // Seeking to the last key goes through the upper bound, which has a different prefix,
+            // so the prefix bloom filters can only be used for forward iteration.
+            let mut opts = Self::read_options(entry.as_deref());
+            if !iter_opts.reverse && range.cf.is_single_prefix_range(&range.lower, &range.upper) {
+                opts.set_prefix_same_as_start(true);
+            } else {
+                opts.set_total_order_seek(true);
+            }
+            opts.set_iterate_lower_bound(range.lower);
+            opts.set_iterate_upper_bound(range.upper);
+
+            let mut iter = db.raw_iterator_cf_opt(&cf, opts);
+            if iter_opts.reverse {
+                iter.seek_to_last();
+            } else {
+                iter.seek_to_first();
+            }
+
+            while iter.valid() {
+                if let Some((key, value)) = iter.item() {
+                    if f(key, value)? {
+                        return Ok(true);
+                    }
+                }
+
+                if iter_opts.reverse {
+                    iter.prev();
+                } else {
+                    iter.next();
+                }
+            }
+            iter.status()?;
+        }
+
+        Ok(false)
+    }
+
+    /**
+     * Count the number of keys with a given prefix. A (fid, postfix) prefix is read from its key
+     * counter once the counters are complete, any other prefix is counted by scanning its keys.
      */
     pub fn count_keys_at_prefix(&self, prefix: &[u8]) -> Result<u32, HubError> {
-        let iter_opts = RocksDB::get_iterator_options(prefix, &PageOptions::default());
+        if key_counters::is_counted_prefix(prefix)
+            && self.key_counters_complete.load(Ordering::Relaxed)
+        {
+            let db = self.db();
+            let count = KeyCounters::new(db.as_ref().unwrap()).get(prefix)?;
+            return Ok(count as u32);
+        }
 
-        let db = self.db();
-        let mut iter = db.as_ref().unwrap().raw_iterator_opt(iter_opts.opts);
+        let iter_opts = RocksDB::get_iterator_options(prefix, &PageOptions::default());
 
         let mut count = 0;
-        iter.seek_to_first();
-        while iter.valid() {
+        self.iterate_range(&iter_opts, None, |_, _| {
             count += 1;
+            Ok(false)
+        })?;
 
-            iter.next();
+        Ok(count)
+    }
+
+    /**
+     * RocksDB's estimate of the number of keys in `column_families`. It is read from the table
+     * properties instead of iterating over the keys, so keys that were overwritten or deleted
+     * since they were last compacted can be miscounted.
+     */
+    fn estimate_num_keys(db: &DB, column_families: &[DbColumnFamily]) -> Result<u64, HubError> {
+        let mut count = 0;
+        for cf in column_families {
+            let handle = db.cf_handle(cf.name()).unwrap();
+            count += db
+                .property_int_value_cf(&handle, "rocksdb.estimate-num-keys")?
+                .unwrap_or(0);
+        }
+
+        Ok(count)
+    }
+
+    /**
+     * Count the keys in `column_families` by iterating over them. `on_progress` is called with the
+     * count so far every SNAPSHOT_PROGRESS_INTERVAL_KEYS keys, returning an error from it stops
+     * the count.
+     */
+    fn count_keys<F>(
+        db: &DB,
+        column_families: &[DbColumnFamily],
+        mut on_progress: F,
+    ) -> Result<u64, HubError>
+    where
+        F: FnMut(u64) -> Result<(), HubError>,
+    {
+        let mut count = 0;
+        for cf in column_families {
+            let handle = db.cf_handle(cf.name()).unwrap();
+            let mut iter = db.raw_iterator_cf_opt(&handle, Self::total_order_read_options());
+            iter.seek_to_first();
+            while iter.valid() {
+                count += 1;
+                if count % SNAPSHOT_PROGRESS_INTERVAL_KEYS == 0 {
+                    on_progress(count)?;
+                }
+                iter.next();
+            }
+            iter.status()?;
         }
 
         Ok(count)
@@ -358,6 +1261,7 @@ impl RocksDB {
         &self,
         prefix: &[u8],
         page_options: &PageOptions,
+        snapshot: Option<&ReadSnapshot>,
         mut f: F,
     ) -> Result<bool, HubError>
     where
@@ -365,39 +1269,24 @@ impl RocksDB {
     {
         let iter_opts = RocksDB::get_iterator_options(prefix, page_options);
 
-        let db = self.db();
-        let mut iter = db.as_ref().unwrap().raw_iterator_opt(iter_opts.opts);
-
-        if iter_opts.reverse {
-            iter.seek_to_last();
-        } else {
-            iter.seek_to_first();
-        }
-
         let mut all_done = true;
         let mut count = 0;
 
-        while iter.valid() {
-            if let Some((key, value)) = iter.item() {
-                if f(&key, &value)? {
-                    all_done = false;
-                    break;
-                }
-                if page_options.page_size.is_some() {
-                    count += 1;
-                    if count >= page_options.page_size.unwrap() {
-                        all_done = true;
-                        break;
-                    }
+        self.iterate_range(&iter_opts, snapshot, |key, value| {
+            if f(key, value)? {
+                all_done = false;
+                return Ok(true);
+            }
+            if let Some(page_size) = page_options.page_size {
+                count += 1;
+                if count >= page_size {
+                    all_done = true;
+                    return Ok(true);
                 }
             }
 
-            if iter_opts.reverse {
-                iter.prev();
-            } else {
-                iter.next();
-            }
-        }
+            Ok(false)
+        })?;
 
         Ok(all_done)
     }
@@ -420,7 +1309,7 @@ impl RocksDB {
         };
 
         let all_done =
-            self.for_each_iterator_by_prefix_paged(prefix, &unbounded_page_options, f)?;
+            self.for_each_iterator_by_prefix_paged(prefix, &unbounded_page_options, None, f)?;
         Ok(all_done)
     }
 
@@ -431,6 +1320,7 @@ impl RocksDB {
     pub fn for_each_iterator_by_jsopts(
         &self,
         js_opts: JsIteratorOptions,
+        snapshot: Option<&ReadSnapshot>,
         mut f: impl FnMut(&[u8], &[u8]) -> Result<bool, HubError>,
     ) -> Result<bool, HubError> {
         // Can't have both gte and gt set
@@ -449,82 +1339,299 @@ impl RocksDB {
             });
         }
 
-        let (lower_bound, increment_first) = if let Some(gte) = js_opts.gte {
+        let (lower_bound, skip_lower_bound) = if let Some(gte) = js_opts.gte {
             (gte, false)
         } else {
             (js_opts.gt.unwrap(), true)
         };
-        let upper_bound = js_opts.lt;
-        let reverse = js_opts.reverse;
+        let iter_opts = IteratorOptions {
+            lower: lower_bound.clone(),
+            upper: js_opts.lt,
+            reverse: js_opts.reverse,
+        };
 
-        let mut opts = rocksdb::ReadOptions::default();
-        opts.set_iterate_lower_bound(lower_bound.clone());
-        opts.set_iterate_upper_bound(upper_bound);
+        let stopped = self.iterate_range(&iter_opts, snapshot, |key, value| {
+            // If we are using gt, we need to skip the lower bound key if it is present
+            if skip_lower_bound && key == lower_bound.as_slice() {
+                return Ok(false);
+            }
+
+            f(key, value)
+        })?;
+
+        Ok(!stopped)
+    }
+
+    /**
+     * Delete all the keys in the range given by the JS iterator options with a single range
+     * delete, and optionally compact the range afterwards to reclaim the space right away.
+     */
+    pub fn delete_all_keys_in_range(
+        &self,
+        js_opts: JsIteratorOptions,
+        compact: bool,
+    ) -> Result<bool, HubError> {
+        let lower = match (js_opts.gte, js_opts.gt) {
+            (Some(_), Some(_)) => {
+                return Err(HubError {
+                    code: "db.invalid_iterator_options".to_string(),
+                    message: "gte and gt cannot be set at the same time".to_string(),
+                })
+            }
+            (Some(gte), None) => gte,
+            // The smallest key that is greater than gt
+            (None, Some(mut gt)) => {
+                gt.push(0);
+                gt
+            }
+            (None, None) => {
+                return Err(HubError {
+                    code: "db.invalid_iterator_options".to_string(),
+                    message: "gte or gt must be set".to_string(),
+                })
+            }
+        };
+
+        self.delete_range(&lower, &js_opts.lt)?;
+        if compact {
+            self.compact_range(&lower, &js_opts.lt);
+        }
+
+        Ok(true)
+    }
 
+    /**
+     * Delete every key in [lower, upper) with range tombstones, without reading the keys first.
+     * The deleted keys still take up space until the range is compacted, see compact_range.
+     */
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<(), HubError> {
+        self.check_writable()?;
+        let _write_guard = self.write_lock.read().unwrap();
         let db = self.db();
-        let mut iter = db.as_ref().unwrap().raw_iterator_opt(opts);
+        let db = db.as_ref().unwrap();
+        let handles = Self::cf_handles(db);
 
-        if reverse {
-            iter.seek_to_last();
-        } else {
-            iter.seek_to_first();
+        let ranges = split_range(lower, upper);
+        let locked = ranges
+            .iter()
+            .any(|range| Self::is_conflict_checked(range.cf));
+        let _commit_guards = locked.then(|| self.lock_all_commits());
+        let counted = ranges.iter().any(|range| range.cf == DbColumnFamily::User);
+
+        let mut write_batch = WriteBatch::default();
+        if counted && self.key_counters_complete.load(Ordering::Relaxed) {
+            let counters = KeyCounters::new(db);
+            for range in ranges.iter().filter(|r| r.cf == DbColumnFamily::User) {
+                counters.delete_range(&mut write_batch, &range.lower, &range.upper)?;
+            }
         }
 
-        // If we are using gt, we need to increment the first key if it matches
-        if increment_first
-            && iter.valid()
-            && iter.key().is_some()
-            && iter.key().unwrap() == lower_bound
-        {
-            iter.next();
+        for range in ranges {
+            write_batch.delete_range_cf(&handles[range.cf as usize], range.lower, range.upper);
         }
 
-        let mut all_done = true;
-        while iter.valid() {
-            if let Some((key, value)) = iter.item() {
-                if f(&key, &value)? {
-                    all_done = false;
-                    break;
-                }
-            }
+        statsd().incr("rust.db.delete_range");
+        db.write(write_batch).map_err(|e| HubError {
+            code: "db.internal_error".to_string(),
+            message: e.to_string(),
+        })
+    }
 
-            if js_opts.reverse {
-                iter.prev();
-            } else {
-                iter.next();
-            }
+    /** Compact [lower, upper) in every column family it spans, dropping any deleted keys */
+    pub fn compact_range(&self, lower: &[u8], upper: &[u8]) {
+        let db = self.db();
+        let db = db.as_ref().unwrap();
+        let handles = Self::cf_handles(db);
+
+        for range in split_range(lower, upper) {
+            db.compact_range_cf(
+                &handles[range.cf as usize],
+                Some(range.lower),
+                Some(range.upper),
+            );
         }
+    }
 
-        Ok(all_done)
+    /** Compact all the column families */
+    pub fn compact(&self) {
+        let db = self.db();
+        let db = db.as_ref().unwrap();
+
+        for cf in Self::cf_handles(db) {
+            db.compact_range_cf(&cf, None::<&[u8]>, None::<&[u8]>);
+        }
     }
 
+    /**
+     * Delete every key in the DB. Each column family is cleared with a single range delete from
+     * its first to its last key, so this doesn't depend on the number of keys in the DB. Returns
+     * RocksDB's estimate of the number of keys deleted, as they aren't read.
+     */
     pub fn clear(&self) -> Result<u32, HubError> {
-        let mut deleted;
+        self.check_writable()?;
+        let _write_guard = self.write_lock.read().unwrap();
+        let _commit_guards = self.lock_all_commits();
+        let db = self.db();
+        let db = db.as_ref().unwrap();
 
-        loop {
-            // reset deleted count
-            deleted = 0;
+        let deleted = Self::estimate_num_keys(db, &ALL_COLUMN_FAMILIES)?;
 
-            // Iterate over all keys and delete them
-            let mut txn = self.txn();
-            let db = self.db();
+        // An empty DB has complete counters, all of them zero
+        let mut write_batch = WriteBatch::default();
+        KeyCounters::new(db).reset(&mut write_batch);
+        for cf in Self::cf_handles(db) {
+            let mut iter = db.raw_iterator_cf_opt(&cf, Self::total_order_read_options());
+            iter.seek_to_first();
+            let first = iter.key().map(|key| key.to_vec());
+            iter.seek_to_last();
+            let last = iter.key().map(|key| key.to_vec());
+            iter.status()?;
 
-            for item in db.as_ref().unwrap().iterator(rocksdb::IteratorMode::Start) {
-                if let Ok((key, _)) = item {
-                    txn.delete(key.to_vec());
-                    deleted += 1;
-                }
+            if let (Some(first), Some(last)) = (first, last) {
+                // The end of a range delete is exclusive, so the last key is deleted on its own
+                write_batch.delete_range_cf(&cf, first.as_slice(), last.as_slice());
+                write_batch.delete_cf(&cf, last);
             }
+        }
+
+        statsd().incr("rust.db.clear");
+        db.write(write_batch).map_err(|e| HubError {
+            code: "db.internal_error".to_string(),
+            message: e.to_string(),
+        })?;
+        self.key_counters_complete.store(true, Ordering::Relaxed);
 
-            self.commit(txn)?;
+        Ok(deleted.min(u32::MAX as u64) as u32)
+    }
 
-            // Check if we deleted anything
-            if deleted == 0 {
-                break;
-            }
+    /**
+     * Start bulk loading the DB, see BulkLoader. The key counters don't see the loaded keys, so
+     * they are marked incomplete until end_loading rebuilds them. If the hub stops while loading,
+     * the counters stay incomplete until they are repaired.
+     */
+    pub fn begin_loading(&self) -> Result<(), HubError> {
+        self.check_writable()?;
+        let _write_guard = self.write_lock.read().unwrap();
+        let _commit_guards = self.lock_all_commits();
+        let db = self.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+
+        if self.loading.swap(true, Ordering::Relaxed) {
+            return Err(HubError {
+                code: "db.loading".to_string(),
+                message: format!("{} is already loading", self.path),
+            });
         }
 
-        Ok(deleted)
+        let mut write_batch = WriteBatch::default();
+        KeyCounters::new(db).mark_incomplete(&mut write_batch);
+        if let Err(e) = db.write(write_batch) {
+            self.loading.store(false, Ordering::Relaxed);
+            return Err(e.into());
+        }
+        self.key_counters_complete.store(false, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /** Stop bulk loading, and rebuild the key counters. Returns the number of counters */
+    pub fn end_loading(&self) -> Result<u64, HubError> {
+        if !self.loading.load(Ordering::Relaxed) {
+            return Err(Self::not_loading_error());
+        }
+
+        let num_counters = self.repair_key_counters()?;
+        self.loading.store(false, Ordering::Relaxed);
+
+        Ok(num_counters)
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Relaxed)
+    }
+
+    fn not_loading_error() -> HubError {
+        HubError {
+            code: "db.not_loading".to_string(),
+            message: "bulk loading is only allowed between begin_loading and end_loading"
+                .to_string(),
+        }
+    }
+
+    pub fn bulk_loader(self: &Arc<Self>) -> Result<BulkLoader, HubError> {
+        if !self.is_loading() {
+            return Err(Self::not_loading_error());
+        }
+
+        Ok(BulkLoader::new(self.clone()))
+    }
+
+    /** The options to write SST files for `cf` with */
+    pub(crate) fn cf_options(&self, cf: DbColumnFamily) -> rocksdb::Options {
+        cf.options(&self.options.read().unwrap(), None)
+    }
+
+    /** Move the SST files into their column families. Only allowed while loading */
+    pub(crate) fn ingest_files(
+        &self,
+        files: HashMap<DbColumnFamily, Vec<PathBuf>>,
+    ) -> Result<(), HubError> {
+        if !self.is_loading() {
+            return Err(Self::not_loading_error());
+        }
+        let _write_guard = self.write_lock.read().unwrap();
+        let db = self.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+
+        let mut opts = rocksdb::IngestExternalFileOptions::default();
+        opts.set_move_files(true);
+        for (cf, paths) in files {
+            let handle = db.cf_handle(cf.name()).unwrap();
+            db.ingest_external_file_cf_opts(&handle, &opts, paths)?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Rebuild all the key counters by scanning the user keys, and start using them. Writes to
+     * user keys wait until this is done. Returns the number of (fid, postfix) counters written.
+     */
+    pub fn repair_key_counters(&self) -> Result<u64, HubError> {
+        self.check_writable()?;
+        let _write_guard = self.write_lock.read().unwrap();
+        let _commit_guards = self.lock_all_commits();
+        let db = self.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+
+        let start = std::time::SystemTime::now();
+        let counters = KeyCounters::new(db);
+
+        let mut write_batch = WriteBatch::default();
+        counters.reset(&mut write_batch);
+
+        let user_prefix = RootPrefix::User as u8;
+        let mut num_counters = 0;
+        counters.count_range(&[user_prefix], &[user_prefix + 1], |prefix, count| {
+            counters.set(&mut write_batch, prefix, count);
+            num_counters += 1;
+        })?;
+
+        db.write(write_batch)?;
+        self.key_counters_complete.store(true, Ordering::Relaxed);
+
+        info!(self.logger, "Repaired key counters";
+            "path" => &self.path,
+            "counters" => num_counters,
+            "time_taken" => format!("{:?}", start.elapsed().unwrap_or_default()));
+
+        Ok(num_counters)
     }
 
     pub fn approximate_size(&self) -> u64 {
@@ -553,7 +1660,9 @@ impl RocksDB {
 
     pub fn js_open(mut cx: FunctionContext) -> JsResult<JsBoolean> {
         let db = get_db(&mut cx)?;
-        let result = match db.open() {
+        let options = store::get_db_options(&mut cx, 0)?;
+
+        let result = match db.open_with_options(&options) {
             Ok(_) => true,
             Err(e) => return hub_error_to_js_throw(&mut cx, e),
         };
@@ -568,14 +1677,37 @@ impl RocksDB {
         Ok(cx.number(result as f64))
     }
 
-    pub fn js_clear(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    /** Optional boolean argument, eg. whether to compact after deleting keys */
+    fn get_optional_bool(cx: &mut FunctionContext, at: usize) -> bool {
+        match cx.argument_opt(at) {
+            Some(arg) => arg
+                .downcast::<JsBoolean, _>(cx)
+                .map_or(false, |value| value.value(cx)),
+            None => false,
+        }
+    }
+
+    pub fn js_clear(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let db = get_db(&mut cx)?;
-        let result = match db.clear() {
-            Ok(result) => result,
-            Err(e) => return hub_error_to_js_throw(&mut cx, e),
-        };
+        let compact = Self::get_optional_bool(&mut cx, 0);
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // Compacting the whole DB can take a while, so keep it off the main thread
+        THREAD_POOL.lock().unwrap().execute(move || {
+            let result = db.clear();
+            if result.is_ok() && compact {
+                db.compact();
+            }
+
+            deferred.settle_with(&channel, move |mut cx| match result {
+                Ok(deleted) => Ok(cx.number(deleted)),
+                Err(e) => hub_error_to_js_throw(&mut cx, e),
+            });
+        });
 
-        Ok(cx.number(result))
+        Ok(promise)
     }
 
     pub fn js_close(mut cx: FunctionContext) -> JsResult<JsBoolean> {
@@ -661,8 +1793,9 @@ impl RocksDB {
     pub fn js_get(mut cx: FunctionContext) -> JsResult<JsBuffer> {
         let db = get_db(&mut cx)?;
         let key = cx.argument::<JsBuffer>(0)?.as_slice(&cx).to_vec();
+        let snapshot = store::get_read_snapshot(&mut cx, 1)?;
 
-        let value = match db.get(&key) {
+        let value = match db.get_at(&key, snapshot.as_deref()) {
             Ok(Some(value)) => value,
             Ok(None) => {
                 return hub_error_to_js_throw(
@@ -693,12 +1826,13 @@ impl RocksDB {
                 .downcast_or_throw::<JsBuffer, _>(&mut cx)?;
             key_vec.push(key.as_slice(&cx).to_vec());
         }
+        let snapshot = store::get_read_snapshot(&mut cx, 1)?;
 
         let channel = cx.channel();
         let (deferred, promise) = cx.promise();
 
         THREAD_POOL.lock().unwrap().execute(move || {
-            let result = db.get_many(&key_vec);
+            let result = db.get_many(&key_vec, snapshot.as_deref());
 
             deferred.settle_with(&channel, move |mut cx| {
                 let result = match result {
@@ -742,7 +1876,8 @@ impl RocksDB {
 
         // We'll read an array of Objects, each with a key and value
         let batch = cx.argument::<JsArray>(0)?;
-        let mut txn_batch = RocksDbTransactionBatch::new();
+        // The key-values are written in order, just like they were added to the transaction
+        let mut write_batch = OrderedWriteBatch::new();
 
         for i in 0..batch.len(&mut cx) {
             let js_object = batch
@@ -770,13 +1905,13 @@ impl RocksDB {
             };
 
             if value.is_none() {
-                txn_batch.delete(key);
+                write_batch.delete(key);
             } else {
-                txn_batch.put(key, value.unwrap());
+                write_batch.put(key, value.unwrap());
             }
         }
 
-        match db.commit(txn_batch) {
+        match db.commit_ordered(write_batch) {
             Ok(_) => (),
             Err(e) => return hub_error_to_js_throw(&mut cx, e),
         };
@@ -823,18 +1958,174 @@ impl RocksDB {
         Ok(result)
     }
 
-    pub fn js_count_keys_at_prefix(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    /** Create a read snapshot, with an optional max age in milliseconds */
+    pub fn js_create_read_snapshot(mut cx: FunctionContext) -> JsResult<JsBox<Arc<ReadSnapshot>>> {
         let db = get_db(&mut cx)?;
 
-        // Prefix
-        let prefix = cx.argument::<JsBuffer>(0)?.as_slice(&cx).to_vec();
+        let max_age = match cx.argument_opt(0) {
+            Some(arg) if arg.is_a::<JsNumber, _>(&mut cx) => {
+                let max_age_ms = arg
+                    .downcast_or_throw::<JsNumber, _>(&mut cx)?
+                    .value(&mut cx);
+                if max_age_ms <= 0.0 {
+                    return hub_error_to_js_throw(
+                        &mut cx,
+                        HubError::invalid_parameter("maxAgeMs must be greater than 0"),
+                    );
+                }
+                Duration::from_millis(max_age_ms as u64)
+            }
+            _ => DEFAULT_READ_SNAPSHOT_MAX_AGE,
+        };
 
-        let channel = cx.channel();
-        let (deferred, promise) = cx.promise();
-        THREAD_POOL.lock().unwrap().execute(move || {
-            let result = db.count_keys_at_prefix(&prefix);
-            deferred.settle_with(&channel, move |mut cx| {
-                let result = match result {
+        match db.create_read_snapshot(max_age) {
+            Ok(snapshot) => Ok(cx.boxed(Arc::new(snapshot))),
+            Err(e) => hub_error_to_js_throw(&mut cx, e),
+        }
+    }
+
+    pub fn js_release_read_snapshot(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let snapshot = cx.argument::<JsBox<Arc<ReadSnapshot>>>(0)?;
+        snapshot.release();
+
+        Ok(cx.undefined())
+    }
+
+    /** The oldest retained event id, or undefined if there are no events */
+    pub fn js_get_oldest_event_id(mut cx: FunctionContext) -> JsResult<JsValue> {
+        let db = get_db(&mut cx)?;
+
+        match db.oldest_event_id() {
+            Ok(Some(event_id)) => Ok(cx.number(event_id as f64).upcast()),
+            Ok(None) => Ok(cx.undefined().upcast()),
+            Err(e) => hub_error_to_js_throw(&mut cx, e),
+        }
+    }
+
+    /** "normal", "delayed" or "stopped", see WriteStallState */
+    pub fn js_get_write_stall_state(mut cx: FunctionContext) -> JsResult<JsString> {
+        let db = get_db(&mut cx)?;
+
+        Ok(cx.string(db.write_stall_state().name()))
+    }
+
+    /** Start reporting stats, with an optional interval in milliseconds and metric prefix */
+    pub fn js_start_stats_reporter(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = get_db(&mut cx)?;
+
+        let interval = match cx.argument_opt(0) {
+            Some(arg) if arg.is_a::<JsNumber, _>(&mut cx) => {
+                let interval_ms = arg
+                    .downcast_or_throw::<JsNumber, _>(&mut cx)?
+                    .value(&mut cx);
+                if interval_ms < 1000.0 {
+                    return hub_error_to_js_throw(
+                        &mut cx,
+                        HubError::invalid_parameter("intervalMs must be at least 1000"),
+                    );
+                }
+                Duration::from_millis(interval_ms as u64)
+            }
+            _ => DEFAULT_STATS_REPORT_INTERVAL,
+        };
+        let prefix = match cx.argument_opt(1) {
+            Some(arg) if arg.is_a::<JsString, _>(&mut cx) => arg
+                .downcast_or_throw::<JsString, _>(&mut cx)?
+                .value(&mut cx),
+            _ => DEFAULT_STATS_PREFIX.to_string(),
+        };
+
+        db.start_stats_reporter(interval, &prefix);
+
+        Ok(cx.undefined())
+    }
+
+    pub fn js_try_catch_up_with_primary(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let db = get_db(&mut cx)?;
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        THREAD_POOL.lock().unwrap().execute(move || {
+            let result = db.try_catch_up_with_primary();
+
+            deferred.settle_with(&channel, move |mut cx| match result {
+                Ok(_) => Ok(cx.undefined()),
+                Err(e) => hub_error_to_js_throw(&mut cx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+
+    pub fn js_begin_loading(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let db = get_db(&mut cx)?;
+
+        match db.begin_loading() {
+            Ok(()) => Ok(cx.undefined()),
+            Err(e) => hub_error_to_js_throw(&mut cx, e),
+        }
+    }
+
+    pub fn js_end_loading(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let db = get_db(&mut cx)?;
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // Rebuilding the key counters reads every user key
+        std::thread::spawn(move || {
+            let result = db.end_loading();
+
+            deferred.settle_with(&channel, move |mut cx| match result {
+                Ok(num_counters) => Ok(cx.number(num_counters as f64)),
+                Err(e) => hub_error_to_js_throw(&mut cx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+
+    pub fn js_create_bulk_loader(mut cx: FunctionContext) -> JsResult<JsBox<SharedBulkLoader>> {
+        let db = get_db(&mut cx)?;
+
+        match db.bulk_loader() {
+            Ok(loader) => Ok(cx.boxed(Arc::new(Mutex::new(Some(loader))))),
+            Err(e) => hub_error_to_js_throw(&mut cx, e),
+        }
+    }
+
+    pub fn js_repair_key_counters(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let db = get_db(&mut cx)?;
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // This reads every user key, so don't hold up the thread pool with it
+        std::thread::spawn(move || {
+            let result = db.repair_key_counters();
+
+            deferred.settle_with(&channel, move |mut cx| match result {
+                Ok(num_counters) => Ok(cx.number(num_counters as f64)),
+                Err(e) => hub_error_to_js_throw(&mut cx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+
+    pub fn js_count_keys_at_prefix(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let db = get_db(&mut cx)?;
+
+        // Prefix
+        let prefix = cx.argument::<JsBuffer>(0)?.as_slice(&cx).to_vec();
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+        THREAD_POOL.lock().unwrap().execute(move || {
+            let result = db.count_keys_at_prefix(&prefix);
+            deferred.settle_with(&channel, move |mut cx| {
+                let result = match result {
                     Ok(r) => r,
                     Err(e) => return hub_error_to_js_throw(&mut cx, e),
                 };
@@ -858,21 +2149,28 @@ impl RocksDB {
         // Page options
         let page_options = store::get_page_options(&mut cx, 1)?;
 
+        // Optional read snapshot, so that all the pages are read from the same view of the DB
+        let snapshot = store::get_read_snapshot(&mut cx, 2)?;
+
         let channel = cx.channel();
         let (deferred, promise) = cx.promise();
         THREAD_POOL.lock().unwrap().execute(move || {
             let mut results = Vec::new();
             let mut next_page_token = Vec::new();
 
-            let iter_result =
-                db.for_each_iterator_by_prefix_paged(&prefix, &page_options, |key, value| {
+            let iter_result = db.for_each_iterator_by_prefix_paged(
+                &prefix,
+                &page_options,
+                snapshot.as_deref(),
+                |key, value| {
                     results.push((key.to_vec(), value.to_vec()));
                     if results.len() > PAGE_SIZE_MAX {
                         next_page_token = key[prefix.len()..].to_vec();
                         return Ok(true);
                     }
                     Ok(false)
-                });
+                },
+            );
 
             deferred.settle_with(&channel, move |mut cx| match iter_result {
                 Err(e) => hub_error_to_js_throw(&mut cx, e),
@@ -923,10 +2221,17 @@ impl RocksDB {
         // The argument is a callback function
         let callback = cx.argument::<JsFunction>(2)?;
 
-        let result = db.for_each_iterator_by_prefix_paged(&prefix, &page_options, |key, value| {
-            // Use the extracted function here
-            Self::call_js_callback(&mut cx, &callback, key, value)
-        });
+        let snapshot = store::get_read_snapshot(&mut cx, 3)?;
+
+        let result = db.for_each_iterator_by_prefix_paged(
+            &prefix,
+            &page_options,
+            snapshot.as_deref(),
+            |key, value| {
+                // Use the extracted function here
+                Self::call_js_callback(&mut cx, &callback, key, value)
+            },
+        );
 
         if result.is_err() {
             return hub_error_to_js_throw(&mut cx, result.err().unwrap());
@@ -948,7 +2253,9 @@ impl RocksDB {
         // The argument is a callback function
         let callback = cx.argument::<JsFunction>(1)?;
 
-        let result = db.for_each_iterator_by_jsopts(js_opts, |key, value| {
+        let snapshot = store::get_read_snapshot(&mut cx, 2)?;
+
+        let result = db.for_each_iterator_by_jsopts(js_opts, snapshot.as_deref(), |key, value| {
             // Use the extracted function here
             Self::call_js_callback(&mut cx, &callback, key, value)
         });
@@ -969,14 +2276,13 @@ impl RocksDB {
 
         // JS Iterator options
         let js_opts = get_iterator_options(&mut cx, 0)?;
+        let compact = Self::get_optional_bool(&mut cx, 1);
 
         let channel = cx.channel();
         let (deferred, promise) = cx.promise();
 
         THREAD_POOL.lock().unwrap().execute(move || {
-            // Delete all keys in the range
-            let result =
-                db.for_each_iterator_by_jsopts(js_opts, |key, _| db.del(key).map(|_| false));
+            let result = db.delete_all_keys_in_range(js_opts, compact);
 
             deferred.settle_with(&channel, move |mut cx| match result {
                 Ok(r) => Ok(cx.boolean(r)),
@@ -989,35 +2295,59 @@ impl RocksDB {
 }
 
 impl RocksDB {
-    fn create_tar_gzip(
-        logger: &Logger,
-        input_dir: &str,
-        timestamp: NaiveDateTime,
-    ) -> Result<String, HubError> {
-        let base_name = Path::new(input_dir)
+    fn tar_base_name(input_dir: &str) -> String {
+        Path::new(input_dir)
             .file_name()
             .map(|s| s.to_string_lossy().to_string())
-            .unwrap_or("rocks.hub._default".to_string());
+            .unwrap_or("rocks.hub._default".to_string())
+    }
+
+    /** The directory the checkpoints of a snapshot are written to, next to the main DB */
+    fn backup_dir(main_db_path: &str) -> PathBuf {
+        Path::new(main_db_path)
+            .join("..") // Create backup as sibling directory of normal path
+            .join("backup")
+    }
+
+    /** The directory that create_tar_gzip writes the chunks for `input_dir` to */
+    fn chunked_output_dir(input_dir: &str, timestamp: NaiveDateTime, codec: ChunkCodec) -> String {
+        let extension = match codec {
+            ChunkCodec::Gzip => "tar.gz",
+            ChunkCodec::Zstd(_) => "tar.zst",
+            ChunkCodec::None => "tar",
+        };
 
-        let chunked_output_dir = Path::new(DB_DIRECTORY)
+        Path::new(DB_DIRECTORY)
             .join(format!(
-                "{}-{}.tar.gz",
-                base_name,
-                timestamp.format("%Y-%m-%d-%s")
+                "{}-{}.{}",
+                Self::tar_base_name(input_dir),
+                timestamp.format("%Y-%m-%d-%s"),
+                extension
             ))
             .as_os_str()
             .to_str()
             .unwrap()
-            .to_string();
+            .to_string()
+    }
+
+    fn create_tar_gzip(
+        logger: &Logger,
+        input_dir: &str,
+        chunked_output_dir: &str,
+        options: &SnapshotOptions,
+        handle: &Arc<SnapshotHandle>,
+    ) -> Result<(), HubError> {
+        let base_name = Self::tar_base_name(input_dir);
 
         let start = std::time::SystemTime::now();
         info!(logger, "Creating chunked tar.gz snapshot for directory: {}",
-            input_dir; o!("output_file_path" => &chunked_output_dir, "base_name" => &base_name));
+            input_dir; o!("output_file_path" => &chunked_output_dir, "base_name" => &base_name,
+                "options" => format!("{:?}", options)));
 
-        let mut multi_chunk_writer = MultiChunkWriter::new(
-            PathBuf::from(chunked_output_dir.clone()),
-            4 * 1024 * 1024 * 1024, // 4GB
-        );
+        let mut multi_chunk_writer =
+            MultiChunkWriter::new(PathBuf::from(chunked_output_dir), options.chunk_size());
+        multi_chunk_writer.set_codec(options.codec(), options.num_threads());
+        multi_chunk_writer.set_snapshot_handle(handle.clone());
 
         let mut tar = Builder::new(&mut multi_chunk_writer);
         tar.append_dir_all(base_name, input_dir)?;
@@ -1025,7 +2355,7 @@ impl RocksDB {
         drop(tar); // Needed so we can call multi_chunk_writer.finish() next
         multi_chunk_writer.finish()?;
 
-        let metadata = fs::metadata(&chunked_output_dir)?;
+        let metadata = fs::metadata(chunked_output_dir)?;
         let time_taken = start.elapsed().expect("Time went backwards");
         info!(
             logger,
@@ -1035,13 +2365,143 @@ impl RocksDB {
             time_taken
         );
 
-        Ok(chunked_output_dir)
+        Ok(())
+    }
+
+    /**
+     * Create RocksDB checkpoints of the main and trie DBs. Checkpoints hard link the SST files
+     * instead of copying keys, so they are fast and take almost no extra disk space. Writes to
+     * both DBs are paused while the checkpoints are created, so they capture the same point.
+     */
+    fn create_checkpoints(
+        main_db: &RocksDB,
+        trie_db: &RocksDB,
+        main_backup_path: &str,
+        triedb_backup_path: &str,
+    ) -> Result<(), HubError> {
+        // The trie may be stored in the main DB, in which case there is only one DB to checkpoint
+        let same_db = std::ptr::eq(main_db, trie_db);
+
+        let _main_write_guard = main_db.write_lock.write().unwrap();
+        let _trie_write_guard = if same_db {
+            None
+        } else {
+            Some(trie_db.write_lock.write().unwrap())
+        };
+
+        Self::create_checkpoint(main_db, main_backup_path)?;
+        if !same_db {
+            Self::create_checkpoint(trie_db, triedb_backup_path)?;
+        }
+
+        Ok(())
+    }
+
+    fn create_checkpoint(db: &RocksDB, path: &str) -> Result<(), HubError> {
+        let db = db.db();
+        let db = db
+            .as_ref()
+            .ok_or_else(|| HubError::internal_db_error("Database is not open"))?;
+
+        let checkpoint = Checkpoint::new(db)?;
+        checkpoint.create_checkpoint(path)?;
+
+        Ok(())
+    }
+
+    /** Read a u32 that was stored big endian by the JS code, eg. the DB schema version */
+    fn get_u32(db: &RocksDB, key: &[u8]) -> Result<Option<u32>, HubError> {
+        match db.get(key)? {
+            Some(bytes) if bytes.len() >= 4 => Ok(Some(u32::from_be_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3],
+            ]))),
+            _ => Ok(None),
+        }
+    }
+
+    /**
+     * Open the checkpoints read-only and start a manifest describing them, with the exact number
+     * of keys in each. The chunks are added to the manifest after the checkpoints have been tarred
+     * up.
+     */
+    fn describe_checkpoints(
+        main_backup_path: &str,
+        triedb_backup_path: &str,
+        timestamp_ms: i64,
+        handle: &SnapshotHandle,
+    ) -> Result<SnapshotManifest, HubError> {
+        // A read-only open doesn't write to the checkpoint, nor migrate it or set up its counters
+        let read_only = RocksDbOptions {
+            mode: DbOpenMode::ReadOnly,
+            ..RocksDbOptions::default()
+        };
+
+        let main_db = RocksDB::new(main_backup_path)?;
+        main_db.open_with_options(&read_only)?;
+
+        let db_schema_version =
+            Self::get_u32(&main_db, &[RootPrefix::DBSchemaVersion as u8])?.unwrap_or(0);
+        let network = Self::get_u32(&main_db, &[RootPrefix::Network as u8])?;
+        let main_db_keys = Self::count_keys(
+            main_db.db().as_ref().unwrap(),
+            &ALL_COLUMN_FAMILIES,
+            |keys| {
+                handle.report(SnapshotProgress::keys("main", keys));
+                handle.check_cancelled()
+            },
+        )?;
+        handle.report(SnapshotProgress::keys("main", main_db_keys));
+
+        // If the trie is stored in the main DB there is no separate trie checkpoint
+        let trie_db = if Path::new(triedb_backup_path).exists() {
+            let trie_db = RocksDB::new(triedb_backup_path)?;
+            trie_db.open_with_options(&read_only)?;
+            Some(trie_db)
+        } else {
+            None
+        };
+        let (trie_db_handle, trie_column_families) = match &trie_db {
+            Some(trie_db) => (trie_db.db(), &ALL_COLUMN_FAMILIES[..]),
+            None => (main_db.db(), &[DbColumnFamily::SyncTrie][..]),
+        };
+        let trie_db_keys = Self::count_keys(
+            trie_db_handle.as_ref().unwrap(),
+            trie_column_families,
+            |keys| {
+                handle.report(SnapshotProgress::keys("trie", keys));
+                handle.check_cancelled()
+            },
+        )?;
+        drop(trie_db_handle);
+        handle.report(SnapshotProgress::keys("trie", trie_db_keys));
+
+        let trie = trie_db.as_ref().unwrap_or(&main_db);
+        let trie_root_hash = match trie.get(&TrieNode::make_primary_key(&[], None))? {
+            Some(root_bytes) => hex::encode(TrieNode::deserialize(&root_bytes)?.hash()),
+            None => String::new(),
+        };
+
+        if let Some(trie_db) = trie_db {
+            trie_db.close()?;
+        }
+        main_db.close()?;
+
+        Ok(SnapshotManifest::new(
+            timestamp_ms,
+            db_schema_version,
+            network,
+            trie_root_hash,
+            main_db_keys,
+            trie_db_keys,
+        ))
     }
 
     fn snapshot_backup(
         main_db: Arc<RocksDB>,
         trie_db: Arc<RocksDB>,
         timestamp_ms: i64,
+        options: SnapshotOptions,
+        handle: Arc<SnapshotHandle>,
     ) -> Result<String, HubError> {
         let snapshot_logger = LOGGER.new(o! ("component" => "RocksDBSnapshotBackup"));
         let main_db_path = main_db.location();
@@ -1049,11 +2509,12 @@ impl RocksDB {
         let timestamp = chrono::NaiveDateTime::from_timestamp_millis(timestamp_ms)
             .unwrap_or(chrono::Utc::now().naive_utc());
 
-        let main_backup_path = Path::new(&main_db_path)
-            .join("..") // Create backup as sibling directory of normal path
-            .join("backup")
-            .join(format!("{}.backup", timestamp.format("%Y-%m-%d-%s")))
-            .join("rocks.hub._default");
+        // Keep gc_snapshots away from this snapshot until it is done
+        let _active = ActiveSnapshot::new(timestamp.and_utc().timestamp());
+
+        let backup_dir = Self::backup_dir(&main_db_path)
+            .join(format!("{}.backup", timestamp.format("%Y-%m-%d-%s")));
+        let main_backup_path = backup_dir.join("rocks.hub._default");
 
         // rm -rf this path if it exists
         if main_backup_path.exists() {
@@ -1067,96 +2528,68 @@ impl RocksDB {
 
         let main_backup_path = main_backup_path.into_os_string().into_string().unwrap();
         let triedb_backup_path = triedb_backup_path.into_os_string().into_string().unwrap();
+        let tar_gz_path = Self::chunked_output_dir(&main_backup_path, timestamp, options.codec());
+
+        let result = Self::create_snapshot(
+            &snapshot_logger,
+            &main_db,
+            &trie_db,
+            &main_backup_path,
+            &triedb_backup_path,
+            &tar_gz_path,
+            timestamp_ms,
+            &options,
+            &handle,
+        );
 
-        let start = std::time::SystemTime::now();
-        info!(snapshot_logger, "Creating snapshot for main DB: {}", main_db_path; 
-        o!("output_file_path_main" => &main_backup_path, "output_file_path_trie" => &triedb_backup_path));
-
-        let backup_main = DB::open_default(&main_backup_path)
-            .map_err(|e| HubError::internal_db_error(&e.to_string()))?;
-        // Prepare write options to disable WAL
-        let mut write_opts = WriteOptions::default();
-        write_opts.disable_wal(true);
-        let mut write_batch = WriteBatch::default();
-
-        let backup_trie = DB::open_default(&triedb_backup_path)
-            .map_err(|e| HubError::internal_db_error(&e.to_string()))?;
-
-        let logger = snapshot_logger.clone();
-        let main_backup_thread = std::thread::spawn(move || {
-            let main_db = main_db.db();
-            let main_db_snapshot = main_db.as_ref().unwrap().snapshot();
-
-            let iterator = main_db_snapshot.iterator(rocksdb::IteratorMode::Start);
-            let mut count = 0;
-            for item in iterator {
-                let (key, value) = item.unwrap();
-                write_batch.put(key, value);
-                if write_batch.len() >= 10_000 {
-                    backup_main.write_opt(write_batch, &write_opts).unwrap();
-                    write_batch = WriteBatch::default();
-                }
-
-                count += 1;
-                if count % 1_000_000 == 0 {
-                    backup_main.flush().unwrap();
-                    info!(
-                        logger,
-                        "mainDb Snapshot backup progress: {}M keys",
-                        count / 1_000_000
-                    );
-                }
-            }
-
-            // write any leftover keys
-            backup_main.write_opt(write_batch, &write_opts).unwrap();
+        if let Err(e) = result {
+            // A cancelled write shows up as an io error, so check the handle to report it as such
+            let e = handle.check_cancelled().err().unwrap_or(e);
+            info!(snapshot_logger, "Snapshot backup failed, removing partial backup";
+                "error" => e.to_string());
 
-            info!(logger, "mainDB Snapshot backup completed: {}", count);
-            drop(main_db_snapshot);
-            drop(backup_main);
-        });
+            // rm -rf whatever was written so far, ignore any errors
+            let _ = fs::remove_dir_all(&backup_dir);
+            let _ = fs::remove_dir_all(&tar_gz_path);
+            return Err(e);
+        }
 
-        let logger = snapshot_logger.clone();
-        // Prepare write options to disable WAL
-        let mut write_opts = WriteOptions::default();
-        write_opts.disable_wal(true);
-        let mut write_batch = WriteBatch::default();
+        handle.report(SnapshotProgress::phase(SnapshotPhase::Cleanup));
 
-        let trie_backup_thread = std::thread::spawn(move || {
-            let trie_db = trie_db.db();
-            let trie_db_snapshot = trie_db.as_ref().unwrap().snapshot();
-
-            let iterator = trie_db_snapshot.iterator(rocksdb::IteratorMode::Start);
-            let mut count = 0;
-            for item in iterator {
-                let (key, value) = item.unwrap();
-                write_batch.put(key, value);
-                if write_batch.len() >= 10_000 {
-                    backup_trie.write_opt(write_batch, &write_opts).unwrap();
-                    write_batch = WriteBatch::default();
-                }
+        // rm -rf the backup path
+        fs::remove_dir_all(&backup_dir).map_err(|e| HubError {
+            code: "db.internal_error".to_string(),
+            message: e.to_string(),
+        })?;
 
-                count += 1;
-                if count % 1_000_000 == 0 {
-                    backup_trie.flush().unwrap();
-                    info!(
-                        logger,
-                        "trieDb Snapshot backup progress: {}M keys",
-                        count / 1_000_000
-                    );
-                }
-            }
+        Ok(tar_gz_path)
+    }
 
-            // write any leftover keys
-            backup_trie.write_opt(write_batch, &write_opts).unwrap();
+    /** The copy and tar phases of snapshot_backup */
+    #[allow(clippy::too_many_arguments)]
+    fn create_snapshot(
+        snapshot_logger: &Logger,
+        main_db: &RocksDB,
+        trie_db: &RocksDB,
+        main_backup_path: &str,
+        triedb_backup_path: &str,
+        tar_gz_path: &str,
+        timestamp_ms: i64,
+        options: &SnapshotOptions,
+        handle: &Arc<SnapshotHandle>,
+    ) -> Result<(), HubError> {
+        handle.check_cancelled()?;
+        handle.report(SnapshotProgress::phase(SnapshotPhase::Copy));
 
-            info!(logger, "trieDB Snapshot backup completed: {}", count);
-            drop(trie_db_snapshot);
-            drop(backup_trie);
-        });
+        let start = std::time::SystemTime::now();
+        info!(snapshot_logger, "Creating snapshot for main DB: {}", main_db.location(); 
+        o!("output_file_path_main" => main_backup_path, "output_file_path_trie" => triedb_backup_path));
 
-        main_backup_thread.join().unwrap();
-        trie_backup_thread.join().unwrap();
+        // The checkpoint directory itself must not exist, but its parent must
+        if let Some(parent) = Path::new(main_backup_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Self::create_checkpoints(main_db, trie_db, main_backup_path, triedb_backup_path)?;
 
         info!(
             snapshot_logger,
@@ -1165,19 +2598,33 @@ impl RocksDB {
             start.elapsed().expect("Time went backwards")
         );
 
-        let tar_gz_path = Self::create_tar_gzip(&snapshot_logger, &main_backup_path, timestamp)?;
+        let mut manifest =
+            Self::describe_checkpoints(main_backup_path, triedb_backup_path, timestamp_ms, handle)?;
+
+        handle.check_cancelled()?;
+        handle.report(SnapshotProgress::phase(SnapshotPhase::Tar));
+
+        Self::create_tar_gzip(
+            snapshot_logger,
+            main_backup_path,
+            tar_gz_path,
+            options,
+            handle,
+        )?;
         info!(
             snapshot_logger,
             "Full DB Snapshot Backup tar.gz created: path = {}", tar_gz_path,
         );
 
-        // rm -rf the backup path
-        fs::remove_dir_all(&main_backup_path).map_err(|e| HubError {
-            code: "db.internal_error".to_string(),
-            message: e.to_string(),
-        })?;
+        manifest.add_chunks(Path::new(tar_gz_path))?;
+        manifest.write(Path::new(tar_gz_path))?;
+        info!(snapshot_logger, "Snapshot manifest written";
+            "chunks" => manifest.chunks.len(),
+            "mainDbKeys" => manifest.main_db_keys,
+            "trieDbKeys" => manifest.trie_db_keys,
+            "trieRootHash" => &manifest.trie_root_hash);
 
-        Ok(tar_gz_path)
+        handle.check_cancelled()
     }
 
     pub fn js_snapshot_backup(mut cx: FunctionContext) -> JsResult<JsPromise> {
@@ -1188,12 +2635,25 @@ impl RocksDB {
 
         let timestamp_ms = cx.argument::<JsNumber>(2)?.value(&mut cx) as i64;
 
+        // Optional handle from dbCreateSnapshotHandle, to report progress and cancel the backup
+        let handle = match cx.argument_opt(3) {
+            Some(arg) if !arg.is_a::<JsUndefined, _>(&mut cx) => {
+                (**arg.downcast_or_throw::<JsBox<Arc<SnapshotHandle>>, _>(&mut cx)?).clone()
+            }
+            _ => Arc::new(SnapshotHandle::new()),
+        };
+
+        let options = store::get_snapshot_options(&mut cx, 4)?;
+        if let Err(e) = options.validate() {
+            return hub_error_to_js_throw(&mut cx, e);
+        }
+
         let channel = cx.channel();
         let (deferred, promise) = cx.promise();
 
         // Spawn a new thread to create the tarball
         std::thread::spawn(move || {
-            let result = Self::snapshot_backup(main_db, trie_db, timestamp_ms);
+            let result = Self::snapshot_backup(main_db, trie_db, timestamp_ms, options, handle);
 
             deferred.settle_with(&channel, move |mut tcx| match result {
                 Ok(output_path) => Ok(tcx.string(output_path)),
@@ -1203,63 +2663,272 @@ impl RocksDB {
 
         Ok(promise)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::db::RocksDbTransactionBatch;
+    /**
+     * Open a restored main DB and its trie DB, and make sure they look usable before they are
+     * swapped into place. The trie DB must have a root node, otherwise the hub would start with
+     * an empty trie and have to resync everything. Both are opened read-only, so the check
+     * doesn't write to them, nor migrate or count their keys.
+     */
+    fn check_restored_db(logger: &Logger, main_db_path: &Path) -> Result<(), HubError> {
+        let trie_db_path = main_db_path.join(TRIE_DBPATH_PREFIX);
+        if !trie_db_path.is_dir() {
+            return Err(HubError::validation_failure(&format!(
+                "snapshot is missing the trie DB at {}",
+                trie_db_path.display()
+            )));
+        }
 
-    #[test]
-    fn test_merge_rocksdb_transaction() {
-        let mut txn1 = RocksDbTransactionBatch::new();
+        let read_only = RocksDbOptions {
+            mode: DbOpenMode::ReadOnly,
+            ..RocksDbOptions::default()
+        };
 
-        let mut txn2 = RocksDbTransactionBatch::new();
+        let main_db = RocksDB::new(&main_db_path.to_string_lossy())?;
+        main_db.open_with_options(&read_only)?;
+        let is_empty =
+            main_db.for_each_iterator_by_prefix(&[], &PageOptions::default(), |_, _| Ok(true));
+        main_db.close()?;
+        if is_empty? {
+            return Err(HubError::validation_failure("snapshot main DB is empty"));
+        }
 
-        // Add some txns to txn2
-        txn2.put(b"key1".to_vec(), b"value1".to_vec());
-        txn2.put(b"key2".to_vec(), b"value2".to_vec());
-        txn2.delete(b"key3".to_vec());
+        let trie_db = RocksDB::new(&trie_db_path.to_string_lossy())?;
+        trie_db.open_with_options(&read_only)?;
+        let root = trie_db.get(&TrieNode::make_primary_key(&[], None));
+        trie_db.close()?;
+        let root = match root? {
+            Some(root_bytes) => TrieNode::deserialize(&root_bytes)?,
+            None => {
+                return Err(HubError::validation_failure(
+                    "snapshot trie DB has no root node",
+                ))
+            }
+        };
 
-        // Merge txn2 into txn1
-        txn1.merge(txn2);
+        info!(logger, "Restored snapshot looks valid";
+            "path" => main_db_path.display().to_string(),
+            "rootHash" => hex::encode(root.hash()),
+            "items" => root.items());
 
-        // Check that txn1 has all the keys from txn2
-        assert_eq!(txn1.batch.len(), 3);
-        assert_eq!(
-            txn1.batch
-                .get(&b"key1".to_vec())
-                .unwrap()
-                .as_ref()
-                .unwrap()
-                .clone(),
-            b"value1".to_vec()
-        );
-        assert_eq!(
-            txn1.batch
-                .get(&b"key2".to_vec())
-                .unwrap()
-                .as_ref()
-                .unwrap()
-                .clone(),
-            b"value2".to_vec()
-        );
-        assert_eq!(txn1.batch.get(&b"key3".to_vec()).unwrap().is_none(), true);
+        Ok(())
+    }
 
-        // Add some more txns to txn3
-        let mut txn3 = RocksDbTransactionBatch::new();
-        txn3.put(b"key4".to_vec(), b"value4".to_vec());
-        txn3.put(b"key5".to_vec(), b"value5".to_vec());
+    /**
+     * Restore a snapshot created by snapshot_backup. The chunked tar.gz archive in `archive_dir`
+     * is unpacked into a staging directory next to `target_path`, checked, and then swapped into
+     * place, see RestorePaths. Any existing DB at `target_path` is only removed after the new one
+     * is in place. The DBs at `target_path` must be closed while this runs.
+     */
+    fn restore_snapshot(archive_dir: &str, target_path: &str) -> Result<String, HubError> {
+        let restore_logger = LOGGER.new(o! ("component" => "RocksDBSnapshotRestore"));
 
-        // Merge txn3 into txn1
-        txn1.merge(txn3);
+        let paths = RestorePaths::new(Path::new(target_path))?;
+        paths.recover(&restore_logger)?;
+        let staging = StagingDir::create(&paths.staging)?;
 
-        // Check that txn1 has all the keys from txn2 and txn3
-        assert_eq!(txn1.batch.len(), 5);
-        assert_eq!(
-            txn1.batch
-                .get(&b"key1".to_vec())
-                .unwrap()
-                .as_ref()
+        let start = std::time::SystemTime::now();
+        info!(restore_logger, "Unpacking snapshot: {}", archive_dir;
+            o!("staging_path" => staging.path().display().to_string()));
+
+        // Older snapshots don't have a manifest, those are only checked after they are unpacked
+        if Path::new(archive_dir).join(SNAPSHOT_MANIFEST_FILE).exists() {
+            SnapshotManifest::verify(Path::new(archive_dir))?;
+        }
+
+        let reader = MultiChunkReader::new(PathBuf::from(archive_dir))?;
+        let mut archive = Archive::new(reader);
+        archive.unpack(staging.path())?;
+
+        // The archive has a single top level directory, named after the backed up DB
+        let mut entries = fs::read_dir(staging.path())?.collect::<Result<Vec<_>, _>>()?;
+        let restored_path = match entries.pop() {
+            Some(entry) if entries.is_empty() && entry.path().is_dir() => entry.path(),
+            _ => {
+                return Err(HubError::validation_failure(
+                    "snapshot must contain exactly one DB directory",
+                ))
+            }
+        };
+
+        Self::check_restored_db(&restore_logger, &restored_path)?;
+
+        // The trie DB lives inside the main DB directory, so this moves both of them
+        paths.swap(&restored_path)?;
+
+        info!(
+            restore_logger,
+            "Snapshot restored: path = {}, time taken = {:?}",
+            target_path,
+            start.elapsed().expect("Time went backwards")
+        );
+
+        Ok(target_path.to_string())
+    }
+
+    /**
+     * Delete the snapshots of `main_db` that `retention` doesn't keep. Returns the deleted paths.
+     * A snapshot that is being written by snapshot_backup is never deleted.
+     */
+    pub fn gc_snapshots(
+        main_db: &RocksDB,
+        retention: &SnapshotRetention,
+    ) -> Result<Vec<String>, HubError> {
+        let gc_logger = LOGGER.new(o! ("component" => "RocksDBSnapshotGc"));
+
+        let deleted = snapshot_retention::gc_snapshots(
+            Path::new(DB_DIRECTORY),
+            &Self::backup_dir(&main_db.location()),
+            retention,
+            chrono::Utc::now().timestamp(),
+        )?;
+        for path in &deleted {
+            info!(gc_logger, "Deleted old snapshot"; "path" => path);
+        }
+
+        Ok(deleted)
+    }
+
+    pub fn js_gc_snapshots(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let main_db_handle = cx.argument::<JsBox<Arc<RocksDB>>>(0)?;
+        let main_db = (**main_db_handle.borrow()).clone();
+
+        let retention = store::get_snapshot_retention(&mut cx, 1)?;
+        if let Err(e) = retention.validate() {
+            return hub_error_to_js_throw(&mut cx, e);
+        }
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        std::thread::spawn(move || {
+            let result = Self::gc_snapshots(&main_db, &retention);
+
+            deferred.settle_with(&channel, move |mut tcx| match result {
+                Ok(deleted) => {
+                    let js_array = JsArray::new(&mut tcx, deleted.len());
+                    for (i, path) in deleted.iter().enumerate() {
+                        let js_path = tcx.string(path);
+                        js_array.set(&mut tcx, i as u32, js_path)?;
+                    }
+                    Ok(js_array)
+                }
+                Err(e) => hub_error_to_js_throw(&mut tcx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+
+    pub fn js_verify_snapshot(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let archive_dir = cx.argument::<JsString>(0)?.value(&mut cx);
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // Hashing the chunks reads the whole snapshot, so do it off the main thread
+        std::thread::spawn(move || {
+            let result = SnapshotManifest::verify(Path::new(&archive_dir))
+                .and_then(|manifest| manifest.to_json());
+
+            deferred.settle_with(&channel, move |mut tcx| match result {
+                Ok(manifest) => Ok(tcx.string(manifest)),
+                Err(e) => hub_error_to_js_throw(&mut tcx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+
+    pub fn js_restore_snapshot(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let archive_dir = cx.argument::<JsString>(0)?.value(&mut cx);
+        let target_path = cx.argument::<JsString>(1)?.value(&mut cx);
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // Spawn a new thread to unpack the tarball
+        std::thread::spawn(move || {
+            let result = Self::restore_snapshot(&archive_dir, &target_path);
+
+            deferred.settle_with(&channel, move |mut tcx| match result {
+                Ok(output_path) => Ok(tcx.string(output_path)),
+                Err(e) => hub_error_to_js_throw(&mut tcx, e),
+            });
+        });
+
+        Ok(promise)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::column_families::DbColumnFamily;
+    use crate::db::rocksdb::CF_MIGRATION_BATCH_SIZE;
+    use crate::db::stats_reporter::StatsCollector;
+    use crate::db::{
+        DbOpenMode, JsIteratorOptions, OrderedWriteBatch, RocksDbOptions, RocksDbTransactionBatch,
+        WritePriority, WriteStallState,
+    };
+    use crate::store::{
+        first_event_id_at, increment_vec_u8, make_user_key, PageOptions, RootPrefix, UserPostfix,
+        FARCASTER_EPOCH,
+    };
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_merge_rocksdb_transaction() {
+        let mut txn1 = RocksDbTransactionBatch::new();
+
+        let mut txn2 = RocksDbTransactionBatch::new();
+
+        // Add some txns to txn2
+        txn2.put(b"key1".to_vec(), b"value1".to_vec());
+        txn2.put(b"key2".to_vec(), b"value2".to_vec());
+        txn2.delete(b"key3".to_vec());
+
+        // Merge txn2 into txn1
+        txn1.merge(txn2);
+
+        // Check that txn1 has all the keys from txn2
+        assert_eq!(txn1.batch.len(), 3);
+        assert_eq!(
+            txn1.batch
+                .get(&b"key1".to_vec())
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .clone(),
+            b"value1".to_vec()
+        );
+        assert_eq!(
+            txn1.batch
+                .get(&b"key2".to_vec())
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .clone(),
+            b"value2".to_vec()
+        );
+        assert_eq!(txn1.batch.get(&b"key3".to_vec()).unwrap().is_none(), true);
+
+        // Add some more txns to txn3
+        let mut txn3 = RocksDbTransactionBatch::new();
+        txn3.put(b"key4".to_vec(), b"value4".to_vec());
+        txn3.put(b"key5".to_vec(), b"value5".to_vec());
+
+        // Merge txn3 into txn1
+        txn1.merge(txn3);
+
+        // Check that txn1 has all the keys from txn2 and txn3
+        assert_eq!(txn1.batch.len(), 5);
+        assert_eq!(
+            txn1.batch
+                .get(&b"key1".to_vec())
+                .unwrap()
+                .as_ref()
                 .unwrap()
                 .clone(),
             b"value1".to_vec()
@@ -1316,6 +2985,250 @@ mod tests {
         assert_eq!(txn1.batch.get(&b"key5".to_vec()).unwrap().is_none(), true);
     }
 
+    #[test]
+    fn test_transaction_conflicts() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        db.put(b"key1", b"value1").unwrap();
+
+        // Nothing changed since the reads, so the commit goes through
+        let mut txn = db.txn();
+        assert_eq!(
+            db.get_for_update(&mut txn, b"key1").unwrap(),
+            Some(b"value1".to_vec())
+        );
+        assert_eq!(db.get_for_update(&mut txn, b"key2").unwrap(), None);
+        txn.put(b"key2".to_vec(), b"value2".to_vec());
+        assert_eq!(
+            db.get_for_update(&mut txn, b"key2").unwrap(),
+            Some(b"value2".to_vec())
+        );
+        db.commit(txn).unwrap();
+        assert_eq!(db.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+
+        // A key that was read is changed before the commit
+        let mut txn = db.txn();
+        db.get_for_update(&mut txn, b"key1").unwrap();
+        txn.put(b"key3".to_vec(), b"value3".to_vec());
+        db.put(b"key1", b"changed").unwrap();
+        assert_eq!(db.commit(txn).unwrap_err().code, "db.transaction_conflict");
+        assert_eq!(db.get(b"key3").unwrap(), None);
+
+        // A key that was read as missing is created before the commit
+        let mut txn = db.txn();
+        db.get_for_update(&mut txn, b"key4").unwrap();
+        db.put(b"key4", b"value4").unwrap();
+        assert_eq!(db.commit(txn).unwrap_err().code, "db.transaction_conflict");
+
+        // A key that was read is deleted before the commit
+        let mut txn = db.txn();
+        db.get_for_update(&mut txn, b"key4").unwrap();
+        db.del(b"key4").unwrap();
+        assert_eq!(db.commit(txn).unwrap_err().code, "db.transaction_conflict");
+
+        // Writes to keys that weren't read don't conflict
+        let mut txn = db.txn();
+        db.get_for_update(&mut txn, b"key1").unwrap();
+        db.put(b"key2", b"changed").unwrap();
+        txn.put(b"key1".to_vec(), b"value1".to_vec());
+        db.commit(txn).unwrap();
+        assert_eq!(db.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+
+        // Reads that were rolled back don't conflict either
+        let mut txn = db.txn();
+        txn.put(b"key5".to_vec(), b"value5".to_vec());
+        txn.set_savepoint();
+        db.get_for_update(&mut txn, b"key2").unwrap();
+        txn.rollback_to_savepoint();
+        db.put(b"key2", b"changed again").unwrap();
+        db.commit(txn).unwrap();
+        assert_eq!(db.get(b"key5").unwrap(), Some(b"value5".to_vec()));
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_commits() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = Arc::new(crate::db::RocksDB::new(&tmp_path).unwrap());
+        db.open().unwrap();
+
+        // Every commit writes the same keys, in two column families
+        let keys = vec![
+            make_user_key(1),
+            vec![RootPrefix::HubEvents as u8, 0, 1],
+            b"key1".to_vec(),
+        ];
+
+        let threads = (0..8u8)
+            .map(|thread| {
+                let db = db.clone();
+                let keys = keys.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100u8 {
+                        let mut txn = db.txn();
+                        for key in &keys {
+                            txn.put(key.clone(), vec![thread, i]);
+                        }
+                        db.commit(txn).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // None of the commits failed, and each was applied whole, so all the keys hold the
+        // values of the last one
+        let last = db.get(&keys[0]).unwrap().unwrap();
+        assert_eq!(last[1], 99);
+        for key in &keys {
+            assert_eq!(db.get(key).unwrap(), Some(last.clone()));
+        }
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_commit_lock_scope() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        let stripe = crate::db::RocksDB::commit_lock_stripe(b"key1");
+        let commit_guard = db.commit_locks[stripe].lock().unwrap();
+
+        // Writing events and trie nodes doesn't wait for the commit locks
+        let mut txn = db.txn();
+        txn.put(vec![RootPrefix::HubEvents as u8, 0, 1], b"event".to_vec());
+        db.commit(txn).unwrap();
+        db.put(&[RootPrefix::SyncMerkleTrieNode as u8, 1], b"node")
+            .unwrap();
+
+        // Nor does writing a key of another stripe
+        let other_key = (0u8..)
+            .map(|i| vec![b'k', i])
+            .find(|key| crate::db::RocksDB::commit_lock_stripe(key) != stripe)
+            .unwrap();
+        db.put(&other_key, b"value").unwrap();
+
+        // The keys of a counted prefix share their stripe
+        let user_key = |fid: u8, id: u8| vec![RootPrefix::User as u8, 0, 0, 0, fid, 1, id];
+        assert_eq!(
+            crate::db::RocksDB::commit_lock_stripe(&user_key(1, 1)),
+            crate::db::RocksDB::commit_lock_stripe(&user_key(1, 2))
+        );
+
+        // Writing a key of the stripe waits for it
+        std::thread::scope(|scope| {
+            let write = scope.spawn(|| db.put(b"key1", b"value1"));
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!write.is_finished());
+
+            drop(commit_guard);
+            write.join().unwrap().unwrap();
+        });
+        assert_eq!(db.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_transaction_savepoint() {
+        let mut txn = RocksDbTransactionBatch::new();
+        txn.put(b"key1".to_vec(), b"value1".to_vec());
+        txn.delete(b"key2".to_vec());
+
+        txn.set_savepoint();
+        txn.put(b"key1".to_vec(), b"changed".to_vec());
+        txn.put(b"key2".to_vec(), b"value2".to_vec());
+        txn.put(b"key3".to_vec(), b"value3".to_vec());
+        txn.delete(b"key3".to_vec());
+        txn.rollback_to_savepoint();
+
+        // Every key is back to what it was at the savepoint, however often it was written since
+        assert_eq!(txn.len(), 2);
+        assert_eq!(txn.batch[b"key1".as_slice()], Some(b"value1".to_vec()));
+        assert_eq!(txn.batch[b"key2".as_slice()], None);
+
+        // A rollback without a savepoint doesn't undo anything
+        txn.put(b"key3".to_vec(), b"value3".to_vec());
+        txn.rollback_to_savepoint();
+        assert_eq!(txn.len(), 3);
+
+        // The reads made since the savepoint are forgotten, the ones before it are kept
+        txn.read(b"key4".to_vec(), None);
+        txn.set_savepoint();
+        txn.read(b"key4".to_vec(), Some(b"value4".to_vec()));
+        txn.read(b"key5".to_vec(), None);
+        txn.rollback_to_savepoint();
+        assert_eq!(txn.reads.len(), 1);
+        assert_eq!(txn.reads[b"key4".as_slice()], None);
+    }
+
+    #[test]
+    fn test_commit_ordered() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        let mut prefix = make_user_key(1);
+        prefix.push(UserPostfix::CastAdds.as_u8());
+        let key1 = [prefix.as_slice(), &[1]].concat();
+        let key2 = [prefix.as_slice(), &[2]].concat();
+
+        // The last write to a key wins, and only the keys that end up put are counted
+        let mut batch = OrderedWriteBatch::new();
+        batch.put(key1.clone(), b"value1".to_vec());
+        batch.delete(key1.clone());
+        batch.delete(key2.clone());
+        batch.put(key2.clone(), b"value2".to_vec());
+        db.commit_ordered(batch).unwrap();
+
+        assert_eq!(db.get(&key1).unwrap(), None);
+        assert_eq!(db.get(&key2).unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(db.count_keys_at_prefix(&prefix).unwrap(), 1);
+
+        let mut batch = OrderedWriteBatch::new();
+        batch.put(key1.clone(), b"value1".to_vec());
+        batch.delete(key2.clone());
+        batch.put(key1.clone(), b"value3".to_vec());
+        db.commit_ordered(batch).unwrap();
+
+        assert_eq!(db.get(&key1).unwrap(), Some(b"value3".to_vec()));
+        assert_eq!(db.get(&key2).unwrap(), None);
+        assert_eq!(db.count_keys_at_prefix(&prefix).unwrap(), 1);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
     #[test]
     fn test_count_keys_at_prefix() {
         let tmp_path = tempfile::tempdir()
@@ -1361,6 +3274,299 @@ mod tests {
         db.destroy().unwrap();
     }
 
+    #[test]
+    fn test_delete_all_keys_in_range() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        // Keys in the User and HubEvents column families
+        let user = RootPrefix::User as u8;
+        let events = RootPrefix::HubEvents as u8;
+        let keys = vec![
+            vec![user, 1],
+            vec![user, 1, 0],
+            vec![user, 2],
+            vec![user, 3],
+            vec![events, 1],
+            vec![events, 2],
+        ];
+        for key in &keys {
+            db.put(key, b"value").unwrap();
+        }
+
+        // gt doesn't delete the bound itself, but does delete the keys that extend it
+        let js_opts = JsIteratorOptions {
+            reverse: false,
+            gte: None,
+            gt: Some(vec![user, 1]),
+            lt: vec![user, 3],
+        };
+        assert!(db.delete_all_keys_in_range(js_opts, false).unwrap());
+        assert_eq!(
+            db.keys_exist(&keys).unwrap(),
+            vec![true, false, false, true, true, true]
+        );
+
+        // A range that spans column families, compacted afterwards
+        let js_opts = JsIteratorOptions {
+            reverse: false,
+            gte: Some(vec![user, 3]),
+            gt: None,
+            lt: vec![events, 2],
+        };
+        assert!(db.delete_all_keys_in_range(js_opts, true).unwrap());
+        assert_eq!(
+            db.keys_exist(&keys).unwrap(),
+            vec![true, false, false, false, false, true]
+        );
+
+        let js_opts = JsIteratorOptions {
+            reverse: false,
+            gte: None,
+            gt: None,
+            lt: vec![events, 2],
+        };
+        assert!(db.delete_all_keys_in_range(js_opts, false).is_err());
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    fn scan_count(db: &crate::db::RocksDB, prefix: &[u8]) -> u32 {
+        let mut count = 0;
+        db.for_each_iterator_by_prefix(prefix, &PageOptions::default(), |_, _| {
+            count += 1;
+            Ok(false)
+        })
+        .unwrap();
+        count
+    }
+
+    #[test]
+    fn test_key_counters() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        // A new DB counts its keys from the start
+        assert!(db.key_counters_complete.load(Ordering::Relaxed));
+
+        let prefix = |fid: u32| {
+            let mut prefix = make_user_key(fid);
+            prefix.push(UserPostfix::CastAdds.as_u8());
+            prefix
+        };
+        let key = |fid: u32, id: u8| {
+            let mut key = prefix(fid);
+            key.push(id);
+            key
+        };
+        let assert_counts = |expected: [u32; 2]| {
+            for (fid, expected) in [1, 2].into_iter().zip(expected) {
+                assert_eq!(db.count_keys_at_prefix(&prefix(fid)).unwrap(), expected);
+                assert_eq!(scan_count(&db, &prefix(fid)), expected);
+            }
+        };
+
+        // Deleting a key that doesn't exist doesn't count
+        let mut txn = db.txn();
+        txn.put(key(1, 1), b"value".to_vec());
+        txn.put(key(1, 2), b"value".to_vec());
+        txn.put(key(2, 1), b"value".to_vec());
+        txn.delete(key(1, 9));
+        db.commit(txn).unwrap();
+        assert_counts([2, 1]);
+
+        // Neither does overwriting a key
+        let mut txn = db.txn();
+        txn.put(key(1, 1), b"value2".to_vec());
+        txn.delete(key(1, 2));
+        db.commit(txn).unwrap();
+        assert_counts([1, 1]);
+
+        db.put(&key(1, 3), b"value").unwrap();
+        assert_counts([2, 1]);
+        db.del(&key(1, 3)).unwrap();
+        assert_counts([1, 1]);
+
+        // A range that starts and ends in the middle of prefixes
+        db.put(&key(1, 2), b"value").unwrap();
+        db.put(&key(2, 2), b"value").unwrap();
+        assert_counts([2, 2]);
+        db.delete_range(&key(1, 2), &key(2, 2)).unwrap();
+        assert_counts([1, 1]);
+        db.put(&key(2, 1), b"value").unwrap();
+        db.delete_range(&key(2, 1), &key(2, 2)).unwrap();
+        assert_counts([1, 1]);
+
+        db.delete_range(&make_user_key(1), &make_user_key(2))
+            .unwrap();
+        assert_counts([0, 1]);
+
+        db.clear().unwrap();
+        assert_counts([0, 0]);
+        db.put(&key(2, 1), b"value").unwrap();
+        assert_counts([0, 1]);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_repair_key_counters() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+
+        let mut prefix = make_user_key(1);
+        prefix.push(UserPostfix::LinkAdds.as_u8());
+        let key = |id: u8| {
+            let mut key = prefix.clone();
+            key.push(id);
+            key
+        };
+
+        // A DB created before the counters existed
+        {
+            let mut opts = rocksdb::Options::default();
+            opts.create_if_missing(true);
+            let old_db = rocksdb::DB::open(&opts, &tmp_path).unwrap();
+            old_db.put(key(1), b"value").unwrap();
+            old_db.put(key(2), b"value").unwrap();
+        }
+
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        // The keys are counted by scanning until the counters are repaired
+        assert!(!db.key_counters_complete.load(Ordering::Relaxed));
+        assert_eq!(db.count_keys_at_prefix(&prefix).unwrap(), 2);
+        db.put(&key(3), b"value").unwrap();
+        assert_eq!(db.count_keys_at_prefix(&prefix).unwrap(), 3);
+
+        assert_eq!(db.repair_key_counters().unwrap(), 1);
+        assert!(db.key_counters_complete.load(Ordering::Relaxed));
+        assert_eq!(db.count_keys_at_prefix(&prefix).unwrap(), 3);
+
+        // The counters are kept from now on, also after reopening
+        db.del(&key(1)).unwrap();
+        db.close().unwrap();
+        db.open().unwrap();
+        assert!(db.key_counters_complete.load(Ordering::Relaxed));
+        assert_eq!(db.count_keys_at_prefix(&prefix).unwrap(), 2);
+        assert_eq!(scan_count(&db, &prefix), 2);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_read_snapshots() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = Arc::new(crate::db::RocksDB::new(&tmp_path).unwrap());
+        db.open().unwrap();
+
+        let mut prefix = make_user_key(1);
+        prefix.push(UserPostfix::CastAdds.as_u8());
+        let key1 = [prefix.as_slice(), &[1]].concat();
+        let key2 = [prefix.as_slice(), &[2]].concat();
+        db.put(&key1, b"value1").unwrap();
+
+        let snapshot = db.create_read_snapshot(Duration::from_secs(60)).unwrap();
+
+        // Writes after the snapshot are not seen through it
+        db.put(&key1, b"value2").unwrap();
+        db.put(&key2, b"value2").unwrap();
+
+        assert_eq!(db.get(&key1).unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(
+            db.get_at(&key1, Some(&snapshot)).unwrap(),
+            Some(b"value1".to_vec())
+        );
+        assert_eq!(db.get_at(&key2, Some(&snapshot)).unwrap(), None);
+        assert_eq!(
+            db.get_many(&[key1.clone(), key2.clone()], Some(&snapshot))
+                .unwrap(),
+            vec![b"value1".to_vec(), vec![]]
+        );
+
+        let mut iterated = vec![];
+        db.for_each_iterator_by_prefix_paged(
+            &prefix,
+            &PageOptions::default(),
+            Some(&snapshot),
+            |key, _| {
+                iterated.push(key.to_vec());
+                Ok(false)
+            },
+        )
+        .unwrap();
+        assert_eq!(iterated, vec![key1.clone()]);
+
+        // A snapshot can only be used with the DB it was taken from
+        let other_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let other_db = crate::db::RocksDB::new(&other_path).unwrap();
+        other_db.open().unwrap();
+        assert_eq!(
+            other_db.get_at(&key1, Some(&snapshot)).unwrap_err().code,
+            "bad_request.invalid_param"
+        );
+        other_db.destroy().unwrap();
+
+        // Released snapshots can't be read from anymore
+        snapshot.release();
+        assert_eq!(
+            db.get_at(&key1, Some(&snapshot)).unwrap_err().code,
+            "db.read_snapshot_released"
+        );
+
+        // Expired snapshots are released by the watchdog
+        let snapshot = db.create_read_snapshot(Duration::ZERO).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(!db.release_expired_read_snapshots());
+        assert_eq!(
+            db.get_at(&key1, Some(&snapshot)).unwrap_err().code,
+            "db.read_snapshot_released"
+        );
+
+        // Closing the DB releases all the snapshots
+        let snapshot = db.create_read_snapshot(Duration::from_secs(60)).unwrap();
+        db.close().unwrap();
+        db.open().unwrap();
+        assert_eq!(
+            db.get_at(&key1, Some(&snapshot)).unwrap_err().code,
+            "db.read_snapshot_released"
+        );
+        drop(snapshot);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
     #[test]
     fn test_keys_exist_in_db() {
         let tmp_path = tempfile::tempdir()
@@ -1379,19 +3585,15 @@ mod tests {
         db.put(b"key200", b"value2").unwrap();
 
         // Check if keys exist
-        let exists = db.keys_exist(&vec![b"key100".to_vec(), b"key101".to_vec()]);
+        let exists = db.keys_exist(&[b"key100".to_vec(), b"key101".to_vec()]);
         assert_eq!(exists.unwrap(), vec![true, true]);
 
         // Check if keys exist with a key that doesn't exist
-        let exists = db.keys_exist(&vec![
-            b"key100".to_vec(),
-            b"key101".to_vec(),
-            b"key102".to_vec(),
-        ]);
+        let exists = db.keys_exist(&[b"key100".to_vec(), b"key101".to_vec(), b"key102".to_vec()]);
         assert_eq!(exists.unwrap(), vec![true, true, false]);
 
         // Check if keys exist with a key that doesn't exist
-        let exists = db.keys_exist(&vec![
+        let exists = db.keys_exist(&[
             b"key100".to_vec(),
             b"key101".to_vec(),
             b"key102".to_vec(),
@@ -1400,10 +3602,713 @@ mod tests {
         assert_eq!(exists.unwrap(), vec![true, true, false, true]);
 
         // No keys should return an empty array
-        let exists = db.keys_exist(&vec![]);
+        let exists = db.keys_exist(&[]);
         assert_eq!(exists.unwrap().len(), 0);
 
         // Cleanup
         db.destroy().unwrap();
     }
+
+    #[test]
+    fn test_iterate_across_column_families() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        // Keys that are routed to different column families
+        let keys = vec![
+            vec![RootPrefix::User as u8, 0, 0, 0, 1],
+            vec![RootPrefix::CastsByParent as u8, 1],
+            vec![RootPrefix::HubState as u8],
+            vec![RootPrefix::SyncMerkleTrieNode as u8, 1],
+            vec![RootPrefix::HubEvents as u8, 1],
+            vec![RootPrefix::OnChainEvent as u8, 1],
+            vec![RootPrefix::VerificationByAddress as u8, 1],
+        ];
+
+        // Commit them all in one transaction
+        let mut txn = db.txn();
+        for key in keys.iter() {
+            txn.put(key.clone(), key.clone());
+        }
+        db.commit(txn).unwrap();
+
+        for key in keys.iter() {
+            assert_eq!(db.get(key).unwrap(), Some(key.clone()));
+        }
+        assert_eq!(db.get_many(&keys, None).unwrap(), keys);
+
+        // Iterating over the whole DB returns the keys in order, across all column families
+        let mut sorted_keys = keys.clone();
+        sorted_keys.sort();
+
+        let mut iterated = vec![];
+        db.for_each_iterator_by_prefix(&[], &PageOptions::default(), |key, _| {
+            iterated.push(key.to_vec());
+            Ok(false)
+        })
+        .unwrap();
+        assert_eq!(iterated, sorted_keys);
+
+        // And in reverse
+        let mut iterated = vec![];
+        let reverse = PageOptions {
+            reverse: true,
+            ..PageOptions::default()
+        };
+        db.for_each_iterator_by_prefix(&[], &reverse, |key, _| {
+            iterated.push(key.to_vec());
+            Ok(false)
+        })
+        .unwrap();
+        sorted_keys.reverse();
+        assert_eq!(iterated, sorted_keys);
+
+        assert_eq!(db.count_keys_at_prefix(&[]).unwrap(), keys.len() as u32);
+        assert_eq!(
+            db.count_keys_at_prefix(&[RootPrefix::User as u8]).unwrap(),
+            1
+        );
+
+        // Clear removes the keys from all column families
+        db.clear().unwrap();
+        assert_eq!(db.count_keys_at_prefix(&[]).unwrap(), 0);
+        db.compact();
+
+        // Clearing an empty DB is a no-op
+        db.clear().unwrap();
+        db.put(&keys[0], b"value").unwrap();
+        assert_eq!(db.count_keys_at_prefix(&[]).unwrap(), 1);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_migrate_to_column_families() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+
+        let user_key = vec![RootPrefix::User as u8, 0, 0, 0, 1, 1];
+        let event_key = vec![RootPrefix::HubEvents as u8, 0, 1];
+        let state_key = vec![RootPrefix::HubState as u8];
+
+        // Create a DB with the old single column family layout
+        {
+            let mut opts = rocksdb::Options::default();
+            opts.create_if_missing(true);
+            let old_db: rocksdb::TransactionDB = rocksdb::TransactionDB::open(
+                &opts,
+                &rocksdb::TransactionDBOptions::default(),
+                &tmp_path,
+            )
+            .unwrap();
+            old_db.put(&user_key, b"user").unwrap();
+            old_db.put(&event_key, b"event").unwrap();
+            old_db.put(&state_key, b"state").unwrap();
+
+            // Enough keys to be moved in several batches
+            for i in 0..(CF_MIGRATION_BATCH_SIZE * 2 + 1) as u32 {
+                let mut key = vec![RootPrefix::User as u8, 0, 0, 0, 2];
+                key.extend_from_slice(&i.to_be_bytes());
+                old_db.put(&key, b"batched").unwrap();
+            }
+        }
+
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+        db.open().unwrap();
+
+        // All keys are readable
+        assert_eq!(db.get(&user_key).unwrap(), Some(b"user".to_vec()));
+        assert_eq!(db.get(&event_key).unwrap(), Some(b"event".to_vec()));
+        assert_eq!(db.get(&state_key).unwrap(), Some(b"state".to_vec()));
+
+        // And have been moved to their column families
+        {
+            let raw_db = db.db();
+            let raw_db = raw_db.as_ref().unwrap();
+            let default_cf = raw_db.cf_handle(DbColumnFamily::Default.name()).unwrap();
+            let user_cf = raw_db.cf_handle(DbColumnFamily::User.name()).unwrap();
+            let events_cf = raw_db.cf_handle(DbColumnFamily::HubEvents.name()).unwrap();
+
+            assert!(raw_db.get_cf(&default_cf, &user_key).unwrap().is_none());
+            assert!(raw_db.get_cf(&default_cf, &event_key).unwrap().is_none());
+            assert!(raw_db.get_cf(&user_cf, &user_key).unwrap().is_some());
+            assert!(raw_db.get_cf(&events_cf, &event_key).unwrap().is_some());
+            assert!(raw_db.get_cf(&default_cf, &state_key).unwrap().is_some());
+        }
+        let batched = CF_MIGRATION_BATCH_SIZE as u32 * 2 + 1;
+        assert_eq!(
+            db.count_keys_at_prefix(&[RootPrefix::User as u8, 0, 0, 0, 2])
+                .unwrap(),
+            batched
+        );
+
+        // Opening again is a no-op
+        db.close().unwrap();
+        db.open().unwrap();
+        assert_eq!(db.count_keys_at_prefix(&[]).unwrap(), 3 + batched);
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_open_with_options() {
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+
+        // Invalid options are rejected before the DB is opened
+        let invalid = RocksDbOptions {
+            level0_slowdown_writes_trigger: Some(10),
+            level0_stop_writes_trigger: Some(5),
+            ..Default::default()
+        };
+        let err = db.open_with_options(&invalid).unwrap_err();
+        assert_eq!(err.code, "bad_request.invalid_param");
+        assert!(db.db().is_none());
+
+        let options = RocksDbOptions {
+            block_cache_size: Some(8 * 1024 * 1024),
+            write_buffer_size: Some(4 * 1024 * 1024),
+            max_background_jobs: Some(2),
+            compression_per_level: Some(vec!["none".to_string(), "lz4".to_string()]),
+            bloom_filter_bits_per_key: Some(12.0),
+            ..Default::default()
+        };
+        db.open_with_options(&options).unwrap();
+
+        db.put(b"key100", b"value1").unwrap();
+        assert_eq!(db.get(b"key100").unwrap(), Some(b"value1".to_vec()));
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_read_only_and_secondary() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let main_path = tmp_dir.path().join("main").to_string_lossy().to_string();
+        let secondary_path = tmp_dir
+            .path()
+            .join("secondary")
+            .to_string_lossy()
+            .to_string();
+
+        // A DB has to exist before it can be opened read only
+        let read_only = crate::db::RocksDB::new(&main_path).unwrap();
+        let read_only_options = RocksDbOptions {
+            mode: DbOpenMode::ReadOnly,
+            ..Default::default()
+        };
+        assert!(read_only.open_with_options(&read_only_options).is_err());
+
+        let primary = crate::db::RocksDB::new(&main_path).unwrap();
+        primary.open().unwrap();
+        primary.put(b"key1", b"value1").unwrap();
+
+        read_only.open_with_options(&read_only_options).unwrap();
+        let secondary = crate::db::RocksDB::new(&main_path).unwrap();
+        secondary
+            .open_with_options(&RocksDbOptions {
+                mode: DbOpenMode::Secondary { secondary_path },
+                ..Default::default()
+            })
+            .unwrap();
+
+        for db in [&read_only, &secondary] {
+            assert_eq!(db.get(b"key1").unwrap(), Some(b"value1".to_vec()));
+
+            // Nothing can be written through them, nor can they delete the primary's files
+            assert_eq!(db.put(b"key2", b"value2").unwrap_err().code, "db.read_only");
+            assert_eq!(db.commit(db.txn()).unwrap_err().code, "db.read_only");
+            assert_eq!(db.clear().unwrap_err().code, "db.read_only");
+            assert_eq!(db.destroy().unwrap_err().code, "db.read_only");
+        }
+
+        // Only the secondary sees the primary's writes, once it catches up
+        primary.put(b"key2", b"value2").unwrap();
+        assert_eq!(secondary.get(b"key2").unwrap(), None);
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(secondary.get(b"key2").unwrap(), Some(b"value2".to_vec()));
+
+        assert_eq!(
+            read_only.try_catch_up_with_primary().unwrap_err().code,
+            "bad_request.invalid_param"
+        );
+        assert_eq!(read_only.get(b"key2").unwrap(), None);
+        assert_eq!(
+            primary.try_catch_up_with_primary().unwrap_err().code,
+            "bad_request.invalid_param"
+        );
+
+        // Cleanup
+        read_only.close().unwrap();
+        secondary.close().unwrap();
+        primary.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_collect_stats() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = crate::db::RocksDB::new(tmp_dir.path().to_str().unwrap()).unwrap();
+
+        // Nothing is collected while the DB is closed
+        let mut collector = StatsCollector::new("test");
+        assert!(db.collect_stats(&mut collector).is_empty());
+
+        db.open_with_options(&RocksDbOptions {
+            enable_statistics: Some(true),
+            ..Default::default()
+        })
+        .unwrap();
+        db.put(b"key1", b"value1").unwrap();
+        db.get(b"key1").unwrap();
+
+        let stats = db.collect_stats(&mut collector);
+        let names = stats
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"test.default.estimate_num_keys"));
+        assert!(names.contains(&"test.user.live_sst_files_size"));
+        assert!(names.contains(&"test.key_counters.size_all_mem_tables"));
+        assert!(names.contains(&"test.block_cache_usage"));
+        assert!(names.contains(&"test.is_write_stopped"));
+        assert!(names.contains(&"test.stall_micros"));
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_admit_write() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = crate::db::RocksDB::new(tmp_dir.path().to_str().unwrap()).unwrap();
+        db.open().unwrap();
+
+        // A freshly opened DB doesn't stall
+        assert_eq!(db.write_stall_state(), WriteStallState::Normal);
+        assert!(db.admit_write(WritePriority::Low).is_ok());
+
+        // Delayed writes are admitted, stopped low priority writes are rejected, and high
+        // priority writes are always admitted
+        db.write_stall.record(WriteStallState::Delayed);
+        assert!(db.admit_write(WritePriority::Low).is_ok());
+        assert!(db.admit_write(WritePriority::High).is_ok());
+
+        db.write_stall.record(WriteStallState::Stopped);
+        let err = db.admit_write(WritePriority::Low).unwrap_err();
+        assert_eq!(err.code, "db.write_stalled");
+        assert!(db.admit_write(WritePriority::High).is_ok());
+
+        // Reopening forgets the cached state
+        db.close().unwrap();
+        db.open().unwrap();
+        assert_eq!(db.write_stall_state(), WriteStallState::Normal);
+
+        let mut txn = db.txn();
+        txn.put(b"key1".to_vec(), b"value1".to_vec());
+        txn.delete(b"key2".to_vec());
+        assert_eq!(txn.byte_size(), 14);
+        db.commit(txn).unwrap();
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_hub_events_expiry() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let db = crate::db::RocksDB::new(tmp_dir.path().to_str().unwrap()).unwrap();
+        db.open_with_options(&RocksDbOptions {
+            hub_events_retention_ms: Some(60 * 60 * 1000),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(db.oldest_event_id().unwrap(), None);
+
+        let now = chrono::Utc::now().timestamp_millis() as u64 - FARCASTER_EPOCH;
+        let old_event = first_event_id_at(now - 2 * 60 * 60 * 1000);
+        let new_event = first_event_id_at(now) + 1;
+        let event_key = |event_id: u64| {
+            let mut key = vec![RootPrefix::HubEvents as u8];
+            key.extend_from_slice(&event_id.to_be_bytes());
+            key
+        };
+        db.put(&event_key(old_event), b"old").unwrap();
+        db.put(&event_key(new_event), b"new").unwrap();
+
+        // The expired event is skipped even before it is compacted away
+        assert_eq!(db.oldest_event_id().unwrap(), Some(new_event));
+        assert!(db.get(&event_key(old_event)).unwrap().is_some());
+
+        db.compact();
+        assert_eq!(db.get(&event_key(old_event)).unwrap(), None);
+        assert_eq!(
+            db.get(&event_key(new_event)).unwrap(),
+            Some(b"new".to_vec())
+        );
+        assert_eq!(db.oldest_event_id().unwrap(), Some(new_event));
+
+        // Without a retention, every event is kept
+        db.close().unwrap();
+        db.open().unwrap();
+        db.put(&event_key(old_event), b"old").unwrap();
+        db.compact();
+        assert_eq!(db.oldest_event_id().unwrap(), Some(old_event));
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_create_checkpoints() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let main_path = tmp_dir.path().join("main");
+        let trie_path = main_path.join("trieDb");
+        let backup_path = tmp_dir.path().join("backup");
+
+        let main_db = crate::db::RocksDB::new(main_path.to_str().unwrap()).unwrap();
+        main_db.open().unwrap();
+        let trie_db = crate::db::RocksDB::new(trie_path.to_str().unwrap()).unwrap();
+        trie_db.open().unwrap();
+
+        let user_key = vec![RootPrefix::User as u8, 0, 0, 0, 1, 1];
+        let trie_key = vec![RootPrefix::SyncMerkleTrieNode as u8, 1];
+        main_db.put(&user_key, b"user").unwrap();
+        main_db.put(b"key100", b"value1").unwrap();
+        trie_db.put(&trie_key, b"node").unwrap();
+
+        let main_backup_path = backup_path.to_str().unwrap().to_string();
+        let trie_backup_path = backup_path.join("trieDb").to_str().unwrap().to_string();
+        crate::db::RocksDB::create_checkpoints(
+            &main_db,
+            &trie_db,
+            &main_backup_path,
+            &trie_backup_path,
+        )
+        .unwrap();
+
+        // Writes after the checkpoint are not in the backup
+        main_db.put(b"key101", b"value2").unwrap();
+
+        let main_backup = crate::db::RocksDB::new(&main_backup_path).unwrap();
+        main_backup.open().unwrap();
+        assert_eq!(main_backup.get(&user_key).unwrap(), Some(b"user".to_vec()));
+        assert_eq!(
+            main_backup.get(b"key100").unwrap(),
+            Some(b"value1".to_vec())
+        );
+        assert_eq!(main_backup.get(b"key101").unwrap(), None);
+        assert_eq!(main_backup.get(&trie_key).unwrap(), None);
+
+        let trie_backup = crate::db::RocksDB::new(&trie_backup_path).unwrap();
+        trie_backup.open().unwrap();
+        assert_eq!(trie_backup.get(&trie_key).unwrap(), Some(b"node".to_vec()));
+        assert_eq!(trie_backup.count_keys_at_prefix(&[]).unwrap(), 1);
+
+        // Cleanup
+        main_backup.destroy().unwrap();
+        trie_backup.destroy().unwrap();
+        trie_db.destroy().unwrap();
+        main_db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_create_snapshot_progress_and_cancellation() {
+        use crate::db::{SnapshotHandle, SnapshotOptions, SnapshotPhase};
+        use std::sync::{Arc, Mutex};
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let main_path = tmp_dir.path().join("main");
+        let backup_path = tmp_dir.path().join("backup").join("rocks.hub._default");
+        let archive_path = tmp_dir.path().join("archive.tar.gz");
+        let logger = crate::logger::LOGGER.new(slog::o!());
+
+        let main_db = crate::db::RocksDB::new(main_path.to_str().unwrap()).unwrap();
+        main_db.open().unwrap();
+        main_db.put(b"key100", b"value1").unwrap();
+        // Overwritten and deleted keys aren't counted
+        main_db.put(b"key100", b"value2").unwrap();
+        main_db.put(b"key200", b"value1").unwrap();
+        main_db.del(b"key200").unwrap();
+
+        let reported = Arc::new(Mutex::new(vec![]));
+        let reported_clone = reported.clone();
+        let handle = Arc::new(SnapshotHandle::with_progress(move |progress| {
+            reported_clone.lock().unwrap().push(progress);
+        }));
+
+        crate::db::RocksDB::create_snapshot(
+            &logger,
+            &main_db,
+            &main_db,
+            backup_path.to_str().unwrap(),
+            backup_path.join("trieDb").to_str().unwrap(),
+            archive_path.to_str().unwrap(),
+            0,
+            &SnapshotOptions::default(),
+            &handle,
+        )
+        .unwrap();
+
+        let phases = reported
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p.phase)
+            .collect::<Vec<_>>();
+        assert_eq!(phases.first(), Some(&SnapshotPhase::Copy));
+        assert_eq!(phases.last(), Some(&SnapshotPhase::Tar));
+        assert!(reported
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|p| p.db == Some("main") && p.keys == 1));
+        let manifest =
+            crate::db::snapshot_manifest::SnapshotManifest::verify(&archive_path).unwrap();
+        assert_eq!(manifest.main_db_keys, 1);
+
+        // A cancelled handle stops the snapshot before anything is written
+        let handle = Arc::new(SnapshotHandle::new());
+        handle.cancel();
+        let other_backup_path = tmp_dir.path().join("backup2");
+        let err = crate::db::RocksDB::create_snapshot(
+            &logger,
+            &main_db,
+            &main_db,
+            other_backup_path.to_str().unwrap(),
+            other_backup_path.join("trieDb").to_str().unwrap(),
+            tmp_dir.path().join("archive2.tar.gz").to_str().unwrap(),
+            0,
+            &SnapshotOptions::default(),
+            &handle,
+        )
+        .unwrap_err();
+        assert_eq!(err.code, "db.snapshot_cancelled");
+        assert!(!other_backup_path.exists());
+
+        // Cleanup
+        main_db.destroy().unwrap();
+    }
+
+    #[test]
+    fn test_restore_snapshot() {
+        use crate::db::multi_chunk_writer::MultiChunkWriter;
+        use crate::trie::trie_node::TrieNode;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let main_path = tmp_dir.path().join("main");
+        let backup_path = tmp_dir.path().join("backup").join("rocks.hub._default");
+        let archive_path = tmp_dir.path().join("archive.tar.gz");
+        let target_path = tmp_dir.path().join("restored");
+
+        let main_db = crate::db::RocksDB::new(main_path.to_str().unwrap()).unwrap();
+        main_db.open().unwrap();
+        let trie_db = crate::db::RocksDB::new(main_path.join("trieDb").to_str().unwrap()).unwrap();
+        trie_db.open().unwrap();
+
+        let user_key = vec![RootPrefix::User as u8, 0, 0, 0, 1, 1];
+        let root_key = TrieNode::make_primary_key(&[], None);
+        main_db.put(&user_key, b"user").unwrap();
+        trie_db
+            .put(&root_key, &TrieNode::serialize(&TrieNode::new()))
+            .unwrap();
+
+        crate::db::RocksDB::create_checkpoints(
+            &main_db,
+            &trie_db,
+            backup_path.to_str().unwrap(),
+            backup_path.join("trieDb").to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Small chunks, so the restore has to read across chunk boundaries
+        let mut writer = MultiChunkWriter::new(archive_path.clone(), 64 * 1024);
+        let mut tar = tar::Builder::new(&mut writer);
+        tar.append_dir_all("rocks.hub._default", &backup_path)
+            .unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        writer.finish().unwrap();
+
+        // An existing DB at the target is replaced
+        let old_db = crate::db::RocksDB::new(target_path.to_str().unwrap()).unwrap();
+        old_db.open().unwrap();
+        old_db.put(b"old", b"value").unwrap();
+        old_db.close().unwrap();
+
+        let restored = crate::db::RocksDB::restore_snapshot(
+            archive_path.to_str().unwrap(),
+            target_path.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(restored, target_path.to_str().unwrap());
+        assert!(!tmp_dir.path().join("restored.restore-staging").exists());
+        assert!(!tmp_dir.path().join("restored.restore-old").exists());
+
+        let restored_db = crate::db::RocksDB::new(&restored).unwrap();
+        restored_db.open().unwrap();
+        assert_eq!(restored_db.get(&user_key).unwrap(), Some(b"user".to_vec()));
+        assert_eq!(restored_db.get(b"old").unwrap(), None);
+        restored_db.close().unwrap();
+
+        let restored_trie =
+            crate::db::RocksDB::new(target_path.join("trieDb").to_str().unwrap()).unwrap();
+        restored_trie.open().unwrap();
+        assert!(restored_trie.get(&root_key).unwrap().is_some());
+
+        // A snapshot without a trie root is rejected, and the target is left alone
+        trie_db.del(&root_key).unwrap();
+        std::fs::remove_dir_all(&backup_path).unwrap();
+        std::fs::remove_dir_all(&archive_path).unwrap();
+        crate::db::RocksDB::create_checkpoints(
+            &main_db,
+            &trie_db,
+            backup_path.to_str().unwrap(),
+            backup_path.join("trieDb").to_str().unwrap(),
+        )
+        .unwrap();
+        let mut writer = MultiChunkWriter::new(archive_path.clone(), 64 * 1024);
+        let mut tar = tar::Builder::new(&mut writer);
+        tar.append_dir_all("rocks.hub._default", &backup_path)
+            .unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        writer.finish().unwrap();
+
+        restored_trie.close().unwrap();
+        let result = crate::db::RocksDB::restore_snapshot(
+            archive_path.to_str().unwrap(),
+            target_path.to_str().unwrap(),
+        );
+        assert!(result.is_err());
+        assert!(target_path.join("trieDb").exists());
+        assert!(!tmp_dir.path().join("restored.restore-staging").exists());
+
+        // So is a corrupt archive, and the part of it that was unpacked is removed
+        let chunks =
+            crate::db::multi_chunk_reader::MultiChunkReader::list_chunks(&archive_path).unwrap();
+        std::fs::write(&chunks[chunks.len() - 1], [0xff; 100]).unwrap();
+        let result = crate::db::RocksDB::restore_snapshot(
+            archive_path.to_str().unwrap(),
+            target_path.to_str().unwrap(),
+        );
+        assert!(result.is_err());
+        assert!(target_path.join("trieDb").exists());
+        assert!(!tmp_dir.path().join("restored.restore-staging").exists());
+
+        // Cleanup
+        trie_db.destroy().unwrap();
+        main_db.destroy().unwrap();
+    }
+
+    /**
+     * Benchmark for scanning a fid's keys with and without the prefix bloom filters. Run with
+     * `cargo test --release bench_fid_prefix_scan -- --ignored --nocapture`
+     */
+    #[test]
+    #[ignore]
+    fn bench_fid_prefix_scan() {
+        use rand::Rng;
+
+        const FIDS: u32 = 50_000;
+        const KEYS: u32 = 1_000_000;
+        const SCANS: u32 = 20_000;
+
+        let tmp_path = tempfile::tempdir()
+            .unwrap()
+            .path()
+            .as_os_str()
+            .to_string_lossy()
+            .to_string();
+        let db = crate::db::RocksDB::new(&tmp_path).unwrap();
+
+        // Small memtables, so the keys are spread over many SST files
+        let options = RocksDbOptions {
+            write_buffer_size: Some(1024 * 1024),
+            ..Default::default()
+        };
+        db.open_with_options(&options).unwrap();
+
+        // Messages arrive in random fid order. Only even fids have any keys, so half the scans
+        // are for fids that aren't in the DB at all.
+        let mut rng = rand::thread_rng();
+        let mut txn = db.txn();
+        for _ in 0..KEYS {
+            let mut key = make_user_key(rng.gen_range(0..FIDS / 2) * 2);
+            key.push(UserPostfix::CastMessage as u8);
+            key.extend_from_slice(&rng.gen::<[u8; 24]>());
+            txn.put(key, rng.gen::<[u8; 32]>().to_vec());
+
+            if txn.len() >= 10_000 {
+                db.commit(txn).unwrap();
+                txn = db.txn();
+            }
+        }
+        db.commit(txn).unwrap();
+
+        let fids = (0..SCANS)
+            .map(|_| rng.gen_range(0..FIDS))
+            .collect::<Vec<_>>();
+
+        // With the prefix bloom filters
+        let start = std::time::Instant::now();
+        let mut prefix_count = 0;
+        for fid in fids.iter() {
+            prefix_count += db.count_keys_at_prefix(&make_user_key(*fid)).unwrap();
+        }
+        let prefix_time = start.elapsed();
+
+        // Without, which is how every scan worked before the prefix extractor
+        let start = std::time::Instant::now();
+        let mut total_order_count = 0;
+        {
+            let raw_db = db.db();
+            let raw_db = raw_db.as_ref().unwrap();
+            let user_cf = raw_db.cf_handle(DbColumnFamily::User.name()).unwrap();
+            for fid in fids.iter() {
+                let prefix = make_user_key(*fid);
+                let mut opts = crate::db::RocksDB::total_order_read_options();
+                opts.set_iterate_lower_bound(prefix.clone());
+                opts.set_iterate_upper_bound(increment_vec_u8(&prefix));
+
+                let mut iter = raw_db.raw_iterator_cf_opt(&user_cf, opts);
+                iter.seek_to_first();
+                while iter.valid() {
+                    total_order_count += 1;
+                    iter.next();
+                }
+            }
+        }
+        let total_order_time = start.elapsed();
+
+        assert_eq!(prefix_count, total_order_count);
+        println!(
+            "{} fid scans ({} keys): prefix bloom = {:?}, total order = {:?}, speedup = {:.2}x",
+            SCANS,
+            prefix_count,
+            prefix_time,
+            total_order_time,
+            total_order_time.as_secs_f64() / prefix_time.as_secs_f64()
+        );
+
+        // Cleanup
+        db.destroy().unwrap();
+    }
 }
@@ -0,0 +1,206 @@
+use crate::db::multi_chunk_reader::MultiChunkReader;
+use crate::store::HubError;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+/** Version of the manifest format itself, bump it when fields change meaning */
+const MANIFEST_VERSION: u32 = 1;
+
+pub const SNAPSHOT_MANIFEST_FILE: &str = "manifest.json";
+
+/** Size and blake3 hash of a single chunk_XXXX.bin file */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotChunk {
+    pub name: String,
+    pub size: u64,
+    pub blake3: String,
+}
+
+/**
+ * The manifest.json written next to the chunks of a snapshot. It describes what is in the
+ * snapshot and lets us check every chunk before we start restoring from it.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotManifest {
+    pub version: u32,
+    pub timestamp: i64,
+    pub db_schema_version: u32,
+    /** The FarcasterNetwork stored in the main DB, if it was set */
+    pub network: Option<u32>,
+    /** Hex encoded hash of the sync trie root */
+    pub trie_root_hash: String,
+    /** The number of keys in the main and trie DBs, counted from the checkpoints */
+    pub main_db_keys: u64,
+    pub trie_db_keys: u64,
+    pub chunks: Vec<SnapshotChunk>,
+}
+
+impl SnapshotManifest {
+    /** A manifest without any chunks, call add_chunks once the chunks have been written */
+    pub fn new(
+        timestamp: i64,
+        db_schema_version: u32,
+        network: Option<u32>,
+        trie_root_hash: String,
+        main_db_keys: u64,
+        trie_db_keys: u64,
+    ) -> Self {
+        SnapshotManifest {
+            version: MANIFEST_VERSION,
+            timestamp,
+            db_schema_version,
+            network,
+            trie_root_hash,
+            main_db_keys,
+            trie_db_keys,
+            chunks: vec![],
+        }
+    }
+
+    /** Hash all the chunks in `dir` and add them to the manifest */
+    pub fn add_chunks(&mut self, dir: &Path) -> Result<(), HubError> {
+        for path in MultiChunkReader::list_chunks(dir)? {
+            self.chunks.push(Self::hash_chunk(&path)?);
+        }
+        Ok(())
+    }
+
+    fn hash_chunk(path: &Path) -> Result<SnapshotChunk, HubError> {
+        let mut hasher = blake3::Hasher::new();
+        let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+
+        Ok(SnapshotChunk {
+            name: path
+                .file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size,
+            blake3: hasher.finalize().to_hex().to_string(),
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, HubError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| HubError::internal_db_error(&format!("invalid manifest: {}", e)))
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), HubError> {
+        fs::write(dir.join(SNAPSHOT_MANIFEST_FILE), self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn read(dir: &Path) -> Result<Self, HubError> {
+        let manifest = fs::read(dir.join(SNAPSHOT_MANIFEST_FILE))?;
+        serde_json::from_slice(&manifest)
+            .map_err(|e| HubError::validation_failure(&format!("invalid snapshot manifest: {}", e)))
+    }
+
+    /**
+     * Check every chunk in `dir` against the manifest. Missing, extra, truncated or corrupted
+     * chunks all fail the check. Returns the manifest if the snapshot is good.
+     */
+    pub fn verify(dir: &Path) -> Result<Self, HubError> {
+        let manifest = Self::read(dir)?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(HubError::validation_failure(&format!(
+                "unsupported snapshot manifest version: {}",
+                manifest.version
+            )));
+        }
+
+        let chunks = MultiChunkReader::list_chunks(dir)?;
+        if chunks.len() != manifest.chunks.len() {
+            return Err(HubError::validation_failure(&format!(
+                "snapshot has {} chunks, manifest lists {}",
+                chunks.len(),
+                manifest.chunks.len()
+            )));
+        }
+
+        for (path, expected) in chunks.iter().zip(manifest.chunks.iter()) {
+            // Check the size first, it catches truncated chunks without reading them
+            let size = fs::metadata(path)?.len();
+            let name = path.file_name().map(|s| s.to_string_lossy().to_string());
+            if name.as_deref() != Some(expected.name.as_str()) || size != expected.size {
+                return Err(HubError::validation_failure(&format!(
+                    "snapshot chunk {} does not match the manifest",
+                    expected.name
+                )));
+            }
+
+            if Self::hash_chunk(path)? != *expected {
+                return Err(HubError::validation_failure(&format!(
+                    "snapshot chunk {} is corrupted",
+                    expected.name
+                )));
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::multi_chunk_writer::MultiChunkWriter;
+    use std::io::Write as _;
+    use tempfile::TempDir;
+
+    fn write_snapshot(dir: &Path) -> SnapshotManifest {
+        let mut writer = MultiChunkWriter::new(dir.to_path_buf(), 5);
+        writer.write_all(b"12345").unwrap();
+        writer.write_all(b"67890").unwrap();
+        writer.finish().unwrap();
+
+        let mut manifest = SnapshotManifest::new(1, 4, Some(1), "00ff".to_string(), 10, 2);
+        manifest.add_chunks(dir).unwrap();
+        manifest.write(dir).unwrap();
+        manifest
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest = write_snapshot(temp_dir.path());
+
+        assert_eq!(manifest.chunks.len(), 2);
+        assert_eq!(manifest.chunks[0].name, "chunk_0001.bin");
+        assert_eq!(SnapshotManifest::read(temp_dir.path()).unwrap(), manifest);
+        assert_eq!(SnapshotManifest::verify(temp_dir.path()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_verify_detects_bad_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        write_snapshot(temp_dir.path());
+        let chunk = temp_dir.path().join("chunk_0002.bin");
+        let contents = fs::read(&chunk).unwrap();
+
+        // Same size, different contents
+        let mut corrupted = contents.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        fs::write(&chunk, &corrupted).unwrap();
+        let err = SnapshotManifest::verify(temp_dir.path()).unwrap_err();
+        assert_eq!(err.code, "bad_request.validation_failure");
+
+        // Truncated
+        fs::write(&chunk, &contents[..contents.len() - 1]).unwrap();
+        assert!(SnapshotManifest::verify(temp_dir.path()).is_err());
+
+        // Missing
+        fs::remove_file(&chunk).unwrap();
+        assert!(SnapshotManifest::verify(temp_dir.path()).is_err());
+
+        // Extra
+        fs::write(&chunk, &contents).unwrap();
+        assert!(SnapshotManifest::verify(temp_dir.path()).is_ok());
+        fs::write(temp_dir.path().join("chunk_0003.bin"), &contents).unwrap();
+        assert!(SnapshotManifest::verify(temp_dir.path()).is_err());
+    }
+}
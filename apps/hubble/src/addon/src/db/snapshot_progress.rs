@@ -0,0 +1,227 @@
+use crate::store::HubError;
+use neon::context::{Context, FunctionContext};
+use neon::handle::{Handle, Root};
+use neon::object::Object;
+use neon::result::JsResult;
+use neon::types::{Finalize, JsBox, JsFunction, JsObject, JsUndefined};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/** The phases of a snapshot backup, in the order they run */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotPhase {
+    /** Checkpointing the DBs and counting their keys */
+    Copy,
+    /** Writing the chunked tar.gz archive and its manifest */
+    Tar,
+    /** Removing the checkpoints */
+    Cleanup,
+}
+
+impl SnapshotPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SnapshotPhase::Copy => "copy",
+            SnapshotPhase::Tar => "tar",
+            SnapshotPhase::Cleanup => "cleanup",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotProgress {
+    pub phase: SnapshotPhase,
+    /** The DB whose keys are being counted in the copy phase, "main" or "trie" */
+    pub db: Option<&'static str>,
+    /** Keys seen so far in `db` */
+    pub keys: u64,
+    /** The chunk being written in the tar phase, starting at 1 */
+    pub chunk: usize,
+    /** Uncompressed bytes written to `chunk` so far */
+    pub chunk_bytes: u64,
+}
+
+impl SnapshotProgress {
+    pub fn phase(phase: SnapshotPhase) -> Self {
+        SnapshotProgress {
+            phase,
+            db: None,
+            keys: 0,
+            chunk: 0,
+            chunk_bytes: 0,
+        }
+    }
+
+    pub fn keys(db: &'static str, keys: u64) -> Self {
+        SnapshotProgress {
+            db: Some(db),
+            keys,
+            ..Self::phase(SnapshotPhase::Copy)
+        }
+    }
+
+    pub fn chunk(chunk: usize, chunk_bytes: u64) -> Self {
+        SnapshotProgress {
+            chunk,
+            chunk_bytes,
+            ..Self::phase(SnapshotPhase::Tar)
+        }
+    }
+
+    fn to_js_object<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsObject> {
+        let obj = cx.empty_object();
+
+        let phase = cx.string(self.phase.as_str());
+        obj.set(cx, "phase", phase)?;
+        if let Some(db) = self.db {
+            let db = cx.string(db);
+            obj.set(cx, "db", db)?;
+        }
+        let keys = cx.number(self.keys as f64);
+        obj.set(cx, "keys", keys)?;
+        let chunk = cx.number(self.chunk as f64);
+        obj.set(cx, "chunk", chunk)?;
+        let chunk_bytes = cx.number(self.chunk_bytes as f64);
+        obj.set(cx, "chunkBytes", chunk_bytes)?;
+
+        Ok(obj)
+    }
+}
+
+type ProgressCallback = Box<dyn Fn(SnapshotProgress) + Send>;
+
+/**
+ * Shared between a running snapshot backup and whoever started it. The backup reports its
+ * progress through the handle, and stops at the next check after cancel() is called.
+ */
+pub struct SnapshotHandle {
+    cancelled: AtomicBool,
+    on_progress: Mutex<Option<ProgressCallback>>,
+}
+
+/** Needed so the handle can be passed to JS in a JsBox */
+impl Finalize for SnapshotHandle {}
+
+impl Default for SnapshotHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotHandle {
+    pub fn new() -> Self {
+        SnapshotHandle {
+            cancelled: AtomicBool::new(false),
+            on_progress: Mutex::new(None),
+        }
+    }
+
+    pub fn with_progress<F>(on_progress: F) -> Self
+    where
+        F: Fn(SnapshotProgress) + Send + 'static,
+    {
+        SnapshotHandle {
+            cancelled: AtomicBool::new(false),
+            on_progress: Mutex::new(Some(Box::new(on_progress))),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn check_cancelled(&self) -> Result<(), HubError> {
+        if self.is_cancelled() {
+            return Err(HubError {
+                code: "db.snapshot_cancelled".to_string(),
+                message: "snapshot backup was cancelled".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn report(&self, progress: SnapshotProgress) {
+        if let Some(on_progress) = self.on_progress.lock().unwrap().as_ref() {
+            on_progress(progress);
+        }
+    }
+}
+
+impl SnapshotHandle {
+    /**
+     * Create a handle for dbSnapshotBackup. The optional callback is called on the JS thread
+     * with a { phase, db, keys, chunk, chunkBytes } object as the backup progresses.
+     */
+    pub fn js_create_snapshot_handle(
+        mut cx: FunctionContext,
+    ) -> JsResult<JsBox<Arc<SnapshotHandle>>> {
+        let callback = match cx.argument_opt(0) {
+            Some(arg) if arg.is_a::<JsFunction, _>(&mut cx) => Some(Arc::new(
+                arg.downcast_or_throw::<JsFunction, _>(&mut cx)?
+                    .root(&mut cx),
+            )),
+            _ => None,
+        };
+
+        let handle = match callback {
+            Some(callback) => {
+                let channel = cx.channel();
+                SnapshotHandle::with_progress(move |progress| {
+                    let callback: Arc<Root<JsFunction>> = callback.clone();
+                    channel.send(move |mut cx| {
+                        let obj = progress.to_js_object(&mut cx)?;
+                        let this = cx.undefined();
+                        let callback: Handle<JsFunction> = callback.to_inner(&mut cx);
+                        callback.call(&mut cx, this, [obj.upcast()])?;
+                        Ok(())
+                    });
+                })
+            }
+            None => SnapshotHandle::new(),
+        };
+
+        Ok(cx.boxed(Arc::new(handle)))
+    }
+
+    pub fn js_cancel_snapshot(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let handle = cx.argument::<JsBox<Arc<SnapshotHandle>>>(0)?;
+        handle.cancel();
+
+        Ok(cx.undefined())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_handle() {
+        let reported = Arc::new(Mutex::new(vec![]));
+        let reported_clone = reported.clone();
+        let handle = SnapshotHandle::with_progress(move |progress| {
+            reported_clone.lock().unwrap().push(progress);
+        });
+
+        handle.report(SnapshotProgress::keys("main", 10));
+        handle.report(SnapshotProgress::chunk(1, 1024));
+        assert_eq!(
+            *reported.lock().unwrap(),
+            vec![
+                SnapshotProgress::keys("main", 10),
+                SnapshotProgress::chunk(1, 1024)
+            ]
+        );
+
+        assert!(handle.check_cancelled().is_ok());
+        handle.cancel();
+        assert_eq!(
+            handle.check_cancelled().unwrap_err().code,
+            "db.snapshot_cancelled"
+        );
+    }
+}
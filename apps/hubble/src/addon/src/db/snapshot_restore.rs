@@ -0,0 +1,213 @@
+use crate::store::HubError;
+use slog::{warn, Logger};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/**
+ * The directories a snapshot restore works in, next to the DB it replaces. The restored DB is
+ * unpacked into `staging`, and swapped into place by moving the old DB to `old` and the restored
+ * one to `target`. `swap_marker` exists for as long as the swap runs, so an interrupted swap can
+ * be told apart from a finished one.
+ */
+pub struct RestorePaths {
+    pub target: PathBuf,
+    pub staging: PathBuf,
+    pub old: PathBuf,
+    pub swap_marker: PathBuf,
+}
+
+impl RestorePaths {
+    pub fn new(target: &Path) -> Result<Self, HubError> {
+        let target_name = target
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .ok_or_else(|| HubError::invalid_parameter("invalid restore target path"))?;
+
+        Ok(RestorePaths {
+            target: target.to_path_buf(),
+            staging: target.with_file_name(format!("{}.restore-staging", target_name)),
+            old: target.with_file_name(format!("{}.restore-old", target_name)),
+            swap_marker: target.with_file_name(format!("{}.restore-swap", target_name)),
+        })
+    }
+
+    /**
+     * Clean up after a swap that didn't finish, before anything opens or replaces the DB at
+     * `target`. The old DB is only deleted once the restored one is known to be in place:
+     *  - if there is no DB at `target`, the swap was interrupted halfway and the old DB is moved
+     *    back
+     *  - if both are there and the marker is gone, the swap finished and the old DB is deleted
+     *  - if both are there and the marker isn't, the DB at `target` may not be the restored one,
+     *    so this fails and leaves the old DB for an operator to look at
+     */
+    pub fn recover(&self, logger: &Logger) -> Result<(), HubError> {
+        if self.old.exists() {
+            if !self.target.exists() {
+                warn!(logger, "Moving the DB back after an interrupted snapshot restore";
+                    "path" => self.target.display().to_string());
+                fs::rename(&self.old, &self.target)?;
+            } else if self.swap_marker.exists() {
+                return Err(HubError::internal_db_error(&format!(
+                    "an interrupted snapshot restore left the previous DB at {}, move it back \
+                     to {} or delete it",
+                    self.old.display(),
+                    self.target.display()
+                )));
+            } else {
+                fs::remove_dir_all(&self.old)?;
+            }
+        }
+
+        if self.swap_marker.exists() {
+            fs::remove_file(&self.swap_marker)?;
+        }
+        Ok(())
+    }
+
+    /** Move the DB at `restored` to `target`, replacing the DB that is there */
+    pub fn swap(&self, restored: &Path) -> Result<(), HubError> {
+        File::create(&self.swap_marker)?.sync_all()?;
+        self.sync_parent()?;
+
+        if self.target.exists() {
+            fs::rename(&self.target, &self.old)?;
+        }
+        if let Err(e) = fs::rename(restored, &self.target) {
+            // Put the old DB back so we don't leave the hub without one
+            if self.old.exists() {
+                fs::rename(&self.old, &self.target)?;
+            }
+            fs::remove_file(&self.swap_marker)?;
+            return Err(e.into());
+        }
+        self.sync_parent()?;
+
+        fs::remove_file(&self.swap_marker)?;
+        if self.old.exists() {
+            fs::remove_dir_all(&self.old)?;
+        }
+        Ok(())
+    }
+
+    /** Make the renames in the directory of `target` durable */
+    fn sync_parent(&self) -> Result<(), HubError> {
+        if let Some(parent) = self.target.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The directory a snapshot is unpacked into. It is removed when this is dropped, so a restore
+ * that fails halfway doesn't leave a partial copy of the DB behind.
+ */
+pub struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    /** Create an empty directory at `path`, removing anything an earlier restore left there */
+    pub fn create(path: &Path) -> Result<Self, HubError> {
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+        fs::create_dir_all(path)?;
+        Ok(StagingDir {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::LOGGER;
+
+    fn write_db(path: &Path, contents: &str) {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join("CURRENT"), contents).unwrap();
+    }
+
+    fn read_db(path: &Path) -> String {
+        fs::read_to_string(path.join("CURRENT")).unwrap()
+    }
+
+    #[test]
+    fn test_swap() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let paths = RestorePaths::new(&tmp_dir.path().join("db")).unwrap();
+        write_db(&paths.target, "old");
+
+        let staging = StagingDir::create(&paths.staging).unwrap();
+        let restored = staging.path().join("rocks.hub._default");
+        write_db(&restored, "restored");
+        paths.swap(&restored).unwrap();
+        drop(staging);
+
+        assert_eq!(read_db(&paths.target), "restored");
+        assert!(!paths.staging.exists());
+        assert!(!paths.old.exists());
+        assert!(!paths.swap_marker.exists());
+
+        // A failed swap leaves the DB that was there
+        assert!(paths.swap(&tmp_dir.path().join("missing")).is_err());
+        assert_eq!(read_db(&paths.target), "restored");
+        assert!(!paths.swap_marker.exists());
+    }
+
+    #[test]
+    fn test_recover() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let paths = RestorePaths::new(&tmp_dir.path().join("db")).unwrap();
+
+        // Nothing to do
+        paths.recover(&LOGGER).unwrap();
+        assert!(!paths.target.exists());
+
+        // Interrupted after the old DB was moved away, it is moved back
+        write_db(&paths.old, "old");
+        File::create(&paths.swap_marker).unwrap();
+        paths.recover(&LOGGER).unwrap();
+        assert_eq!(read_db(&paths.target), "old");
+        assert!(!paths.old.exists());
+        assert!(!paths.swap_marker.exists());
+
+        // Interrupted while there is a DB at the target that may not be the restored one, the
+        // old DB is kept
+        write_db(&paths.old, "old");
+        File::create(&paths.swap_marker).unwrap();
+        assert!(paths.recover(&LOGGER).is_err());
+        assert_eq!(read_db(&paths.old), "old");
+
+        // Interrupted after the swap finished, the old DB is deleted
+        fs::remove_file(&paths.swap_marker).unwrap();
+        paths.recover(&LOGGER).unwrap();
+        assert!(!paths.old.exists());
+        assert_eq!(read_db(&paths.target), "old");
+    }
+
+    #[test]
+    fn test_staging_dir() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("db.restore-staging");
+        write_db(&path.join("leftover"), "leftover");
+
+        let staging = StagingDir::create(&path).unwrap();
+        assert_eq!(fs::read_dir(staging.path()).unwrap().count(), 0);
+        write_db(&path.join("restored"), "restored");
+
+        drop(staging);
+        assert!(!path.exists());
+    }
+}
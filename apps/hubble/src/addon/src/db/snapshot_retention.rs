@@ -0,0 +1,287 @@
+use crate::db::SnapshotRetention;
+use crate::store::HubError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+/** Timestamps (in seconds) of the snapshots that are being written right now */
+static ACTIVE_SNAPSHOTS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+const ARCHIVE_EXTENSIONS: [&str; 3] = [".tar.gz", ".tar.zst", ".tar"];
+const CHECKPOINT_EXTENSION: &str = ".backup";
+
+/**
+ * Marks a snapshot as being written until it is dropped. gc_snapshots never deletes the archive
+ * or the checkpoint of an active snapshot.
+ */
+pub struct ActiveSnapshot {
+    timestamp: i64,
+}
+
+impl ActiveSnapshot {
+    pub fn new(timestamp: i64) -> Self {
+        ACTIVE_SNAPSHOTS.lock().unwrap().push(timestamp);
+        ActiveSnapshot { timestamp }
+    }
+}
+
+impl Drop for ActiveSnapshot {
+    fn drop(&mut self) {
+        let mut active = ACTIVE_SNAPSHOTS.lock().unwrap();
+        if let Some(index) = active.iter().position(|t| *t == self.timestamp) {
+            active.swap_remove(index);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub path: PathBuf,
+    /** Seconds since the unix epoch, parsed from the directory name */
+    pub timestamp: i64,
+    pub size: u64,
+}
+
+/**
+ * Parse the timestamp out of a snapshot directory name. Both the archives
+ * (`<name>-%Y-%m-%d-%s.tar.gz`) and the checkpoints (`%Y-%m-%d-%s.backup`) end with the date
+ * followed by the unix timestamp in seconds.
+ */
+fn parse_timestamp(name: &str, extensions: &[&str]) -> Option<i64> {
+    let stem = extensions
+        .iter()
+        .find_map(|extension| name.strip_suffix(extension))?;
+
+    let mut parts = stem.rsplitn(5, '-');
+    let timestamp = parts.next()?.parse::<i64>().ok()?;
+    let date = [parts.next()?, parts.next()?, parts.next()?];
+    if date
+        .iter()
+        .any(|part| part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return None;
+    }
+
+    Some(timestamp)
+}
+
+fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/** The snapshot directories in `dir` whose names end with one of `extensions`, newest first */
+fn list_snapshots(dir: &Path, extensions: &[&str]) -> Result<Vec<SnapshotEntry>, HubError> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let timestamp = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| parse_timestamp(name, extensions));
+        if let Some(timestamp) = timestamp {
+            let size = dir_size(&path);
+            entries.push(SnapshotEntry {
+                path,
+                timestamp,
+                size,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.path.cmp(&a.path)));
+    Ok(entries)
+}
+
+/** The archives that `retention` doesn't keep, `archives` must be sorted newest first */
+fn expired_archives(
+    archives: Vec<SnapshotEntry>,
+    retention: &SnapshotRetention,
+    now: i64,
+) -> Vec<SnapshotEntry> {
+    let mut kept = 0;
+    let mut kept_bytes = 0;
+
+    archives
+        .into_iter()
+        .filter(|archive| {
+            let too_many = retention.keep_last.map_or(false, |n| kept >= n);
+            let too_old = retention.max_age_ms.map_or(false, |max_age| {
+                now - archive.timestamp > (max_age / 1000) as i64
+            });
+            let too_big = retention
+                .max_bytes
+                .map_or(false, |max_bytes| kept_bytes + archive.size > max_bytes);
+
+            let expired = too_many || too_old || too_big;
+            if !expired {
+                kept += 1;
+                kept_bytes += archive.size;
+            }
+            expired
+        })
+        .collect()
+}
+
+/**
+ * Delete the snapshot archives in `archive_dir` that `retention` doesn't keep, and any leftover
+ * checkpoints in `backup_dir`. Snapshots that are being written are skipped, and don't count
+ * towards the limits. `now` is in seconds. Returns the paths that were deleted.
+ */
+pub fn gc_snapshots(
+    archive_dir: &Path,
+    backup_dir: &Path,
+    retention: &SnapshotRetention,
+    now: i64,
+) -> Result<Vec<String>, HubError> {
+    let is_active =
+        |entry: &SnapshotEntry| ACTIVE_SNAPSHOTS.lock().unwrap().contains(&entry.timestamp);
+
+    let archives = list_snapshots(archive_dir, &ARCHIVE_EXTENSIONS)?
+        .into_iter()
+        .filter(|archive| !is_active(archive))
+        .collect();
+    let mut expired = expired_archives(archives, retention, now);
+
+    // Checkpoints are only needed while their snapshot is being written
+    expired.extend(list_snapshots(backup_dir, &[CHECKPOINT_EXTENSION])?);
+
+    let mut deleted = vec![];
+    for entry in expired {
+        // Hold the lock while deleting, so a snapshot with the same timestamp can't start
+        let active = ACTIVE_SNAPSHOTS.lock().unwrap();
+        if active.contains(&entry.timestamp) {
+            continue;
+        }
+        fs::remove_dir_all(&entry.path)?;
+        drop(active);
+
+        deleted.push(entry.path.to_string_lossy().to_string());
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn make_snapshot(dir: &Path, name: &str, size: usize) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("chunk_0001.bin"), vec![0u8; size]).unwrap();
+        path
+    }
+
+    fn archive_name(timestamp: i64) -> String {
+        let date = chrono::NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap();
+        format!("rocks.hub._default-{}.tar.gz", date.format("%Y-%m-%d-%s"))
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp(
+                "rocks.hub._default-2024-03-01-1709251200.tar.gz",
+                &ARCHIVE_EXTENSIONS
+            ),
+            Some(1709251200)
+        );
+        assert_eq!(
+            parse_timestamp("rocks-hub-2024-03-01-1709251200.tar", &ARCHIVE_EXTENSIONS),
+            Some(1709251200)
+        );
+        assert_eq!(
+            parse_timestamp("2024-03-01-1709251200.backup", &[CHECKPOINT_EXTENSION]),
+            Some(1709251200)
+        );
+
+        assert_eq!(
+            parse_timestamp("rocks.hub._default", &ARCHIVE_EXTENSIONS),
+            None
+        );
+        assert_eq!(
+            parse_timestamp("rocks.hub._default-latest.tar.gz", &ARCHIVE_EXTENSIONS),
+            None
+        );
+        assert_eq!(
+            parse_timestamp("2024-03-01-1709251200.backup", &ARCHIVE_EXTENSIONS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_gc_snapshots() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive_dir = temp_dir.path().join(".rocks");
+        let backup_dir = temp_dir.path().join("backup");
+
+        let now = 1_709_251_200;
+        let archives = (0..5)
+            .map(|day| make_snapshot(&archive_dir, &archive_name(now - day * DAY), 100))
+            .collect::<Vec<_>>();
+
+        // Not snapshots, these must never be touched
+        let db = make_snapshot(&archive_dir, "rocks.hub._default", 1000);
+        let other = make_snapshot(&archive_dir, "rocks.hub._default-latest.tar.gz", 100);
+
+        // Nothing but leftover checkpoints is deleted without any limits
+        let checkpoint = make_snapshot(&backup_dir, "2024-02-01-1706745600.backup", 10);
+        let deleted = gc_snapshots(
+            &archive_dir,
+            &backup_dir,
+            &SnapshotRetention::default(),
+            now,
+        )
+        .unwrap();
+        assert_eq!(deleted, vec![checkpoint.to_string_lossy().to_string()]);
+        assert!(archives.iter().all(|archive| archive.exists()));
+
+        // Keep the snapshots from the last 3 days, but at most 250 bytes of them
+        let retention = SnapshotRetention {
+            max_age_ms: Some(3 * DAY as u64 * 1000),
+            max_bytes: Some(250),
+            ..Default::default()
+        };
+        let deleted = gc_snapshots(&archive_dir, &backup_dir, &retention, now).unwrap();
+        assert_eq!(deleted.len(), 3);
+        assert!(archives[0].exists());
+        assert!(archives[1].exists());
+        assert!(!archives[2].exists());
+        assert!(!archives[4].exists());
+        assert!(db.exists());
+        assert!(other.exists());
+
+        // The newest snapshot is still being written, so it's neither deleted nor counted
+        let active = ActiveSnapshot::new(now);
+        let retention = SnapshotRetention {
+            keep_last: Some(1),
+            ..Default::default()
+        };
+        let deleted = gc_snapshots(&archive_dir, &backup_dir, &retention, now).unwrap();
+        assert!(deleted.is_empty());
+        assert!(archives[0].exists());
+        assert!(archives[1].exists());
+
+        drop(active);
+        let deleted = gc_snapshots(&archive_dir, &backup_dir, &retention, now).unwrap();
+        assert_eq!(deleted, vec![archives[1].to_string_lossy().to_string()]);
+        assert!(archives[0].exists());
+    }
+}
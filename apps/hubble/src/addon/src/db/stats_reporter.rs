@@ -0,0 +1,143 @@
+use crate::db::column_families::{DbColumnFamily, ALL_COLUMN_FAMILIES};
+use crate::db::RocksDB;
+use crate::statsd::statsd;
+use rocksdb::properties::{self, PropName};
+use rocksdb::statistics::Ticker;
+use rocksdb::{Options, DB};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/** How often the RocksDB stats are reported, unless configured otherwise */
+pub const DEFAULT_STATS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/** The metric prefix, unless configured otherwise */
+pub const DEFAULT_STATS_PREFIX: &str = "rust.db";
+
+/** Properties that are reported for each column family */
+const CF_PROPERTIES: [(&PropName, &str); 5] = [
+    (properties::ESTIMATE_NUM_KEYS, "estimate_num_keys"),
+    (properties::LIVE_SST_FILES_SIZE, "live_sst_files_size"),
+    (
+        properties::ESTIMATE_PENDING_COMPACTION_BYTES,
+        "pending_compaction_bytes",
+    ),
+    (
+        properties::CUR_SIZE_ALL_MEM_TABLES,
+        "cur_size_all_mem_tables",
+    ),
+    (properties::SIZE_ALL_MEM_TABLES, "size_all_mem_tables"),
+];
+
+/** Properties that are reported once for the whole DB */
+const DB_PROPERTIES: [(&PropName, &str); 4] = [
+    (properties::BLOCK_CACHE_USAGE, "block_cache_usage"),
+    (
+        properties::NUM_RUNNING_COMPACTIONS,
+        "num_running_compactions",
+    ),
+    (
+        properties::ACTUAL_DELAYED_WRITE_RATE,
+        "actual_delayed_write_rate",
+    ),
+    (properties::IS_WRITE_STOPPED, "is_write_stopped"),
+];
+
+/**
+ * Reads the RocksDB properties, and the statistics if they are enabled, into gauges. The
+ * statistics are cumulative, so they are reported as the change since the previous report.
+ */
+#[derive(Default)]
+pub struct StatsCollector {
+    prefix: String,
+    last_cache_hits: u64,
+    last_cache_misses: u64,
+    last_stall_micros: u64,
+}
+
+impl StatsCollector {
+    pub fn new(prefix: &str) -> Self {
+        StatsCollector {
+            prefix: prefix.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /** The gauges for `db`. `statistics` are the options the DB was opened with, if it has them */
+    pub fn collect(&mut self, db: &DB, statistics: Option<&Options>) -> Vec<(String, u64)> {
+        let mut gauges = vec![];
+
+        let column_families = ALL_COLUMN_FAMILIES
+            .iter()
+            .chain(std::iter::once(&DbColumnFamily::KeyCounters));
+        for cf in column_families {
+            let handle = match db.cf_handle(cf.name()) {
+                Some(handle) => handle,
+                None => continue,
+            };
+            for (property, name) in CF_PROPERTIES {
+                if let Ok(Some(value)) = db.property_int_value_cf(&handle, property) {
+                    gauges.push((format!("{}.{}.{}", self.prefix, cf.name(), name), value));
+                }
+            }
+        }
+
+        for (property, name) in DB_PROPERTIES {
+            if let Ok(Some(value)) = db.property_int_value(property) {
+                gauges.push((format!("{}.{}", self.prefix, name), value));
+            }
+        }
+
+        if let Some(statistics) = statistics {
+            let hits = statistics.get_ticker_count(Ticker::BlockCacheHit);
+            let misses = statistics.get_ticker_count(Ticker::BlockCacheMiss);
+            let stall_micros = statistics.get_ticker_count(Ticker::StallMicros);
+
+            let new_hits = hits.saturating_sub(self.last_cache_hits);
+            let new_misses = misses.saturating_sub(self.last_cache_misses);
+            if new_hits + new_misses > 0 {
+                let hit_rate = new_hits * 100 / (new_hits + new_misses);
+                gauges.push((format!("{}.block_cache_hit_rate", self.prefix), hit_rate));
+            }
+            gauges.push((
+                format!("{}.stall_micros", self.prefix),
+                stall_micros.saturating_sub(self.last_stall_micros),
+            ));
+
+            self.last_cache_hits = hits;
+            self.last_cache_misses = misses;
+            self.last_stall_micros = stall_micros;
+        }
+
+        gauges
+    }
+}
+
+/**
+ * Report the stats of `db` every `interval` until `stop` is set or the DB is dropped. Nothing is
+ * reported while the DB is closed.
+ */
+pub fn spawn_stats_reporter(
+    db: Weak<RocksDB>,
+    interval: Duration,
+    prefix: String,
+    stop: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        let mut collector = StatsCollector::new(&prefix);
+        loop {
+            std::thread::sleep(interval);
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let db = match db.upgrade() {
+                Some(db) => db,
+                None => break,
+            };
+
+            for (metric, value) in db.collect_stats(&mut collector) {
+                statsd().gauge(&metric, value);
+            }
+        }
+    });
+}
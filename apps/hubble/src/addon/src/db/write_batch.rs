@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+/**
+ * A list of puts (Some) and deletes (None) that are written together, in the order they were
+ * added. Unlike RocksDbTransactionBatch, writing the same key twice keeps both operations, and
+ * the last one wins when the batch is written. Use RocksDB::commit_ordered to write it.
+ */
+#[derive(Default)]
+pub struct OrderedWriteBatch {
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    byte_size: usize,
+    // How many times each key was written. Only kept in debug builds, where it's used to report
+    // the keys that were overwritten within the batch.
+    #[cfg(debug_assertions)]
+    writes: HashMap<Vec<u8>, usize>,
+}
+
+impl OrderedWriteBatch {
+    pub fn new() -> OrderedWriteBatch {
+        OrderedWriteBatch::default()
+    }
+
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.push(key, Some(value));
+    }
+
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.push(key, None);
+    }
+
+    fn push(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.byte_size += key.len() + value.as_ref().map_or(0, |value| value.len());
+
+        #[cfg(debug_assertions)]
+        {
+            *self.writes.entry(key.clone()).or_insert(0) += 1;
+        }
+
+        self.ops.push((key, value));
+    }
+
+    /** The number of operations, including the ones that write the same key again */
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /** The total size of all the keys and values in the batch */
+    pub fn byte_size(&self) -> usize {
+        self.byte_size
+    }
+
+    /** The operations, in the order they were added */
+    pub fn ops(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> {
+        self.ops
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.as_deref()))
+    }
+
+    /** Whether each key that the batch writes ends up put (true) or deleted (false) */
+    pub fn final_ops(&self) -> HashMap<&[u8], bool> {
+        self.ops
+            .iter()
+            .map(|(key, value)| (key.as_slice(), value.is_some()))
+            .collect()
+    }
+
+    /** The keys that were written more than once, with the number of times they were written */
+    #[cfg(debug_assertions)]
+    pub fn overwrites(&self) -> Vec<(&[u8], usize)> {
+        let mut overwrites = self
+            .writes
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(key, count)| (key.as_slice(), *count))
+            .collect::<Vec<_>>();
+        overwrites.sort();
+        overwrites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordered_write_batch() {
+        let mut batch = OrderedWriteBatch::new();
+        assert!(batch.is_empty());
+
+        batch.put(b"b".to_vec(), b"1".to_vec());
+        batch.put(b"a".to_vec(), b"22".to_vec());
+        batch.delete(b"b".to_vec());
+        batch.put(b"c".to_vec(), b"333".to_vec());
+        batch.delete(b"c".to_vec());
+        batch.put(b"c".to_vec(), b"4".to_vec());
+
+        // Every operation is kept, in order
+        assert_eq!(batch.len(), 6);
+        assert_eq!(
+            batch.ops().collect::<Vec<_>>(),
+            vec![
+                (&b"b"[..], Some(&b"1"[..])),
+                (&b"a"[..], Some(&b"22"[..])),
+                (&b"b"[..], None),
+                (&b"c"[..], Some(&b"333"[..])),
+                (&b"c"[..], None),
+                (&b"c"[..], Some(&b"4"[..])),
+            ]
+        );
+        assert_eq!(batch.byte_size(), 2 + 3 + 1 + 4 + 1 + 2);
+
+        // The last operation on each key wins
+        let final_ops = batch.final_ops();
+        assert_eq!(final_ops.len(), 3);
+        assert!(final_ops[&b"a"[..]]);
+        assert!(!final_ops[&b"b"[..]]);
+        assert!(final_ops[&b"c"[..]]);
+
+        #[cfg(debug_assertions)]
+        assert_eq!(batch.overwrites(), vec![(&b"b"[..], 2), (&b"c"[..], 3)]);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::statsd::statsd;
+use crate::store::HubError;
+use rocksdb::{properties, DB};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/** How long the write stall state is cached for. Stalls start and end with flushes and compactions */
+const STALL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/**
+ * Whether RocksDB is holding back writes because flushes or compactions can't keep up, going by
+ * its "is-write-stopped" and "actual-delayed-write-rate" properties.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteStallState {
+    Normal = 0,
+    /** Writes are slowed down to the delayed write rate */
+    Delayed = 1,
+    /** Writes block until the stall clears */
+    Stopped = 2,
+}
+
+impl WriteStallState {
+    fn from_properties(is_write_stopped: u64, delayed_write_rate: u64) -> Self {
+        if is_write_stopped > 0 {
+            WriteStallState::Stopped
+        } else if delayed_write_rate > 0 {
+            WriteStallState::Delayed
+        } else {
+            WriteStallState::Normal
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => WriteStallState::Delayed,
+            2 => WriteStallState::Stopped,
+            _ => WriteStallState::Normal,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WriteStallState::Normal => "normal",
+            WriteStallState::Delayed => "delayed",
+            WriteStallState::Stopped => "stopped",
+        }
+    }
+}
+
+/**
+ * How urgent a write is. High priority writes are always admitted, low priority writes fail with
+ * "db.write_stalled" while RocksDB stops writes, see RocksDB::admit_write.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum WritePriority {
+    #[default]
+    High,
+    Low,
+}
+
+impl WritePriority {
+    pub fn parse(name: &str) -> Result<Self, HubError> {
+        match name {
+            "high" => Ok(WritePriority::High),
+            "low" => Ok(WritePriority::Low),
+            _ => Err(HubError::invalid_parameter(&format!(
+                "unknown write priority: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/** Caches the WriteStallState of a DB, so that every write doesn't have to read the properties */
+#[derive(Default)]
+pub struct WriteStallDetector {
+    state: AtomicU8,
+    checked_at: Mutex<Option<Instant>>,
+}
+
+impl WriteStallDetector {
+    /** The stall state of `db`, read again if the cached state is older than STALL_CHECK_INTERVAL */
+    pub fn state(&self, db: &DB) -> WriteStallState {
+        {
+            let mut checked_at = self.checked_at.lock().unwrap();
+            if checked_at.is_some_and(|at| at.elapsed() < STALL_CHECK_INTERVAL) {
+                return WriteStallState::from_u8(self.state.load(Ordering::Relaxed));
+            }
+            *checked_at = Some(Instant::now());
+        }
+
+        let read = |property| db.property_int_value(property).ok().flatten().unwrap_or(0);
+        let state = WriteStallState::from_properties(
+            read(properties::IS_WRITE_STOPPED),
+            read(properties::ACTUAL_DELAYED_WRITE_RATE),
+        );
+
+        let previous = self.state.swap(state as u8, Ordering::Relaxed);
+        if previous != state as u8 {
+            statsd().gauge("rust.db.write_stall.state", state as u64);
+        }
+
+        state
+    }
+
+    /** Use `state` until the next check is due */
+    #[cfg(test)]
+    pub fn record(&self, state: WriteStallState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+        *self.checked_at.lock().unwrap() = Some(Instant::now());
+    }
+
+    /** Forget the cached state, eg. when the DB is reopened */
+    pub fn reset(&self) {
+        self.state
+            .store(WriteStallState::Normal as u8, Ordering::Relaxed);
+        *self.checked_at.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_stall_state() {
+        assert_eq!(
+            WriteStallState::from_properties(0, 0),
+            WriteStallState::Normal
+        );
+        assert_eq!(
+            WriteStallState::from_properties(0, 16 * 1024 * 1024),
+            WriteStallState::Delayed
+        );
+        assert_eq!(
+            WriteStallState::from_properties(1, 16 * 1024 * 1024),
+            WriteStallState::Stopped
+        );
+
+        for state in [
+            WriteStallState::Normal,
+            WriteStallState::Delayed,
+            WriteStallState::Stopped,
+        ] {
+            assert_eq!(WriteStallState::from_u8(state as u8), state);
+        }
+
+        assert_eq!(WritePriority::parse("low").unwrap(), WritePriority::Low);
+        assert!(WritePriority::parse("urgent").is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::statsd::statsd;
+use crate::THREAD_POOL;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek::{Signature, VerifyingKey};
+use neon::context::{Context, FunctionContext};
+use neon::handle::Handle;
+use neon::object::Object;
+use neon::result::JsResult;
+use neon::types::buffer::TypedArray;
+use neon::types::{JsArray, JsBuffer, JsPromise, JsValue};
+
+/** How many signatures are verified together. A batch that fails is verified again one by one */
+const VERIFY_BATCH_SIZE: usize = 64;
+
+/** A signature to verify, as (signature, hash, signer) */
+pub type SignedHash = (Vec<u8>, Vec<u8>, Vec<u8>);
+
+fn parse(signature: &[u8], signer: &[u8]) -> Option<(Signature, VerifyingKey)> {
+    let signature = Signature::from_bytes(&<[u8; 64]>::try_from(signature).ok()?);
+    let public_key = VerifyingKey::from_bytes(&<[u8; 32]>::try_from(signer).ok()?).ok()?;
+    Some((signature, public_key))
+}
+
+/** Whether `signature` is a valid ed25519 signature of `hash` by `signer` */
+pub fn ed25519_verify_hash(signature: &[u8], hash: &[u8], signer: &[u8]) -> bool {
+    match parse(signature, signer) {
+        Some((signature, public_key)) => public_key.verify_strict(hash, &signature).is_ok(),
+        None => false,
+    }
+}
+
+/**
+ * Whether batch verification of the signature gives the same result as verify_strict. Batch
+ * verification checks the equation of all the signatures of the batch at once, and doesn't
+ * reject small order points (eg. the identity, which is torsion free), torsion components, or R
+ * encodings that aren't canonical, the way verify_strict does. Signatures with those are rare,
+ * and are verified on their own.
+ */
+fn is_batchable(signature: &Signature, signer: &[u8]) -> bool {
+    let is_batchable_point = |bytes: &[u8; 32]| {
+        CompressedEdwardsY(*bytes)
+            .decompress()
+            .is_some_and(|point| {
+                !point.is_small_order() && point.is_torsion_free() && point.compress().0 == *bytes
+            })
+    };
+
+    signer.try_into().is_ok_and(is_batchable_point) && is_batchable_point(signature.r_bytes())
+}
+
+/**
+ * Verify `signed_hashes` with ed25519 batch verification. Returns whether each signature is
+ * valid, the same as ed25519_verify_hash would. When a batch fails, its signatures are verified
+ * one at a time to find the ones that are invalid.
+ */
+pub fn ed25519_verify_batch(signed_hashes: &[SignedHash]) -> Vec<bool> {
+    let mut results = vec![false; signed_hashes.len()];
+
+    let mut batch = vec![];
+    for (i, (signature, hash, signer)) in signed_hashes.iter().enumerate() {
+        match parse(signature, signer) {
+            Some((signature, public_key)) if is_batchable(&signature, signer) => {
+                batch.push((i, signature, public_key));
+            }
+            Some((signature, public_key)) => {
+                results[i] = public_key.verify_strict(hash, &signature).is_ok();
+            }
+            None => {}
+        }
+    }
+
+    for chunk in batch.chunks(VERIFY_BATCH_SIZE) {
+        let hashes = chunk
+            .iter()
+            .map(|(i, _, _)| signed_hashes[*i].1.as_slice())
+            .collect::<Vec<_>>();
+        let signatures = chunk.iter().map(|(_, s, _)| *s).collect::<Vec<_>>();
+        let public_keys = chunk.iter().map(|(_, _, k)| *k).collect::<Vec<_>>();
+
+        if ed25519_dalek::verify_batch(&hashes, &signatures, &public_keys).is_ok() {
+            for (i, _, _) in chunk {
+                results[*i] = true;
+            }
+        } else {
+            statsd().incr("rust.ed25519.verify_batch.failed_batches");
+            for ((i, signature, public_key), hash) in chunk.iter().zip(hashes) {
+                results[*i] = public_key.verify_strict(hash, signature).is_ok();
+            }
+        }
+    }
+
+    statsd().count("rust.ed25519.verify_batch.signatures", results.len() as i64);
+    results
+}
+
+/**
+ * Verify signatures[i] of hashes[i] by signers[i] on the thread pool. Resolves to an array with
+ * whether each signature is valid.
+ */
+pub fn js_ed25519_verify_batch(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let signatures = cx.argument::<JsArray>(0)?.to_vec(&mut cx)?;
+    let hashes = cx.argument::<JsArray>(1)?.to_vec(&mut cx)?;
+    let signers = cx.argument::<JsArray>(2)?.to_vec(&mut cx)?;
+    if signatures.len() != hashes.len() || signatures.len() != signers.len() {
+        return cx.throw_error("signatures, hashes and signers must have the same length");
+    }
+
+    let to_vec = |cx: &mut FunctionContext, value: Handle<JsValue>| {
+        value
+            .downcast_or_throw::<JsBuffer, _>(cx)
+            .map(|buffer| buffer.as_slice(cx).to_vec())
+    };
+    let mut signed_hashes = Vec::with_capacity(signatures.len());
+    for ((signature, hash), signer) in signatures.into_iter().zip(hashes).zip(signers) {
+        signed_hashes.push((
+            to_vec(&mut cx, signature)?,
+            to_vec(&mut cx, hash)?,
+            to_vec(&mut cx, signer)?,
+        ));
+    }
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    THREAD_POOL.lock().unwrap().execute(move || {
+        let results = ed25519_verify_batch(&signed_hashes);
+
+        deferred.settle_with(&channel, move |mut cx| {
+            let js_results = JsArray::new(&mut cx, results.len());
+            for (i, result) in results.into_iter().enumerate() {
+                let value = cx.boolean(result);
+                js_results.set(&mut cx, i as u32, value)?;
+            }
+            Ok(js_results)
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn signed_hash(seed: u8, hash: &[u8]) -> SignedHash {
+        let signing_key = SigningKey::from_bytes(&[seed; 32]);
+        (
+            signing_key.sign(hash).to_bytes().to_vec(),
+            hash.to_vec(),
+            signing_key.verifying_key().to_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_ed25519_verify_batch() {
+        // More than a batch, so that the failing batch is only one of them
+        let mut signed_hashes = (0..VERIFY_BATCH_SIZE as u8 + 10)
+            .map(|i| signed_hash(i, &[i; 20]))
+            .collect::<Vec<_>>();
+        assert!(ed25519_verify_batch(&signed_hashes)
+            .into_iter()
+            .all(|result| result));
+
+        // A wrong hash, a signature by another key, and signatures and signers that don't parse
+        signed_hashes[3].1 = vec![0; 20];
+        signed_hashes[5].2 = signed_hashes[6].2.clone();
+        signed_hashes[7].0 = vec![1; 63];
+        signed_hashes[8].2 = vec![];
+        let failed = [3, 5, 7, 8];
+
+        let results = ed25519_verify_batch(&signed_hashes);
+        assert_eq!(results.len(), signed_hashes.len());
+        for (i, (result, (signature, hash, signer))) in
+            results.iter().zip(&signed_hashes).enumerate()
+        {
+            assert_eq!(*result, !failed.contains(&i));
+            assert_eq!(*result, ed25519_verify_hash(signature, hash, signer));
+        }
+
+        assert!(ed25519_verify_batch(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_ed25519_verify_batch_small_order() {
+        // The identity as the key and as R, with s = 0, satisfies the verification equation for
+        // any hash. verify_strict rejects it because the key is of small order, and so must the
+        // batch, even though the identity is torsion free
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let mut signature = identity.to_vec();
+        signature.extend_from_slice(&[0; 32]);
+        let forged = (signature, vec![2; 20], identity.to_vec());
+        assert!(!ed25519_verify_hash(&forged.0, &forged.1, &forged.2));
+
+        let signed_hashes = vec![signed_hash(1, &[1; 20]), forged, signed_hash(3, &[3; 20])];
+        assert_eq!(
+            ed25519_verify_batch(&signed_hashes),
+            vec![true, false, true]
+        );
+    }
+}
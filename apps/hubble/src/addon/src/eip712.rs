@@ -0,0 +1,181 @@
+use crate::store::HubError;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha3::{Digest, Keccak256};
+
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
+/** The EIP-712 domain of typed data. Fields that are None are left out of the domain type */
+#[derive(Debug, Default, Clone)]
+pub struct Eip712Domain<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    pub chain_id: Option<u64>,
+    pub verifying_contract: Option<[u8; 20]>,
+    pub salt: Option<[u8; 32]>,
+}
+
+impl<'a> Eip712Domain<'a> {
+    pub fn separator(&self) -> [u8; 32] {
+        let mut type_string = "EIP712Domain(string name,string version".to_string();
+        let mut fields = vec![encode_string(self.name), encode_string(self.version)];
+
+        if let Some(chain_id) = self.chain_id {
+            type_string.push_str(",uint256 chainId");
+            fields.push(encode_uint(chain_id));
+        }
+        if let Some(verifying_contract) = &self.verifying_contract {
+            type_string.push_str(",address verifyingContract");
+            fields.push(encode_address(verifying_contract));
+        }
+        if let Some(salt) = self.salt {
+            type_string.push_str(",bytes32 salt");
+            fields.push(salt);
+        }
+        type_string.push(')');
+
+        hash_struct(&type_string, &fields)
+    }
+}
+
+/**
+ * hashStruct of EIP-712, for a struct of type `type_string` (eg. "Mail(address from,string
+ * contents)") whose fields are already encoded with the encode_* functions
+ */
+pub fn hash_struct(type_string: &str, fields: &[[u8; 32]]) -> [u8; 32] {
+    let mut encoded = Vec::with_capacity(32 * (fields.len() + 1));
+    encoded.extend_from_slice(&keccak256(type_string.as_bytes()));
+    for field in fields {
+        encoded.extend_from_slice(field);
+    }
+    keccak256(&encoded)
+}
+
+/** The digest that is signed for typed data with the struct hash `struct_hash` */
+pub fn hash_typed_data(domain: &Eip712Domain, struct_hash: &[u8; 32]) -> [u8; 32] {
+    let mut encoded = Vec::with_capacity(2 + 32 + 32);
+    encoded.extend_from_slice(&[0x19, 0x01]);
+    encoded.extend_from_slice(&domain.separator());
+    encoded.extend_from_slice(struct_hash);
+    keccak256(&encoded)
+}
+
+pub fn encode_uint(value: u64) -> [u8; 32] {
+    let mut encoded = [0u8; 32];
+    encoded[24..].copy_from_slice(&value.to_be_bytes());
+    encoded
+}
+
+pub fn encode_address(address: &[u8; 20]) -> [u8; 32] {
+    let mut encoded = [0u8; 32];
+    encoded[12..].copy_from_slice(address);
+    encoded
+}
+
+pub fn encode_string(value: &str) -> [u8; 32] {
+    keccak256(value.as_bytes())
+}
+
+/**
+ * Recover the address of the key that signed `hash`. The signature is r, s and v, with v being
+ * 27 or 28 (or 0 or 1), the way eth wallets sign typed data. Signatures with a high s are
+ * accepted, like viem does.
+ */
+pub fn recover_address(hash: &[u8; 32], signature: &[u8]) -> Result<[u8; 20], HubError> {
+    let invalid_signature = || HubError::validation_failure("invalid signature");
+
+    if signature.len() != 65 {
+        return Err(invalid_signature());
+    }
+    let (rs, v) = signature.split_at(64);
+    let mut recovery_id = match v[0] {
+        0 | 27 => 0,
+        1 | 28 => 1,
+        _ => return Err(invalid_signature()),
+    };
+
+    let mut signature = Signature::from_slice(rs).map_err(|_| invalid_signature())?;
+    // k256 only recovers from signatures with a low s. Negating s flips the parity of R's y
+    if let Some(normalized) = signature.normalize_s() {
+        signature = normalized;
+        recovery_id ^= 1;
+    }
+    let recovery_id = RecoveryId::from_byte(recovery_id).ok_or_else(invalid_signature)?;
+
+    let key = VerifyingKey::recover_from_prehash(hash, &signature, recovery_id)
+        .map_err(|_| invalid_signature())?;
+    let point = key.to_encoded_point(false);
+    let key_hash = keccak256(&point.as_bytes()[1..]);
+
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&key_hash[12..]);
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recover_address() {
+        // The username proof signature of the core eip712 tests
+        let domain = Eip712Domain {
+            name: "Farcaster name verification",
+            version: "1",
+            chain_id: Some(1),
+            verifying_contract: Some(
+                hex::decode("e3be01d99baa8db9905b33a3ca391238234b79d1")
+                    .unwrap()
+                    .try_into()
+                    .unwrap(),
+            ),
+            salt: None,
+        };
+        let owner: [u8; 20] = hex::decode("8773442740c17c9d0f0b87022c722f9a136206ed")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let struct_hash = hash_struct(
+            "UserNameProof(string name,uint256 timestamp,address owner)",
+            &[
+                encode_string("farcaster"),
+                encode_uint(1628882891),
+                encode_address(&owner),
+            ],
+        );
+        let hash = hash_typed_data(&domain, &struct_hash);
+
+        let signature = hex::decode(
+            "b7181760f14eda0028e0b647ff15f45235526ced3b4ae07fcce06141b73d3296\
+             0d3253776e62f761363fb8137087192047763f4af838950a96f3885f3c2289c41b",
+        )
+        .unwrap();
+        let signer = hex::decode("bc5274efc266311015793d89e9b591fa46294741").unwrap();
+        assert_eq!(recover_address(&hash, &signature).unwrap().to_vec(), signer);
+
+        // v can also be 0 or 1
+        let mut signature_v0 = signature.clone();
+        signature_v0[64] -= 27;
+        assert_eq!(
+            recover_address(&hash, &signature_v0).unwrap().to_vec(),
+            signer
+        );
+
+        // Another hash recovers another address
+        let other_hash = keccak256(b"farcaster");
+        assert_ne!(
+            recover_address(&other_hash, &signature).unwrap().to_vec(),
+            signer
+        );
+
+        assert_eq!(
+            recover_address(&hash, &signature[..64]).unwrap_err().code,
+            "bad_request.validation_failure"
+        );
+        let mut bad_v = signature.clone();
+        bad_v[64] = 29;
+        assert!(recover_address(&hash, &bad_v).is_err());
+        assert!(recover_address(&hash, &[0; 65]).is_err());
+    }
+}
@@ -2,14 +2,16 @@ use crate::{
     store::{CastStore, StoreEventHandler, UsernameProofStore, VerificationStore},
     trie::merkle_trie::MerkleTrie,
 };
-use db::RocksDB;
-use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, EXPANDED_SECRET_KEY_LENGTH};
+use db::{RocksDB, SnapshotHandle};
+use ed25519_dalek::{Signer, SigningKey, EXPANDED_SECRET_KEY_LENGTH};
 use neon::{prelude::*, types::buffer::TypedArray};
 use std::{convert::TryInto, sync::Mutex};
 use store::{LinkStore, ReactionStore, Store, UserDataStore};
 use threadpool::ThreadPool;
 
 mod db;
+mod ed25519;
+mod eip712;
 mod logger;
 mod statsd;
 mod store;
@@ -46,27 +48,12 @@ fn ed25519_verify(mut cx: FunctionContext) -> JsResult<JsNumber> {
     let hash_arg = cx.argument::<JsBuffer>(1)?;
     let signer_arg = cx.argument::<JsBuffer>(2)?;
 
-    // Convert to the types expected by ed25519_dalek 2.0
-    let sig_bytes: [u8; 64] = match signature_arg.as_slice(&cx).try_into() {
-        Ok(bytes) => bytes,
-        Err(_) => return Ok(cx.number(0)),
-    };
-    let signature = Signature::from_bytes(&sig_bytes);
-
-    let signer_bytes: [u8; 32] = match signer_arg.as_slice(&cx).try_into() {
-        Ok(bytes) => bytes,
-        Err(_) => return Ok(cx.number(0)),
-    };
-    let public_key = match VerifyingKey::from_bytes(&signer_bytes) {
-        Ok(pk) => pk,
-        Err(_) => return Ok(cx.number(0)),
-    };
-
-    // Verify the signature
-    match public_key.verify_strict(&hash_arg.as_slice(&cx), &signature) {
-        Ok(_) => Ok(cx.number(1)),
-        Err(_) => Ok(cx.number(0)),
-    }
+    let is_valid = ed25519::ed25519_verify_hash(
+        signature_arg.as_slice(&cx),
+        hash_arg.as_slice(&cx),
+        signer_arg.as_slice(&cx),
+    );
+    Ok(cx.number(if is_valid { 1 } else { 0 }))
 }
 
 fn js_blake3_20(mut cx: FunctionContext) -> JsResult<JsBuffer> {
@@ -87,7 +74,13 @@ fn js_blake3_20(mut cx: FunctionContext) -> JsResult<JsBuffer> {
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("ed25519_signMessageHash", ed25519_sign_message_hash)?;
     cx.export_function("ed25519_verify", ed25519_verify)?;
+    cx.export_function("ed25519_verifyBatch", ed25519::js_ed25519_verify_batch)?;
     cx.export_function("blake3_20", js_blake3_20)?;
+    cx.export_function("validateMessage", store::js_validate_message)?;
+    cx.export_function(
+        "verifyVerificationClaim",
+        store::js_verify_verification_claim,
+    )?;
 
     cx.export_function("createStatsdClient", statsd::js_create_statsd_client)?;
 
@@ -114,7 +107,31 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("dbDel", RocksDB::js_del)?;
     cx.export_function("dbCommit", RocksDB::js_commit_transaction)?;
     cx.export_function("dbSnapshotBackup", RocksDB::js_snapshot_backup)?;
+    cx.export_function("dbRestoreSnapshot", RocksDB::js_restore_snapshot)?;
+    cx.export_function("dbVerifySnapshot", RocksDB::js_verify_snapshot)?;
+    cx.export_function("dbGcSnapshots", RocksDB::js_gc_snapshots)?;
+    cx.export_function(
+        "dbCreateSnapshotHandle",
+        SnapshotHandle::js_create_snapshot_handle,
+    )?;
+    cx.export_function("dbCancelSnapshot", SnapshotHandle::js_cancel_snapshot)?;
     cx.export_function("dbCountKeysAtPrefix", RocksDB::js_count_keys_at_prefix)?;
+    cx.export_function("dbStartStatsReporter", RocksDB::js_start_stats_reporter)?;
+    cx.export_function("dbGetWriteStallState", RocksDB::js_get_write_stall_state)?;
+    cx.export_function("dbGetOldestEventId", RocksDB::js_get_oldest_event_id)?;
+    cx.export_function(
+        "dbTryCatchUpWithPrimary",
+        RocksDB::js_try_catch_up_with_primary,
+    )?;
+    cx.export_function("dbCreateReadSnapshot", RocksDB::js_create_read_snapshot)?;
+    cx.export_function("dbReleaseReadSnapshot", RocksDB::js_release_read_snapshot)?;
+    cx.export_function("dbRepairKeyCounters", RocksDB::js_repair_key_counters)?;
+    cx.export_function("dbRunMigrations", store::js_run_migrations)?;
+    cx.export_function("dbBeginLoading", RocksDB::js_begin_loading)?;
+    cx.export_function("dbEndLoading", RocksDB::js_end_loading)?;
+    cx.export_function("dbCreateBulkLoader", RocksDB::js_create_bulk_loader)?;
+    cx.export_function("dbBulkLoaderAdd", db::BulkLoader::js_add)?;
+    cx.export_function("dbBulkLoaderFinish", db::BulkLoader::js_finish)?;
     cx.export_function(
         "dbDeleteAllKeysInRange",
         RocksDB::js_delete_all_keys_in_range,
@@ -138,6 +155,8 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
     // Generic methods that can accept any store
     cx.export_function("merge", Store::js_merge)?;
     cx.export_function("mergeMany", Store::js_merge_many)?;
+    cx.export_function("simulateMerge", Store::js_simulate_merge)?;
+    cx.export_function("setMergeValidation", Store::js_set_merge_validation)?;
     cx.export_function("revoke", Store::js_revoke)?;
     cx.export_function("pruneMessages", Store::js_prune_messages)?;
     cx.export_function("getAllMessagesByFid", Store::js_get_all_messages_by_fid)?;
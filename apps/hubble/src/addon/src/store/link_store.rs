@@ -1,14 +1,13 @@
 use std::{borrow::Borrow, convert::TryInto, sync::Arc};
 
-use crate::db::{RocksDB, RocksDbTransactionBatch};
-use crate::logger::LOGGER;
+use crate::db::{JsIteratorOptions, Migration, RocksDB, RocksDbTransactionBatch};
 use crate::protos::link_body::Target;
 use crate::protos::message_data::Body;
 use crate::protos::{message_data, LinkBody, Message, MessageData, MessageType};
 use crate::store::{
-    get_message, get_page_options, get_store, hub_error_to_js_throw, make_fid_key, make_user_key,
-    message, utils, HubError, IntoI32, IntoU8, MessagesPage, PageOptions, RootPrefix, Store,
-    StoreDef, StoreEventHandler, UserPostfix, PAGE_SIZE_MAX, TS_HASH_LENGTH,
+    get_page_options, get_store, hub_error_to_js_throw, make_fid_key, make_user_key, message,
+    HubError, IntoI32, IntoU8, MessagesPage, PageOptions, RootPrefix, Store, StoreDef,
+    StoreEventHandler, UserPostfix, FID_BYTES, PAGE_SIZE_MAX, TS_HASH_LENGTH,
 };
 use crate::{protos, THREAD_POOL};
 use neon::prelude::{JsPromise, JsString};
@@ -19,7 +18,6 @@ use neon::{
     types::{JsBox, JsNumber},
 };
 use prost::Message as _;
-use slog::{o, warn};
 
 use super::deferred_settle_messages;
 
@@ -119,22 +117,7 @@ impl LinkStore {
             ..Default::default()
         };
 
-        let result = store.get_add(&partial_message);
-        if let Ok(None) = result {
-            // Check for incorrectly padded keys
-            let unpadded_key = Self::make_add_key_padded(&partial_message, false)?;
-            let message_ts_hash = store.db().get(&unpadded_key)?;
-            if message_ts_hash.is_none() {
-                return Ok(None);
-            }
-            return get_message(
-                store.db().borrow(),
-                partial_message.data.as_ref().unwrap().fid as u32,
-                store.store_def().postfix(),
-                &utils::vec_to_u8_24(&message_ts_hash)?,
-            );
-        }
-        result
+        store.get_add(&partial_message)
     }
 
     pub fn get_link_adds_by_fid(
@@ -249,22 +232,7 @@ impl LinkStore {
             ..Default::default()
         };
 
-        let result = store.get_remove(&partial_message);
-        if let Ok(None) = result {
-            // Check for incorrectly padded keys
-            let unpadded_key = Self::make_remove_key_padded(&partial_message, false)?;
-            let message_ts_hash = store.db().get(&unpadded_key)?;
-            if message_ts_hash.is_none() {
-                return Ok(None);
-            }
-            return get_message(
-                store.db().borrow(),
-                partial_message.data.as_ref().unwrap().fid as u32,
-                store.store_def().postfix(),
-                &utils::vec_to_u8_24(&message_ts_hash)?,
-            );
-        }
-        result
+        store.get_remove(&partial_message)
     }
 
     // Generates a unique key used to store a LinkCompactState message key in the store
@@ -751,133 +719,12 @@ impl StoreDef for LinkStore {
         message: &Message,
     ) -> Result<(), HubError> {
         let (by_target_key, _) = self.secondary_index_key(ts_hash, message)?;
-        if self.is_add_type(message) {
-            let incorrectly_padded_key = Self::make_add_key_padded(message, false)?;
-            txn.delete(incorrectly_padded_key);
-        } else if self.is_remove_type(message) {
-            let incorrectly_padded_key = Self::make_remove_key_padded(message, false)?;
-            txn.delete(incorrectly_padded_key);
-        };
 
         txn.delete(by_target_key);
 
         Ok(())
     }
 
-    fn delete_remove_secondary_indices(
-        &self,
-        txn: &mut RocksDbTransactionBatch,
-        message: &Message,
-    ) -> Result<(), HubError> {
-        if self.is_add_type(message) {
-            let incorrectly_padded_key = Self::make_add_key_padded(message, false)?;
-            txn.delete(incorrectly_padded_key);
-        } else if self.is_remove_type(message) {
-            let incorrectly_padded_key = Self::make_remove_key_padded(message, false)?;
-            txn.delete(incorrectly_padded_key);
-        };
-
-        Ok(())
-    }
-
-    // During the initial rust migration, we were not padding the type field to 8 bytes, so we still
-    // have some links that don't have the right padding. Override the default merge conflict resolution
-    // to check for the presence of incorrectly padded links as well
-    fn get_merge_conflicts(
-        &self,
-        db: &RocksDB,
-        message: &Message,
-        ts_hash: &[u8; TS_HASH_LENGTH],
-    ) -> Result<Vec<Message>, HubError> {
-        // First, call the default implementation to get the default merge conflicts
-        let mut conflicts = Self::get_default_merge_conflicts(self, db, message, ts_hash)?;
-
-        let remove_key = Self::make_remove_key_padded(message, false)?;
-        let remove_ts_hash = db.get(&remove_key)?;
-
-        if remove_ts_hash.is_some() {
-            let remove_compare = self.message_compare(
-                self.remove_message_type(),
-                &remove_ts_hash.clone().unwrap(),
-                message.data.as_ref().unwrap().r#type as u8,
-                &ts_hash.to_vec(),
-            );
-
-            if remove_compare > 0 {
-                return Err(HubError {
-                    code: "bad_request.conflict".to_string(),
-                    message: "message conflicts with a more recent remove".to_string(),
-                });
-            }
-            if remove_compare == 0 {
-                return Err(HubError {
-                    code: "bad_request.duplicate".to_string(),
-                    message: "message has already been merged".to_string(),
-                });
-            }
-
-            // If the existing remove has a lower order than the new message, retrieve the full
-            // Remove message and delete it as part of the RocksDB transaction
-            let maybe_existing_remove = get_message(
-                &db,
-                message.data.as_ref().unwrap().fid as u32,
-                self.postfix(),
-                &utils::vec_to_u8_24(&remove_ts_hash)?,
-            )?;
-
-            if maybe_existing_remove.is_some() {
-                conflicts.push(maybe_existing_remove.unwrap());
-            } else {
-                warn!(LOGGER, "Message's ts_hash exists but message not found in store";
-                        o!("remove_ts_hash" => format!("{:x?}", remove_ts_hash.unwrap())));
-            }
-        }
-
-        // Check if there is an add timestamp hash for this
-        let add_key = Self::make_add_key_padded(message, false)?;
-        let add_ts_hash = db.get(&add_key)?;
-
-        if add_ts_hash.is_some() {
-            let add_compare = self.message_compare(
-                self.add_message_type(),
-                &add_ts_hash.clone().unwrap(),
-                message.data.as_ref().unwrap().r#type as u8,
-                &ts_hash.to_vec(),
-            );
-
-            if add_compare > 0 {
-                return Err(HubError {
-                    code: "bad_request.conflict".to_string(),
-                    message: "message conflicts with a more recent add".to_string(),
-                });
-            }
-            if add_compare == 0 {
-                return Err(HubError {
-                    code: "bad_request.duplicate".to_string(),
-                    message: "message has already been merged".to_string(),
-                });
-            }
-
-            // If the existing add has a lower order than the new message, retrieve the full
-            // Add message and delete it as part of the RocksDB transaction
-            let maybe_existing_add = get_message(
-                &db,
-                message.data.as_ref().unwrap().fid as u32,
-                self.postfix(),
-                &utils::vec_to_u8_24(&add_ts_hash)?,
-            )?;
-
-            if maybe_existing_add.is_none() {
-                warn!(LOGGER, "Message's ts_hash exists but message not found in store";
-                    o!("add_ts_hash" => format!("{:x?}", add_ts_hash.unwrap())));
-            } else {
-                conflicts.push(maybe_existing_add.unwrap());
-            }
-        }
-
-        return Ok(conflicts);
-    }
-
     fn find_merge_add_conflicts(&self, _db: &RocksDB, _message: &Message) -> Result<(), HubError> {
         // For links, there will be no additional conflict logic
         Ok(())
@@ -929,8 +776,8 @@ impl StoreDef for LinkStore {
     }
 
     fn make_add_key(&self, message: &Message) -> Result<Vec<u8>, HubError> {
-        // Type bytes must be padded to 8 bytes, but we had a bug which allowed unpadded types,
-        // so this function allows access to both types of keys
+        // Type bytes must be padded to 8 bytes. Keys written without the padding were moved by
+        // LinkKeyPaddingMigration
         return Self::make_add_key_padded(message, true);
     }
 
@@ -942,3 +789,97 @@ impl StoreDef for LinkStore {
         self.prune_size_limit
     }
 }
+
+/**
+ * During the initial rust migration, we were not padding the link type to 8 bytes in the set index
+ * keys. This moves those index entries to their padded keys, so the lookups only have to check
+ * the padded keys.
+ */
+pub struct LinkKeyPaddingMigration;
+
+impl LinkKeyPaddingMigration {
+    // The number of user keys that are read per batch
+    const BATCH_SIZE: usize = 10_000;
+
+    fn migrate_message(
+        db: &RocksDB,
+        txn: &mut RocksDbTransactionBatch,
+        value: &[u8],
+    ) -> Result<(), HubError> {
+        let message = match message::message_decode(value) {
+            Ok(message) => message,
+            Err(_) => return Ok(()), // Ignore invalid messages
+        };
+
+        let message_type = message.data.as_ref().map_or(0, |data| data.r#type);
+        let keys = if message_type == MessageType::LinkAdd.into_i32() {
+            LinkStore::make_add_key_padded(&message, true)
+                .and_then(|padded| Ok((padded, LinkStore::make_add_key_padded(&message, false)?)))
+        } else if message_type == MessageType::LinkRemove.into_i32() {
+            LinkStore::make_remove_key_padded(&message, true).and_then(|padded| {
+                Ok((padded, LinkStore::make_remove_key_padded(&message, false)?))
+            })
+        } else {
+            return Ok(());
+        };
+        let (padded_key, unpadded_key) = match keys {
+            Ok(keys) => keys,
+            Err(_) => return Ok(()), // Ignore messages with an invalid link body
+        };
+        if padded_key == unpadded_key {
+            return Ok(());
+        }
+
+        if let Some(ts_hash) = db.get(&unpadded_key)? {
+            if db.get(&padded_key)?.is_none() {
+                txn.put(padded_key, ts_hash);
+            }
+            txn.delete(unpadded_key);
+        }
+
+        Ok(())
+    }
+}
+
+impl Migration for LinkKeyPaddingMigration {
+    fn version(&self) -> u32 {
+        12
+    }
+
+    fn name(&self) -> &'static str {
+        "linkKeyPadding"
+    }
+
+    fn migrate_batch(
+        &self,
+        db: &RocksDB,
+        checkpoint: Option<&[u8]>,
+        txn: &mut RocksDbTransactionBatch,
+    ) -> Result<Option<Vec<u8>>, HubError> {
+        // The checkpoint is the last user key of the previous batch
+        let iter_opts = JsIteratorOptions {
+            reverse: false,
+            gte: checkpoint.is_none().then(|| vec![RootPrefix::User as u8]),
+            gt: checkpoint.map(|checkpoint| checkpoint.to_vec()),
+            lt: vec![RootPrefix::User as u8 + 1],
+        };
+
+        let mut count = 0;
+        let mut last_key = None;
+        db.for_each_iterator_by_jsopts(iter_opts, None, |key, value| {
+            if key.get(1 + FID_BYTES) == Some(&UserPostfix::LinkMessage.as_u8()) {
+                Self::migrate_message(db, txn, value)?;
+            }
+
+            count += 1;
+            last_key = Some(key.to_vec());
+            Ok(count >= Self::BATCH_SIZE)
+        })?;
+
+        Ok(if count >= Self::BATCH_SIZE {
+            last_key
+        } else {
+            None
+        })
+    }
+}
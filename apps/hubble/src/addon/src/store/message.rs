@@ -282,6 +282,31 @@ pub fn get_message(
     }
 }
 
+/**
+ * Read a message as part of `txn`, see RocksDB::get_for_update. This sees the messages `txn`
+ * itself writes, so merges that are batched in one transaction find each other as conflicts.
+ */
+pub fn get_message_for_update(
+    db: &RocksDB,
+    txn: &mut RocksDbTransactionBatch,
+    fid: u32,
+    set: u8,
+    ts_hash: &[u8; TS_HASH_LENGTH],
+) -> Result<Option<MessageProto>, HubError> {
+    let key = make_message_primary_key(fid, set, Some(ts_hash));
+
+    match db.get_for_update(txn, &key)? {
+        Some(bytes) => match message_decode(bytes.as_slice()) {
+            Ok(message) => Ok(Some(message)),
+            Err(_) => Err(HubError {
+                code: "db.internal_error".to_string(),
+                message: "could not decode message".to_string(),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
 /** Read many messages.
  * Note that if a message is not found, that corresponding entry in the result will be None.
  * This is different from the behaviour of get_message, which returns an error.
@@ -0,0 +1,97 @@
+use super::{get_db, hub_error_to_js_throw, LinkKeyPaddingMigration};
+use crate::db::{run_migrations, schema_version, Migration, MigrationReport};
+use neon::context::{Context, FunctionContext};
+use neon::object::Object;
+use neon::result::JsResult;
+use neon::types::{JsBoolean, JsNumber, JsObject, JsPromise};
+
+/**
+ * The migrations that run in Rust, in schema version order. The migrations up to schema version
+ * 11 are run by the JS migrations, which call runMigrations for the versions after that.
+ */
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![Box::new(LinkKeyPaddingMigration)]
+}
+
+/** The schema version of the DB once all the Rust migrations have run */
+pub fn latest_schema_version() -> u32 {
+    migrations()
+        .iter()
+        .map(|migration| migration.version())
+        .max()
+        .unwrap_or(0)
+}
+
+/**
+ * Run the Rust migrations up to the optional `toVersion`, or all of them. With `dryRun` nothing is
+ * written. Resolves to the schema versions before and after, and a report for each migration.
+ */
+pub fn js_run_migrations(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let db = get_db(&mut cx)?;
+
+    let mut to_version = latest_schema_version();
+    let mut dry_run = false;
+    if let Some(arg) = cx.argument_opt(0) {
+        if let Ok(options) = arg.downcast::<JsObject, _>(&mut cx) {
+            if let Some(v) = options.get_opt::<JsNumber, _, _>(&mut cx, "toVersion")? {
+                to_version = v.value(&mut cx) as u32;
+            }
+            if let Some(v) = options.get_opt::<JsBoolean, _, _>(&mut cx, "dryRun")? {
+                dry_run = v.value(&mut cx);
+            }
+        }
+    }
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    // Migrations can read every key, so don't hold up the thread pool with them
+    std::thread::spawn(move || {
+        let result = schema_version(&db).and_then(|from_version| {
+            let reports = run_migrations(&db, &migrations(), to_version, dry_run)?;
+            Ok((from_version, reports))
+        });
+
+        deferred.settle_with(&channel, move |mut cx| {
+            let (from_version, reports) = match result {
+                Ok(result) => result,
+                Err(e) => return hub_error_to_js_throw(&mut cx, e),
+            };
+
+            let js_reports = cx.empty_array();
+            for (i, report) in reports.iter().enumerate() {
+                let js_report = report_to_js_object(&mut cx, report)?;
+                js_reports.set(&mut cx, i as u32, js_report)?;
+            }
+
+            let js_result = cx.empty_object();
+            let value = cx.number(from_version);
+            js_result.set(&mut cx, "fromVersion", value)?;
+            let value = cx.number(reports.last().map_or(from_version, |report| report.version));
+            js_result.set(&mut cx, "toVersion", value)?;
+            js_result.set(&mut cx, "migrations", js_reports)?;
+
+            Ok(js_result)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn report_to_js_object<'a, C: Context<'a>>(
+    cx: &mut C,
+    report: &MigrationReport,
+) -> JsResult<'a, JsObject> {
+    let js_report = cx.empty_object();
+
+    let value = cx.number(report.version);
+    js_report.set(cx, "version", value)?;
+    let value = cx.string(report.name);
+    js_report.set(cx, "name", value)?;
+    let value = cx.number(report.batches as f64);
+    js_report.set(cx, "batches", value)?;
+    let value = cx.number(report.writes as f64);
+    js_report.set(cx, "writes", value)?;
+
+    Ok(js_report)
+}
@@ -1,17 +1,21 @@
 pub use self::cast_store::*;
 pub use self::link_store::*;
 pub use self::message::*;
+pub use self::migrations::*;
 pub use self::reaction_store::*;
 pub use self::store::*;
 pub use self::store_event_handler::*;
 pub use self::user_data_store::*;
 pub use self::username_proof_store::*;
 pub use self::utils::*;
+pub use self::validation::*;
+pub use self::verification_claim::*;
 pub use self::verification_store::*;
 
 mod cast_store;
 mod link_store;
 mod message;
+mod migrations;
 mod name_registry_events;
 mod reaction_store;
 mod store;
@@ -19,4 +23,6 @@ mod store_event_handler;
 mod user_data_store;
 mod username_proof_store;
 mod utils;
+mod validation;
+mod verification_claim;
 mod verification_store;
@@ -1,20 +1,20 @@
 use super::{
-    bytes_compare, delete_message_transaction, get_message, hub_error_to_js_throw,
-    is_message_in_time_range, make_message_primary_key, message, message_decode, message_encode,
-    put_message_transaction,
+    bytes_compare, delete_message_transaction, get_message, get_message_for_update,
+    hub_error_to_js_throw, is_message_in_time_range, make_message_primary_key, message,
+    message_decode, message_encode, put_message_transaction,
     utils::{self, encode_messages_to_js_object, get_page_options, get_store, vec_to_u8_24},
-    MessagesPage, StoreEventHandler, TS_HASH_LENGTH,
+    validate_message, MessagesPage, StoreEventHandler, TS_HASH_LENGTH,
 };
 use crate::{
-    db::{RocksDB, RocksDbTransactionBatch},
+    db::{RocksDB, RocksDbTransactionBatch, WritePriority},
     protos::{
-        self, hub_event, link_body::Target, message_data::Body, HubEvent, HubEventType,
-        MergeMessageBody, Message, MessageType,
+        self, hub_event, link_body::Target, message_data::Body, FarcasterNetwork, HubEvent,
+        HubEventType, MergeMessageBody, Message, MessageType,
     },
     store::make_ts_hash,
 };
-use crate::{logger::LOGGER, THREAD_POOL};
-use neon::types::{Finalize, JsBuffer, JsNumber, JsString};
+use crate::{logger::LOGGER, statsd::statsd, THREAD_POOL};
+use neon::types::{Finalize, JsBuffer, JsNumber, JsString, JsUndefined};
 use neon::{context::Context, types::JsArray};
 use neon::{context::FunctionContext, result::JsResult, types::JsPromise};
 use neon::{object::Object, types::buffer::TypedArray};
@@ -22,10 +22,11 @@ use prost::Message as _;
 use rocksdb;
 use slog::{o, warn};
 use std::string::ToString;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
 use std::{clone::Clone, fmt::Display};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct HubError {
     pub code: String,
     pub message: String,
@@ -97,7 +98,8 @@ impl From<std::io::Error> for HubError {
     }
 }
 
-pub const FID_LOCKS_COUNT: usize = 4;
+/** How many times a merge is retried when its transaction conflicts with another write */
+pub const MERGE_CONFLICT_RETRIES: usize = 3;
 pub const PAGE_SIZE_MAX: usize = 10_000;
 
 #[derive(Debug, Default)]
@@ -171,18 +173,24 @@ pub trait StoreDef: Send + Sync {
 
     fn get_prune_size_limit(&self) -> u32;
 
+    /**
+     * The messages that merging `message` deletes. The keys that decide the conflicts are read
+     * with get_for_update, so that the merge fails if they change before `txn` is committed.
+     */
     fn get_merge_conflicts(
         &self,
         db: &RocksDB,
+        txn: &mut RocksDbTransactionBatch,
         message: &Message,
         ts_hash: &[u8; TS_HASH_LENGTH],
     ) -> Result<Vec<Message>, HubError> {
-        Self::get_default_merge_conflicts(&self, db, message, ts_hash)
+        Self::get_default_merge_conflicts(&self, db, txn, message, ts_hash)
     }
 
     fn get_default_merge_conflicts(
         &self,
         db: &RocksDB,
+        txn: &mut RocksDbTransactionBatch,
         message: &Message,
         ts_hash: &[u8; TS_HASH_LENGTH],
     ) -> Result<Vec<Message>, HubError> {
@@ -199,7 +207,7 @@ pub trait StoreDef: Send + Sync {
 
         if self.remove_type_supported() {
             let remove_key = self.make_remove_key(message)?;
-            let remove_ts_hash = db.get(&remove_key)?;
+            let remove_ts_hash = db.get_for_update(txn, &remove_key)?;
 
             if remove_ts_hash.is_some() {
                 let remove_compare = self.message_compare(
@@ -224,8 +232,9 @@ pub trait StoreDef: Send + Sync {
 
                 // If the existing remove has a lower order than the new message, retrieve the full
                 // Remove message and delete it as part of the RocksDB transaction
-                let maybe_existing_remove = get_message(
-                    &db,
+                let maybe_existing_remove = get_message_for_update(
+                    db,
+                    txn,
                     message.data.as_ref().unwrap().fid as u32,
                     self.postfix(),
                     &utils::vec_to_u8_24(&remove_ts_hash)?,
@@ -242,7 +251,7 @@ pub trait StoreDef: Send + Sync {
 
         // Check if there is an add timestamp hash for this
         let add_key = self.make_add_key(message)?;
-        let add_ts_hash = db.get(&add_key)?;
+        let add_ts_hash = db.get_for_update(txn, &add_key)?;
 
         if add_ts_hash.is_some() {
             let add_compare = self.message_compare(
@@ -267,8 +276,9 @@ pub trait StoreDef: Send + Sync {
 
             // If the existing add has a lower order than the new message, retrieve the full
             // Add message and delete it as part of the RocksDB transaction
-            let maybe_existing_add = get_message(
-                &db,
+            let maybe_existing_add = get_message_for_update(
+                db,
+                txn,
                 message.data.as_ref().unwrap().fid as u32,
                 self.postfix(),
                 &utils::vec_to_u8_24(&add_ts_hash)?,
@@ -356,10 +366,20 @@ pub trait StoreDef: Send + Sync {
     }
 }
 
+/** The result of Store::simulate_merge */
+pub struct MergeSimulation {
+    pub merge_conflicts: Vec<Message>,
+    pub hub_event: HubEvent,
+}
+
 pub struct Store {
     store_def: Box<dyn StoreDef>,
     store_event_handler: Arc<StoreEventHandler>,
-    fid_locks: Arc<[Mutex<()>; 4]>,
+    // A compact state merge iterates over all the messages of the fid, which get_for_update can't
+    // track, so it takes this exclusively. All other merges take it shared.
+    compact_state_lock: RwLock<()>,
+    // The network merged messages are validated for, see set_merge_validation
+    validation_network: AtomicI32,
     db: Arc<RocksDB>,
     logger: slog::Logger,
 }
@@ -377,12 +397,8 @@ impl Store {
         Store {
             store_def,
             store_event_handler,
-            fid_locks: Arc::new([
-                Mutex::new(()),
-                Mutex::new(()),
-                Mutex::new(()),
-                Mutex::new(()),
-            ]),
+            compact_state_lock: RwLock::new(()),
+            validation_network: AtomicI32::new(FarcasterNetwork::None as i32),
             db,
             logger: LOGGER.new(o!("component" => "Store")),
         }
@@ -404,6 +420,24 @@ impl Store {
         self.store_event_handler.clone()
     }
 
+    /**
+     * Validate the messages merged into this store with validate_message for `network`, or stop
+     * validating them with None. Off by default, since the engine validates messages before it
+     * merges them.
+     */
+    pub fn set_merge_validation(&self, network: Option<FarcasterNetwork>) {
+        let network = network.unwrap_or(FarcasterNetwork::None);
+        self.validation_network
+            .store(network as i32, Ordering::Relaxed);
+    }
+
+    fn merge_validation(&self) -> Option<FarcasterNetwork> {
+        match FarcasterNetwork::try_from(self.validation_network.load(Ordering::Relaxed)) {
+            Ok(FarcasterNetwork::None) | Err(_) => None,
+            Ok(network) => Some(network),
+        }
+    }
+
     pub fn postfix(&self) -> u8 {
         self.store_def.postfix()
     }
@@ -646,14 +680,14 @@ impl Store {
         Ok(())
     }
 
-    pub fn merge(&self, message: &Message) -> Result<Vec<u8>, HubError> {
-        // Grab a merge lock. The typescript code does this by individual fid, but we don't have a
-        // good way of doing that efficiently here. We'll just use an array of locks, with each fid
-        // deterministically mapped to a lock.
-        let _fid_lock = &self.fid_locks
-            [message.data.as_ref().unwrap().fid as usize % FID_LOCKS_COUNT]
-            .lock()
-            .unwrap();
+    /**
+     * Check that `message` can be merged into this store, and make its ts_hash. The message is
+     * validated first if merge validation is on.
+     */
+    fn check_merge_type(&self, message: &Message) -> Result<[u8; TS_HASH_LENGTH], HubError> {
+        if let Some(network) = self.merge_validation() {
+            validate_message(message, network)?;
+        }
 
         if !self.store_def.is_add_type(message)
             && !(self.store_def.remove_type_supported() && self.store_def.is_remove_type(message))
@@ -666,14 +700,144 @@ impl Store {
             });
         }
 
-        let ts_hash = make_ts_hash(message.data.as_ref().unwrap().timestamp, &message.hash)?;
+        make_ts_hash(message.data.as_ref().unwrap().timestamp, &message.hash)
+    }
 
-        if self.store_def().is_compact_state_type(message) {
-            self.merge_compact_state(message)
-        } else if self.store_def.is_add_type(message) {
-            self.merge_add(&ts_hash, message)
-        } else {
-            self.merge_remove(&ts_hash, message)
+    /**
+     * Merge `message` into the store. Low priority merges go through RocksDB::admit_write first, so
+     * they fail with "db.write_stalled" while RocksDB stops writes.
+     */
+    pub fn merge(&self, message: &Message, priority: WritePriority) -> Result<Vec<u8>, HubError> {
+        let ts_hash = self.check_merge_type(message)?;
+
+        self.db.admit_write(priority)?;
+
+        // Merges don't lock the fid. Instead, the keys that decide the merge conflicts are read
+        // with get_for_update, and if another write changed them before the commit, the merge is
+        // done again from scratch.
+        let mut retries = 0;
+        loop {
+            let result = if self.store_def().is_compact_state_type(message) {
+                self.merge_compact_state(message)
+            } else if self.store_def.is_add_type(message) {
+                self.merge_add(&ts_hash, message)
+            } else {
+                self.merge_remove(&ts_hash, message)
+            };
+
+            match result {
+                Err(e)
+                    if e.code == "db.transaction_conflict" && retries < MERGE_CONFLICT_RETRIES =>
+                {
+                    statsd().incr("rust.store.merge.retry");
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /**
+     * Merge `messages` in as few transactions as possible. The messages between two compact state
+     * messages are merged in one transaction, and each compact state message in one of its own.
+     * Conflicts between the messages are resolved as if they were merged one at a time, in order,
+     * and every message that is merged gets its own HubEvent. Returns the result of each message.
+     */
+    pub fn merge_many(
+        &self,
+        messages: &[Message],
+        priority: WritePriority,
+    ) -> Vec<Result<Vec<u8>, HubError>> {
+        if let Err(e) = self.db.admit_write(priority) {
+            return messages.iter().map(|_| Err(e.clone())).collect();
+        }
+        statsd().histogram("rust.store.merge_many.messages", messages.len() as u64);
+
+        let mut results = Vec::with_capacity(messages.len());
+        let mut batch = vec![];
+        for message in messages {
+            // A compact state merge iterates over the messages of the fid in the DB, so it
+            // wouldn't see the messages of the batch before it
+            if self.store_def.compact_state_type_supported()
+                && self.store_def.is_compact_state_type(message)
+            {
+                results.extend(self.merge_batch(&batch));
+                batch.clear();
+                results.push(self.merge(message, WritePriority::High));
+            } else {
+                batch.push(message);
+            }
+        }
+        results.extend(self.merge_batch(&batch));
+
+        results
+    }
+
+    /**
+     * Merge adds and removes in a single transaction. A message that fails is rolled back on its
+     * own, the others are still merged. If the commit fails, all the merges that had succeeded
+     * fail with its error.
+     */
+    fn merge_batch(&self, messages: &[&Message]) -> Vec<Result<Vec<u8>, HubError>> {
+        if messages.is_empty() {
+            return vec![];
+        }
+
+        let mut retries = 0;
+        loop {
+            let _compact_state_guard = self.compact_state_lock.read().unwrap();
+            let mut txn = self.db.txn();
+
+            let results = messages
+                .iter()
+                .map(|message| {
+                    let ts_hash = self.check_merge_type(message)?;
+
+                    txn.set_savepoint();
+                    let result = if self.store_def.is_add_type(message) {
+                        self.merge_add_transaction(&mut txn, &ts_hash, message)
+                    } else {
+                        self.merge_remove_transaction(&mut txn, &ts_hash, message)
+                    }
+                    .and_then(|merge_conflicts| {
+                        self.commit_merge_event(&mut txn, message, merge_conflicts)
+                    });
+                    if result.is_err() {
+                        txn.rollback_to_savepoint();
+                    }
+
+                    result
+                })
+                .collect::<Vec<_>>();
+
+            if txn.len() == 0 {
+                return results
+                    .into_iter()
+                    .map(|result| result.map(|hub_event| hub_event.encode_to_vec()))
+                    .collect();
+            }
+
+            statsd().histogram("rust.store.merge_many.batch_size", txn.len() as u64);
+            match self.db.commit(txn) {
+                Ok(()) => {
+                    return results
+                        .into_iter()
+                        .map(|result| result.map(|hub_event| hub_event.encode_to_vec()))
+                        .collect()
+                }
+                Err(e)
+                    if e.code == "db.transaction_conflict" && retries < MERGE_CONFLICT_RETRIES =>
+                {
+                    statsd().incr("rust.store.merge_many.retry");
+                    retries += 1;
+                }
+                Err(e) => {
+                    return results
+                        .into_iter()
+                        .map(|result| result.and_then(|_| Err(e.clone())))
+                        .collect()
+                }
+            }
         }
     }
 
@@ -741,12 +905,33 @@ impl Store {
     }
 
     pub fn merge_compact_state(&self, message: &Message) -> Result<Vec<u8>, HubError> {
+        let _compact_state_guard = self.compact_state_lock.write().unwrap();
+        let mut txn = self.db.txn();
+        let merge_conflicts = self.merge_compact_state_transaction(&mut txn, message)?;
+        let hub_event = self.commit_merge_event(&mut txn, message, merge_conflicts)?;
+
+        // Commit the transaction
+        self.db.commit(txn)?;
+
+        // Serialize the hub_event
+        Ok(hub_event.encode_to_vec())
+    }
+
+    /**
+     * Add the writes of merging a compact state message to `txn`, and return the messages it
+     * deletes. The caller holds compact_state_lock exclusively.
+     */
+    fn merge_compact_state_transaction(
+        &self,
+        txn: &mut RocksDbTransactionBatch,
+        message: &Message,
+    ) -> Result<Vec<Message>, HubError> {
         let mut merge_conflicts = vec![];
 
         // First, find if there's an existing compact state message, and if there is,
         // delete it if it is older
         let compact_state_key = self.store_def.make_compact_state_add_key(message)?;
-        let existing_compact_state = self.db.get(&compact_state_key)?;
+        let existing_compact_state = self.db.get_for_update(txn, &compact_state_key)?;
 
         if existing_compact_state.is_some() {
             if let Ok(existing_compact_state_message) =
@@ -805,28 +990,58 @@ impl Store {
                 Ok(false) // Continue the iteration
             })?;
 
-        let mut txn = self.db.txn();
         // Delete all the merge conflicts
-        self.delete_many_transaction(&mut txn, &merge_conflicts)?;
+        self.delete_many_transaction(txn, &merge_conflicts)?;
 
         // Add the Link compact state message
-        self.put_add_compact_state_transaction(&mut txn, message)?;
+        self.put_add_compact_state_transaction(txn, message)?;
+
+        Ok(merge_conflicts)
+    }
 
-        // Event Handler
+    /** Add the HubEvent of merging `message` to `txn`, which gets it its event id */
+    fn commit_merge_event(
+        &self,
+        txn: &mut RocksDbTransactionBatch,
+        message: &Message,
+        merge_conflicts: Vec<Message>,
+    ) -> Result<HubEvent, HubError> {
         let mut hub_event = self.store_def.merge_event_args(message, merge_conflicts);
 
         let id = self
             .store_event_handler
-            .commit_transaction(&mut txn, &mut hub_event)?;
+            .commit_transaction(txn, &mut hub_event)?;
+        hub_event.id = id;
 
-        // Commit the transaction
-        self.db.commit(txn)?;
+        Ok(hub_event)
+    }
 
-        hub_event.id = id;
-        // Serialize the hub_event
-        let hub_event_bytes = hub_event.encode_to_vec();
+    /**
+     * What merging `message` would do, without merging it: the messages it would delete, and the
+     * HubEvent it would be merged with, which has no id yet. Fails with the error the merge would
+     * fail with, like "bad_request.conflict", "bad_request.duplicate" or "bad_request.prunable".
+     */
+    pub fn simulate_merge(&self, message: &Message) -> Result<MergeSimulation, HubError> {
+        let ts_hash = self.check_merge_type(message)?;
 
-        Ok(hub_event_bytes)
+        // Nothing is committed, so the writes are only made to find the conflicts
+        let mut txn = self.db.txn();
+        let merge_conflicts = if self.store_def.is_compact_state_type(message) {
+            self.merge_compact_state_transaction(&mut txn, message)?
+        } else if self.store_def.is_add_type(message) {
+            self.merge_add_transaction(&mut txn, &ts_hash, message)?
+        } else {
+            self.merge_remove_transaction(&mut txn, &ts_hash, message)?
+        };
+
+        let hub_event = self
+            .store_def
+            .merge_event_args(message, merge_conflicts.clone());
+
+        Ok(MergeSimulation {
+            merge_conflicts,
+            hub_event,
+        })
     }
 
     pub fn merge_add(
@@ -834,11 +1049,35 @@ impl Store {
         ts_hash: &[u8; TS_HASH_LENGTH],
         message: &Message,
     ) -> Result<Vec<u8>, HubError> {
+        let _compact_state_guard = self.compact_state_lock.read().unwrap();
+        let mut txn = self.db.txn();
+        let merge_conflicts = self.merge_add_transaction(&mut txn, ts_hash, message)?;
+        let hub_event = self.commit_merge_event(&mut txn, message, merge_conflicts)?;
+
+        // Commit the transaction
+        self.db.commit(txn)?;
+
+        // Serialize the hub_event
+        Ok(hub_event.encode_to_vec())
+    }
+
+    /**
+     * Add the writes of merging an add to `txn`, and return the messages it deletes. The caller
+     * holds compact_state_lock.
+     */
+    fn merge_add_transaction(
+        &self,
+        txn: &mut RocksDbTransactionBatch,
+        ts_hash: &[u8; TS_HASH_LENGTH],
+        message: &Message,
+    ) -> Result<Vec<Message>, HubError> {
         // If the store supports compact state messages, we don't merge messages that don't exist in the compact state
         if self.store_def.compact_state_type_supported() {
             // Get the compact state message
             let compact_state_key = self.store_def.make_compact_state_add_key(message)?;
-            if let Some(compact_state_message_bytes) = self.db.get(&compact_state_key)? {
+            if let Some(compact_state_message_bytes) =
+                self.db.get_for_update(txn, &compact_state_key)?
+            {
                 let compact_state_message = message_decode(compact_state_message_bytes.as_ref())?;
 
                 let (_, compact_state_timestamp, target_fids) =
@@ -866,44 +1105,52 @@ impl Store {
         // Get the merge conflicts first
         let merge_conflicts = self
             .store_def
-            .get_merge_conflicts(&self.db, message, ts_hash)?;
+            .get_merge_conflicts(&self.db, txn, message, ts_hash)?;
 
-        // start a transaction
-        let mut txn = self.db.txn();
         // Delete all the merge conflicts
-        self.delete_many_transaction(&mut txn, &merge_conflicts)?;
+        self.delete_many_transaction(txn, &merge_conflicts)?;
 
         // Add ops to store the message by messageKey and index the messageKey by set and by target
-        self.put_add_transaction(&mut txn, &ts_hash, message)?;
+        self.put_add_transaction(txn, ts_hash, message)?;
 
-        // Event handler
-        let mut hub_event = self.store_def.merge_event_args(message, merge_conflicts);
+        Ok(merge_conflicts)
+    }
 
-        let id = self
-            .store_event_handler
-            .commit_transaction(&mut txn, &mut hub_event)?;
+    pub fn merge_remove(
+        &self,
+        ts_hash: &[u8; TS_HASH_LENGTH],
+        message: &Message,
+    ) -> Result<Vec<u8>, HubError> {
+        let _compact_state_guard = self.compact_state_lock.read().unwrap();
+        let mut txn = self.db.txn();
+        let merge_conflicts = self.merge_remove_transaction(&mut txn, ts_hash, message)?;
+        let hub_event = self.commit_merge_event(&mut txn, message, merge_conflicts)?;
 
         // Commit the transaction
         self.db.commit(txn)?;
 
-        hub_event.id = id;
         // Serialize the hub_event
-        let hub_event_bytes = hub_event.encode_to_vec();
-
-        Ok(hub_event_bytes)
+        Ok(hub_event.encode_to_vec())
     }
 
-    pub fn merge_remove(
+    /**
+     * Add the writes of merging a remove to `txn`, and return the messages it deletes. The caller
+     * holds compact_state_lock.
+     */
+    fn merge_remove_transaction(
         &self,
+        txn: &mut RocksDbTransactionBatch,
         ts_hash: &[u8; TS_HASH_LENGTH],
         message: &Message,
-    ) -> Result<Vec<u8>, HubError> {
+    ) -> Result<Vec<Message>, HubError> {
         // If the store supports compact state messages, we don't merge remove messages before its timestamp
         // If the store supports compact state messages, we don't merge messages that don't exist in the compact state
         if self.store_def.compact_state_type_supported() {
             // Get the compact state message
             let compact_state_key = self.store_def.make_compact_state_add_key(message)?;
-            if let Some(compact_state_message_bytes) = self.db.get(&compact_state_key)? {
+            if let Some(compact_state_message_bytes) =
+                self.db.get_for_update(txn, &compact_state_key)?
+            {
                 let compact_state_message = message_decode(compact_state_message_bytes.as_ref())?;
 
                 let (_, compact_state_timestamp, _) =
@@ -924,32 +1171,15 @@ impl Store {
         // Get the merge conflicts first
         let merge_conflicts = self
             .store_def
-            .get_merge_conflicts(&self.db, message, ts_hash)?;
-
-        // start a transaction
-        let mut txn = self.db.txn();
+            .get_merge_conflicts(&self.db, txn, message, ts_hash)?;
 
         // Delete all the merge conflicts
-        self.delete_many_transaction(&mut txn, &merge_conflicts)?;
+        self.delete_many_transaction(txn, &merge_conflicts)?;
 
         // Add ops to store the message by messageKey and index the messageKey by set and by target
-        self.put_remove_transaction(&mut txn, ts_hash, message)?;
-
-        // Event handler
-        let mut hub_event = self.store_def.merge_event_args(message, merge_conflicts);
-
-        let id = self
-            .store_event_handler
-            .commit_transaction(&mut txn, &mut hub_event)?;
-
-        // Commit the transaction
-        self.db.commit(txn)?;
-
-        hub_event.id = id;
-        // Serialize the hub_event
-        let hub_event_bytes = hub_event.encode_to_vec();
+        self.put_remove_transaction(txn, ts_hash, message)?;
 
-        Ok(hub_event_bytes)
+        Ok(merge_conflicts)
     }
 
     fn prune_messages(
@@ -1079,7 +1309,7 @@ impl Store {
             })
         } else {
             let m = message.unwrap();
-            store.merge(&m)
+            store.merge(&m, WritePriority::High)
         };
 
         let channel = cx.channel();
@@ -1098,6 +1328,7 @@ impl Store {
         Ok(promise)
     }
 
+    /** Merge an array of messages, with an optional "high" (the default) or "low" write priority */
     pub fn js_merge_many(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let store = get_store(&mut cx)?;
 
@@ -1119,17 +1350,37 @@ impl Store {
             })
             .collect::<Vec<_>>();
 
+        let priority = match cx.argument_opt(1) {
+            Some(arg) if arg.is_a::<JsString, _>(&mut cx) => {
+                let name = arg
+                    .downcast_or_throw::<JsString, _>(&mut cx)?
+                    .value(&mut cx);
+                match WritePriority::parse(&name) {
+                    Ok(priority) => priority,
+                    Err(e) => return hub_error_to_js_throw(&mut cx, e),
+                }
+            }
+            _ => WritePriority::High,
+        };
+
         let channel = cx.channel();
         let (deferred, promise) = cx.promise();
 
         // We run the merge in a threadpool because it can be very CPU intensive and it will block
         // the NodeJS main thread.
         THREAD_POOL.lock().unwrap().execute(move || {
-            let results = messages
+            // The messages that decoded are merged together, the others keep their errors
+            let mut decoded = vec![];
+            let decode_errors = messages
+                .into_iter()
+                .map(|message| message.map(|message| decoded.push(message)).err())
+                .collect::<Vec<_>>();
+            let mut merged = store.merge_many(&decoded, priority).into_iter();
+            let results = decode_errors
                 .into_iter()
-                .map(|message| match message {
-                    Err(e) => return Err(e),
-                    Ok(message) => store.merge(&message),
+                .map(|error| match error {
+                    Some(e) => Err(e),
+                    None => merged.next().unwrap(),
                 })
                 .collect::<Vec<_>>();
 
@@ -1157,6 +1408,78 @@ impl Store {
         Ok(promise)
     }
 
+    /**
+     * Resolves to what merging the message would do, as { mergeConflicts, hubEvent } with the
+     * encoded messages it would delete and the encoded HubEvent, or rejects with the merge error.
+     */
+    pub fn js_simulate_merge(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let store = get_store(&mut cx)?;
+
+        let message_bytes = cx.argument::<JsBuffer>(0)?;
+        let message = Message::decode(message_bytes.as_slice(&cx)).map_err(|e| HubError {
+            code: "bad_request.validation_failure".to_string(),
+            message: e.to_string(),
+        });
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+
+        // A compact state message reads all the messages of its fid
+        THREAD_POOL.lock().unwrap().execute(move || {
+            let result = message.and_then(|message| store.simulate_merge(&message));
+
+            deferred.settle_with(&channel, move |mut cx| {
+                let simulation = match result {
+                    Ok(simulation) => simulation,
+                    Err(e) => return hub_error_to_js_throw(&mut cx, e),
+                };
+
+                let js_conflicts = JsArray::new(&mut cx, simulation.merge_conflicts.len());
+                for (i, message) in simulation.merge_conflicts.iter().enumerate() {
+                    let message_bytes = message.encode_to_vec();
+                    let mut js_buffer = cx.buffer(message_bytes.len())?;
+                    js_buffer
+                        .as_mut_slice(&mut cx)
+                        .copy_from_slice(&message_bytes);
+                    js_conflicts.set(&mut cx, i as u32, js_buffer)?;
+                }
+
+                let hub_event_bytes = simulation.hub_event.encode_to_vec();
+                let mut js_hub_event = cx.buffer(hub_event_bytes.len())?;
+                js_hub_event
+                    .as_mut_slice(&mut cx)
+                    .copy_from_slice(&hub_event_bytes);
+
+                let js_result = cx.empty_object();
+                js_result.set(&mut cx, "mergeConflicts", js_conflicts)?;
+                js_result.set(&mut cx, "hubEvent", js_hub_event)?;
+
+                Ok(js_result)
+            });
+        });
+
+        Ok(promise)
+    }
+
+    /** Validate merged messages for the network given as a number, 0 turns validation off */
+    pub fn js_set_merge_validation(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let store = get_store(&mut cx)?;
+
+        let network = cx.argument::<JsNumber>(0)?.value(&mut cx) as i32;
+        match FarcasterNetwork::try_from(network) {
+            Ok(FarcasterNetwork::None) => store.set_merge_validation(None),
+            Ok(network) => store.set_merge_validation(Some(network)),
+            Err(_) => {
+                return hub_error_to_js_throw(
+                    &mut cx,
+                    HubError::invalid_parameter("invalid network"),
+                )
+            }
+        }
+
+        Ok(cx.undefined())
+    }
+
     pub fn js_revoke(mut cx: FunctionContext) -> JsResult<JsPromise> {
         let store = get_store(&mut cx)?;
 
@@ -17,6 +17,11 @@ fn make_event_id(timestamp: u64, seq: u64) -> u64 {
     shifted_timestamp | padded_seq
 }
 
+/** The lowest event id that can be generated at `timestamp`, in ms since the Farcaster epoch */
+pub fn first_event_id_at(timestamp: u64) -> u64 {
+    make_event_id(timestamp, 0)
+}
+
 struct HubEventIdGenerator {
     last_timestamp: u64, // ms since epoch
     last_seq: u64,
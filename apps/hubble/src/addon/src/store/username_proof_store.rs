@@ -1,5 +1,5 @@
 use super::{
-    get_message, hub_error_to_js_throw, make_fid_key, make_user_key, read_fid_key,
+    get_message_for_update, hub_error_to_js_throw, make_fid_key, make_user_key, read_fid_key,
     store::{Store, StoreDef},
     utils::{self, encode_messages_to_js_object, get_page_options, get_store},
     HubError, IntoU8, MessagesPage, PageOptions, RootPrefix, StoreEventHandler, UserPostfix,
@@ -185,6 +185,7 @@ impl StoreDef for UsernameProofStoreDef {
     fn get_merge_conflicts(
         &self,
         db: &RocksDB,
+        txn: &mut RocksDbTransactionBatch,
         message: &Message,
         ts_hash: &[u8; TS_HASH_LENGTH],
     ) -> Result<Vec<Message>, HubError> {
@@ -209,14 +210,17 @@ impl StoreDef for UsernameProofStoreDef {
         let mut conflicts = Vec::new();
         let by_name_key = Self::make_username_proof_by_name_key(name);
 
-        let fid_result = db.get(by_name_key.as_slice());
+        let fid_result = db.get_for_update(txn, by_name_key.as_slice());
         if let Ok(Some(fid_bytes)) = fid_result {
             let fid = read_fid_key(&fid_bytes);
             if fid > 0 {
                 let existing_add_key = Self::make_username_proof_by_fid_key(fid, name);
-                if let Ok(existing_message_ts_hash) = db.get(existing_add_key.as_slice()) {
-                    if let Ok(Some(existing_message)) = get_message(
+                if let Ok(existing_message_ts_hash) =
+                    db.get_for_update(txn, existing_add_key.as_slice())
+                {
+                    if let Ok(Some(existing_message)) = get_message_for_update(
                         db,
+                        txn,
                         fid,
                         self.postfix(),
                         &utils::vec_to_u8_24(&existing_message_ts_hash)?,
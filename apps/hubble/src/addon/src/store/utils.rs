@@ -1,15 +1,20 @@
 use super::{HubError, MessagesPage, PageOptions, Store, FARCASTER_EPOCH};
 use crate::{
-    db::{JsIteratorOptions, RocksDB},
+    db::{
+        DbOpenMode, JsIteratorOptions, ReadSnapshot, RocksDB, RocksDbOptions, SnapshotOptions,
+        SnapshotRetention,
+    },
     trie::merkle_trie::{MerkleTrie, NodeMetadata},
 };
 use neon::{
     context::{Context, FunctionContext, TaskContext},
     event::Channel,
+    handle::Handle,
     object::Object,
     result::{JsResult, Throw},
     types::{
         buffer::TypedArray, Deferred, JsArray, JsBoolean, JsBox, JsBuffer, JsNumber, JsObject,
+        JsString,
     },
 };
 use std::{borrow::Borrow, sync::Arc};
@@ -191,6 +196,124 @@ pub fn get_page_options(cx: &mut FunctionContext, at: usize) -> Result<PageOptio
     })
 }
 
+/** The optional number property `key` of `js_object` */
+fn get_number(
+    cx: &mut FunctionContext,
+    js_object: Handle<JsObject>,
+    key: &str,
+) -> Result<Option<f64>, Throw> {
+    Ok(js_object
+        .get_opt::<JsNumber, _, _>(cx, key)?
+        .map(|v| v.value(cx)))
+}
+
+/**
+ * Extract the RocksDB tuning options from an optional JavaScript object at the given index. Any
+ * option that is not provided keeps its default.
+ */
+pub fn get_db_options(cx: &mut FunctionContext, at: usize) -> Result<RocksDbOptions, Throw> {
+    let js_object = match cx.argument_opt(at) {
+        Some(arg) if arg.is_a::<JsObject, _>(cx) => arg.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(RocksDbOptions::default()),
+    };
+
+    let compression_per_level =
+        match js_object.get_opt::<JsArray, _, _>(cx, "compressionPerLevel")? {
+            Some(js_array) => {
+                let mut levels = vec![];
+                for i in 0..js_array.len(cx) {
+                    let level = js_array.get::<JsString, _, _>(cx, i)?.value(cx);
+                    levels.push(level);
+                }
+                Some(levels)
+            }
+            None => None,
+        };
+
+    let mode = match js_object.get_opt::<JsString, _, _>(cx, "mode")? {
+        Some(mode) => match mode.value(cx).as_str() {
+            "primary" => DbOpenMode::Primary,
+            "readOnly" => DbOpenMode::ReadOnly,
+            "secondary" => DbOpenMode::Secondary {
+                secondary_path: js_object
+                    .get_opt::<JsString, _, _>(cx, "secondaryPath")?
+                    .map(|v| v.value(cx))
+                    .unwrap_or_default(),
+            },
+            mode => {
+                return hub_error_to_js_throw(
+                    cx,
+                    HubError::invalid_parameter(&format!("unknown mode: {}", mode)),
+                )
+            }
+        },
+        None => DbOpenMode::Primary,
+    };
+
+    Ok(RocksDbOptions {
+        block_cache_size: get_number(cx, js_object, "blockCacheSize")?.map(|v| v as usize),
+        write_buffer_size: get_number(cx, js_object, "writeBufferSize")?.map(|v| v as usize),
+        max_write_buffer_number: get_number(cx, js_object, "maxWriteBufferNumber")?
+            .map(|v| v as i32),
+        max_background_jobs: get_number(cx, js_object, "maxBackgroundJobs")?.map(|v| v as i32),
+        compression_per_level,
+        bloom_filter_bits_per_key: get_number(cx, js_object, "bloomFilterBitsPerKey")?,
+        rate_limit_bytes_per_sec: get_number(cx, js_object, "rateLimitBytesPerSec")?
+            .map(|v| v as i64),
+        level0_slowdown_writes_trigger: get_number(cx, js_object, "level0SlowdownWritesTrigger")?
+            .map(|v| v as i32),
+        level0_stop_writes_trigger: get_number(cx, js_object, "level0StopWritesTrigger")?
+            .map(|v| v as i32),
+        hub_events_retention_ms: get_number(cx, js_object, "hubEventsRetentionMs")?
+            .map(|v| v as u64),
+        enable_statistics: js_object
+            .get_opt::<JsBoolean, _, _>(cx, "enableStatistics")?
+            .map(|v| v.value(cx)),
+        mode,
+    })
+}
+
+/**
+ * Extract the snapshot options from an optional JavaScript object at the given index, eg.
+ * { codec: "zstd", compressionLevel: 19, numThreads: 8, chunkSize: 1073741824 }
+ */
+pub fn get_snapshot_options(cx: &mut FunctionContext, at: usize) -> Result<SnapshotOptions, Throw> {
+    let js_object = match cx.argument_opt(at) {
+        Some(arg) if arg.is_a::<JsObject, _>(cx) => arg.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(SnapshotOptions::default()),
+    };
+
+    let codec = js_object
+        .get_opt::<JsString, _, _>(cx, "codec")?
+        .map(|v| v.value(cx));
+
+    Ok(SnapshotOptions {
+        codec,
+        compression_level: get_number(cx, js_object, "compressionLevel")?.map(|v| v as i32),
+        num_threads: get_number(cx, js_object, "numThreads")?.map(|v| v as usize),
+        chunk_size: get_number(cx, js_object, "chunkSize")?.map(|v| v as usize),
+    })
+}
+
+/**
+ * Read the optional { keepLast, maxAgeMs, maxBytes } retention policy at `at`
+ */
+pub fn get_snapshot_retention(
+    cx: &mut FunctionContext,
+    at: usize,
+) -> Result<SnapshotRetention, Throw> {
+    let js_object = match cx.argument_opt(at) {
+        Some(arg) if arg.is_a::<JsObject, _>(cx) => arg.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(SnapshotRetention::default()),
+    };
+
+    Ok(SnapshotRetention {
+        keep_last: get_number(cx, js_object, "keepLast")?.map(|v| v as usize),
+        max_age_ms: get_number(cx, js_object, "maxAgeMs")?.map(|v| v as u64),
+        max_bytes: get_number(cx, js_object, "maxBytes")?.map(|v| v as u64),
+    })
+}
+
 /**
  * Extract the iterator opts
  */
@@ -235,6 +358,20 @@ pub fn get_db(cx: &mut FunctionContext) -> Result<Arc<RocksDB>, Throw> {
     Ok((**db_js_box.borrow()).clone())
 }
 
+/** The optional read snapshot from dbCreateReadSnapshot at `at` */
+pub fn get_read_snapshot(
+    cx: &mut FunctionContext,
+    at: usize,
+) -> Result<Option<Arc<ReadSnapshot>>, Throw> {
+    match cx.argument_opt(at) {
+        Some(arg) if arg.is_a::<JsBox<Arc<ReadSnapshot>>, _>(cx) => {
+            let snapshot = arg.downcast_or_throw::<JsBox<Arc<ReadSnapshot>>, _>(cx)?;
+            Ok(Some((**snapshot).clone()))
+        }
+        _ => Ok(None),
+    }
+}
+
 /** Get the merkle trie object */
 pub fn get_merkle_trie(cx: &mut FunctionContext) -> Result<Arc<MerkleTrie>, Throw> {
     let merkle_trie_js_box = cx.this::<JsBox<Arc<MerkleTrie>>>()?;
@@ -0,0 +1,1000 @@
+use super::{
+    blake3_20, get_farcaster_time, hub_error_to_js_throw, to_farcaster_time, HubError, HASH_LENGTH,
+};
+use crate::ed25519::ed25519_verify_hash;
+use crate::protos::{
+    self, cast_add_body, embed, link_body, message_data::Body, reaction_body, CastAddBody, CastId,
+    CastType, FarcasterNetwork, FrameActionBody, HashScheme, LinkBody, LinkCompactStateBody,
+    Message, MessageData, MessageType, Protocol, ReactionBody, ReactionType, SignatureScheme,
+    UserDataBody, UserDataType, UserNameProof, UserNameType, VerificationAddAddressBody,
+    VerificationRemoveBody,
+};
+use crate::THREAD_POOL;
+use neon::context::{Context, FunctionContext};
+use neon::result::JsResult;
+use neon::types::buffer::TypedArray;
+use neon::types::{JsBuffer, JsNumber, JsPromise};
+use prost::Message as _;
+
+/** Number of seconds (10 minutes) that is appropriate for clock skew */
+pub const ALLOWED_CLOCK_SKEW_SECONDS: u64 = 10 * 60;
+
+/** 5/3/23 00:00 UTC. Casts from before it may still use string embeds */
+pub const EMBEDS_V1_CUTOFF: u32 = 73612800;
+
+/** The longest ENS name a username can be set to */
+pub const USERNAME_MAX_LENGTH: usize = 20;
+
+const MAX_DATA_BYTES: usize = 2048;
+
+// The regexes of the JS validator, which are part of its error messages
+const FNAME_REGEX: &str = "/^[a-z0-9][a-z0-9-]{0,15}$/";
+const TWITTER_REGEX: &str = "/^[a-z0-9_]{0,15}$/";
+const GITHUB_REGEX: &str = "/^[a-z\\d](?:[a-z\\d]|-(?!-)){0,38}$/i";
+
+/**
+ * Validate `message` the way the JS validateMessage does, after the network check of the
+ * engine: the message data and its body first, then the hash and the signature. The errors
+ * have the same codes and messages as the JS ones. Verification claim signatures are only
+ * checked for their length, see verify_verification_claim.
+ */
+pub fn validate_message(message: &Message, network: FarcasterNetwork) -> Result<(), HubError> {
+    let data = match &message.data {
+        Some(data) => data,
+        None => return Err(HubError::validation_failure("data is missing")),
+    };
+
+    if data.network != network as i32 {
+        return Err(HubError::validation_failure(&format!(
+            "incorrect network: {} (expected: {})",
+            data.network, network as i32
+        )));
+    }
+
+    validate_message_data(data)?;
+
+    // The signature is of the hash of the data_bytes if they are set, otherwise of the encoded data
+    let computed_hash = match &message.data_bytes {
+        Some(data_bytes) if !data_bytes.is_empty() => {
+            if data_bytes.len() > MAX_DATA_BYTES {
+                return Err(HubError::validation_failure("dataBytes > 2048 bytes"));
+            }
+            blake3_20(data_bytes)
+        }
+        _ => blake3_20(&data.encode_to_vec()),
+    };
+
+    if message.hash_scheme != HashScheme::Blake3 as i32 {
+        return Err(HubError::validation_failure("invalid hashScheme"));
+    }
+    if message.hash != computed_hash {
+        return Err(HubError::validation_failure(&format!(
+            "invalid hash. Expected={}, computed={}",
+            join_bytes(&message.hash),
+            join_bytes(&computed_hash)
+        )));
+    }
+
+    if message.signature_scheme != SignatureScheme::Ed25519 as i32 {
+        return Err(HubError::validation_failure("invalid signatureScheme"));
+    }
+    if !ed25519_verify_hash(&message.signature, &message.hash, &message.signer) {
+        return Err(HubError::validation_failure("invalid signature"));
+    }
+
+    Ok(())
+}
+
+/** Bytes the way JS prints a Uint8Array */
+fn join_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/** The length of `s` in UTF-16 code units, which is what JS counts */
+fn js_length(s: &str) -> usize {
+    s.encode_utf16().count()
+}
+
+fn validate_message_data(data: &MessageData) -> Result<(), HubError> {
+    validate_fid(data.fid)?;
+
+    if data.timestamp as u64 > get_farcaster_time()? + ALLOWED_CLOCK_SKEW_SECONDS {
+        return Err(HubError::validation_failure(
+            "timestamp more than 10 mins in the future",
+        ));
+    }
+
+    let message_type = MessageType::try_from(data.r#type)
+        .map_err(|_| HubError::validation_failure("invalid message type"))?;
+
+    let body = match &data.body {
+        Some(body) => body,
+        None => return Err(HubError::validation_failure("only one body can be set")),
+    };
+
+    match (message_type, body) {
+        (MessageType::CastAdd, Body::CastAddBody(body)) => {
+            validate_cast_add_body(body, data.timestamp < EMBEDS_V1_CUTOFF)
+        }
+        (MessageType::CastRemove, Body::CastRemoveBody(body)) => {
+            validate_message_hash(&body.target_hash)
+        }
+        (MessageType::ReactionAdd | MessageType::ReactionRemove, Body::ReactionBody(body)) => {
+            validate_reaction_body(body)
+        }
+        (MessageType::LinkCompactState, Body::LinkCompactStateBody(body)) => {
+            validate_link_compact_state_body(body)
+        }
+        (MessageType::LinkAdd | MessageType::LinkRemove, Body::LinkBody(body)) => {
+            validate_link_body(body)
+        }
+        (MessageType::UserDataAdd, Body::UserDataBody(body)) => validate_user_data_add_body(body),
+        (MessageType::VerificationAddEthAddress, Body::VerificationAddAddressBody(body)) => {
+            validate_verification_add_address_body(body)
+        }
+        (MessageType::VerificationRemove, Body::VerificationRemoveBody(body)) => {
+            validate_verification_remove_body(body)
+        }
+        (MessageType::UsernameProof, Body::UsernameProofBody(body)) => {
+            validate_username_proof_body(body, data)
+        }
+        (MessageType::FrameAction, Body::FrameActionBody(body)) => validate_frame_action_body(body),
+        _ => Err(HubError::invalid_parameter("bodyType is invalid")),
+    }
+}
+
+fn validate_fid(fid: u64) -> Result<(), HubError> {
+    if fid == 0 {
+        return Err(HubError::validation_failure("fid is missing"));
+    }
+
+    Ok(())
+}
+
+fn validate_message_hash(hash: &[u8]) -> Result<(), HubError> {
+    if hash.is_empty() {
+        return Err(HubError::validation_failure("hash is missing"));
+    }
+    if hash.len() != HASH_LENGTH {
+        return Err(HubError::validation_failure("hash must be 20 bytes"));
+    }
+
+    Ok(())
+}
+
+/** Reports the errors of the fid and the hash together, like the JS validateCastId */
+fn validate_cast_id(cast_id: &CastId) -> Result<(), HubError> {
+    let errors = [
+        validate_fid(cast_id.fid),
+        validate_message_hash(&cast_id.hash),
+    ]
+    .into_iter()
+    .filter_map(|result| result.err().map(|e| e.message))
+    .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(HubError::validation_failure(&errors.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn validate_url(url: &str) -> Result<(), HubError> {
+    if url.is_empty() {
+        return Err(HubError::invalid_parameter("url < 1 byte"));
+    }
+    if url.len() > 256 {
+        return Err(HubError::invalid_parameter("url > 256 bytes"));
+    }
+
+    Ok(())
+}
+
+fn validate_embed(embed: &protos::Embed) -> Result<(), HubError> {
+    match &embed.embed {
+        Some(embed::Embed::Url(url)) => validate_url(url),
+        Some(embed::Embed::CastId(cast_id)) => validate_cast_id(cast_id),
+        None => Err(HubError::validation_failure(
+            "embed must have either url or castId",
+        )),
+    }
+}
+
+fn validate_cast_add_body(
+    body: &CastAddBody,
+    allow_embeds_deprecated: bool,
+) -> Result<(), HubError> {
+    let text_length = body.text.len();
+    if body.r#type == CastType::Cast as i32 && text_length > 320 {
+        return Err(HubError::validation_failure("text > 320 bytes"));
+    }
+    if body.r#type == CastType::LongCast as i32 {
+        if text_length > 1024 {
+            return Err(HubError::validation_failure(
+                "text > 1024 bytes for long cast",
+            ));
+        }
+        if text_length <= 320 {
+            return Err(HubError::validation_failure("text too short for long cast"));
+        }
+    }
+    if CastType::try_from(body.r#type).is_err() {
+        return Err(HubError::validation_failure("invalid cast type"));
+    }
+
+    if body.embeds.len() > 2 {
+        return Err(HubError::validation_failure("embeds > 2"));
+    }
+    if allow_embeds_deprecated && body.embeds_deprecated.len() > 2 {
+        return Err(HubError::validation_failure("string embeds > 2"));
+    }
+    if !allow_embeds_deprecated && !body.embeds_deprecated.is_empty() {
+        return Err(HubError::validation_failure(
+            "string embeds have been deprecated",
+        ));
+    }
+
+    if body.mentions.len() > 10 {
+        return Err(HubError::validation_failure("mentions > 10"));
+    }
+    if body.mentions.len() != body.mentions_positions.len() {
+        return Err(HubError::validation_failure(
+            "mentions and mentionsPositions must match",
+        ));
+    }
+
+    if !body.embeds.is_empty() && !body.embeds_deprecated.is_empty() {
+        return Err(HubError::validation_failure(
+            "cannot use both embeds and string embeds",
+        ));
+    }
+
+    if body.text.is_empty()
+        && body.embeds.is_empty()
+        && body.embeds_deprecated.is_empty()
+        && body.mentions.is_empty()
+    {
+        return Err(HubError::validation_failure("cast is empty"));
+    }
+
+    for embed in &body.embeds {
+        validate_embed(embed)?;
+    }
+    for embed in &body.embeds_deprecated {
+        validate_url(embed)?;
+    }
+
+    for (i, (mention, position)) in body
+        .mentions
+        .iter()
+        .zip(&body.mentions_positions)
+        .enumerate()
+    {
+        validate_fid(*mention)?;
+        if *position as usize > text_length {
+            return Err(HubError::validation_failure(
+                "mentionsPositions must be a position in text",
+            ));
+        }
+        if i > 0 && *position < body.mentions_positions[i - 1] {
+            return Err(HubError::validation_failure(
+                "mentionsPositions must be sorted in ascending order",
+            ));
+        }
+    }
+
+    match &body.parent {
+        Some(cast_add_body::Parent::ParentUrl(url)) => validate_url(url),
+        Some(cast_add_body::Parent::ParentCastId(cast_id)) => validate_cast_id(cast_id),
+        None => Ok(()),
+    }
+}
+
+fn validate_link_type(link_type: &str) -> Result<(), HubError> {
+    if link_type.is_empty() || link_type.len() > 8 {
+        return Err(HubError::validation_failure(
+            "type must be between 1-8 bytes",
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_link_body(body: &LinkBody) -> Result<(), HubError> {
+    validate_link_type(&body.r#type)?;
+
+    match &body.target {
+        Some(link_body::Target::TargetFid(fid)) => validate_fid(*fid),
+        None => Err(HubError::validation_failure("target is missing")),
+    }
+}
+
+fn validate_link_compact_state_body(body: &LinkCompactStateBody) -> Result<(), HubError> {
+    validate_link_type(&body.r#type)?;
+
+    for fid in &body.target_fids {
+        validate_fid(*fid)?;
+    }
+
+    Ok(())
+}
+
+fn validate_reaction_body(body: &ReactionBody) -> Result<(), HubError> {
+    if ReactionType::try_from(body.r#type).is_err() {
+        return Err(HubError::validation_failure("invalid reaction type"));
+    }
+
+    match &body.target {
+        Some(reaction_body::Target::TargetCastId(cast_id)) => validate_cast_id(cast_id),
+        Some(reaction_body::Target::TargetUrl(url)) => validate_url(url),
+        None => Err(HubError::validation_failure("target is missing")),
+    }
+}
+
+pub(super) fn validate_eth_address(address: &[u8]) -> Result<(), HubError> {
+    if address.is_empty() {
+        return Err(HubError::validation_failure("Ethereum address is missing"));
+    }
+    if address.len() != 20 {
+        return Err(HubError::validation_failure(
+            "Ethereum address must be 20 bytes",
+        ));
+    }
+
+    Ok(())
+}
+
+pub(super) fn validate_sol_address(address: &[u8]) -> Result<(), HubError> {
+    if address.is_empty() {
+        return Err(HubError::validation_failure("solana address is missing"));
+    }
+    if address.len() != 32 {
+        return Err(HubError::validation_failure(
+            "solana address must be 32 bytes",
+        ));
+    }
+
+    Ok(())
+}
+
+pub(super) fn validate_block_hash(block_hash: &[u8]) -> Result<(), HubError> {
+    if block_hash.is_empty() {
+        return Err(HubError::validation_failure("blockHash is missing"));
+    }
+    if block_hash.len() != 32 {
+        return Err(HubError::validation_failure("blockHash must be 32 bytes"));
+    }
+
+    Ok(())
+}
+
+fn validate_verification_add_address_body(
+    body: &VerificationAddAddressBody,
+) -> Result<(), HubError> {
+    match Protocol::try_from(body.protocol) {
+        Ok(Protocol::Ethereum) => {
+            validate_eth_address(&body.address)?;
+            validate_block_hash(&body.block_hash)?;
+            if body.claim_signature.len() > 2048 {
+                return Err(HubError::validation_failure("claimSignature > 2048 bytes"));
+            }
+        }
+        Ok(Protocol::Solana) => {
+            if validate_sol_address(&body.address).is_err() {
+                return Err(HubError::validation_failure(
+                    "solana address must be 32 bytes",
+                ));
+            }
+            if validate_block_hash(&body.block_hash).is_err() {
+                return Err(HubError::validation_failure("blockHash must be 32 bytes"));
+            }
+            if body.claim_signature.len() != 64 {
+                return Err(HubError::validation_failure("claimSignature != 64 bytes"));
+            }
+        }
+        Err(_) => {
+            return Err(HubError::validation_failure(
+                "invalid verification protocol",
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_verification_remove_body(body: &VerificationRemoveBody) -> Result<(), HubError> {
+    match Protocol::try_from(body.protocol) {
+        Ok(Protocol::Ethereum) => validate_eth_address(&body.address),
+        Ok(Protocol::Solana) => validate_sol_address(&body.address),
+        Err(_) => Err(HubError::validation_failure(
+            "invalid verification protocol",
+        )),
+    }
+}
+
+fn validate_username_proof_body(body: &UserNameProof, data: &MessageData) -> Result<(), HubError> {
+    // Gossiped username proofs must only have an ENS type
+    if body.r#type != UserNameType::UsernameTypeEnsL1 as i32 {
+        return Err(HubError::validation_failure(&format!(
+            "invalid username type: {}",
+            body.r#type
+        )));
+    }
+
+    let name = std::str::from_utf8(&body.name)
+        .map_err(|_| HubError::validation_failure("ensName is not valid utf8"))?;
+    validate_ens_name(name)?;
+
+    if body.fid != data.fid {
+        return Err(HubError::validation_failure(
+            "fid in username proof does not match fid in message data",
+        ));
+    }
+
+    // Proof time is in Unix seconds
+    if to_farcaster_time(body.timestamp.saturating_mul(1000))? != data.timestamp as u64 {
+        return Err(HubError::validation_failure(
+            "timestamp in username proof does not match timestamp in message data",
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_frame_action_body(body: &FrameActionBody) -> Result<(), HubError> {
+    // url and buttonId are required and must not exceed the length limits. cast id is optional
+    if body.button_index > 5 {
+        return Err(HubError::validation_failure("invalid button index"));
+    }
+
+    if body.url.is_empty() || body.url.len() > 1024 {
+        return Err(HubError::validation_failure("invalid url"));
+    }
+    if body.input_text.len() > 256 {
+        return Err(HubError::validation_failure("invalid input text"));
+    }
+    if body.state.len() > 4096 {
+        return Err(HubError::validation_failure("invalid state"));
+    }
+    if body.transaction_id.len() > 256 {
+        return Err(HubError::validation_failure("invalid transaction ID"));
+    }
+    if body.address.len() > 64 {
+        return Err(HubError::validation_failure("invalid address"));
+    }
+
+    match &body.cast_id {
+        Some(cast_id) => validate_cast_id(cast_id),
+        None => Ok(()),
+    }
+}
+
+fn validate_user_data_add_body(body: &UserDataBody) -> Result<(), HubError> {
+    let value = body.value.as_str();
+
+    match UserDataType::try_from(body.r#type) {
+        Ok(UserDataType::Pfp) if value.len() > 256 => {
+            Err(HubError::validation_failure("pfp value > 256"))
+        }
+        Ok(UserDataType::Display) if value.len() > 32 => {
+            Err(HubError::validation_failure("display value > 32"))
+        }
+        Ok(UserDataType::Bio) if value.len() > 256 => {
+            Err(HubError::validation_failure("bio value > 256"))
+        }
+        Ok(UserDataType::Url) if value.len() > 256 => {
+            Err(HubError::validation_failure("url value > 256"))
+        }
+        Ok(UserDataType::Pfp | UserDataType::Display | UserDataType::Bio | UserDataType::Url) => {
+            Ok(())
+        }
+        // An empty username removes the fname, otherwise it has to be a valid fname or ENS name
+        Ok(UserDataType::Username) => match validate_fname(value) {
+            Err(e) if !value.is_empty() && validate_ens_name(value).is_err() => Err(e),
+            _ => Ok(()),
+        },
+        Ok(UserDataType::Location) => validate_user_location(value),
+        Ok(UserDataType::Twitter) => validate_twitter_username(value),
+        Ok(UserDataType::Github) => validate_github_username(value),
+        Ok(UserDataType::None) | Err(_) => {
+            Err(HubError::validation_failure("invalid user data type"))
+        }
+    }
+}
+
+/** Whether `label` matches FNAME_REGEX */
+fn is_fname_label(label: &str) -> bool {
+    let bytes = label.as_bytes();
+    !bytes.is_empty()
+        && bytes.len() <= 16
+        && (bytes[0].is_ascii_lowercase() || bytes[0].is_ascii_digit())
+        && bytes[1..]
+            .iter()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || *b == b'-')
+}
+
+fn validate_fname(fname: &str) -> Result<(), HubError> {
+    if fname.is_empty() {
+        return Err(HubError::validation_failure("fname is missing"));
+    }
+
+    if js_length(fname) > 16 {
+        return Err(HubError::validation_failure(&format!(
+            "fname \"{}\" > 16 characters",
+            fname
+        )));
+    }
+
+    if !is_fname_label(fname) {
+        return Err(HubError::validation_failure(&format!(
+            "fname \"{}\" doesn't match {}",
+            fname, FNAME_REGEX
+        )));
+    }
+
+    Ok(())
+}
+
+/**
+ * Whether `name` survives ENS normalization. Only the ASCII rules of ENSIP-15 are applied:
+ * labels must not be empty, may only use letters, digits, hyphens and leading underscores, and
+ * must not have hyphens as their 3rd and 4th characters. Names with other characters never
+ * match FNAME_REGEX, so for them only the error message can differ from the JS validator.
+ */
+fn is_normalizable_ens_name(name: &str) -> bool {
+    name.split('.').all(|label| {
+        let bytes = label.as_bytes();
+        let leading_underscores = bytes.iter().take_while(|b| **b == b'_').count();
+
+        !label.is_empty()
+            && bytes[leading_underscores..]
+                .iter()
+                .all(|b| !b.is_ascii() || b.is_ascii_alphanumeric() || *b == b'-')
+            && !(label.is_ascii() && bytes.len() >= 4 && &bytes[2..4] == b"--")
+    })
+}
+
+fn validate_ens_name(ens_name: &str) -> Result<(), HubError> {
+    if ens_name.is_empty() {
+        return Err(HubError::validation_failure("ensName is missing"));
+    }
+
+    if !is_normalizable_ens_name(ens_name) {
+        return Err(HubError::validation_failure(&format!(
+            "ensName \"{}\" is not a valid ENS name",
+            ens_name
+        )));
+    }
+
+    if !ens_name.ends_with(".eth") {
+        return Err(HubError::validation_failure(&format!(
+            "ensName \"{}\" doesn't end with .eth",
+            ens_name
+        )));
+    }
+
+    let name_parts = ens_name.split('.').collect::<Vec<_>>();
+    if name_parts.len() != 2 {
+        return Err(HubError::validation_failure(&format!(
+            "ensName \"{}\" unsupported subdomain",
+            ens_name
+        )));
+    }
+
+    if js_length(ens_name) > USERNAME_MAX_LENGTH {
+        return Err(HubError::validation_failure(&format!(
+            "ensName \"{}\" > 20 characters",
+            ens_name
+        )));
+    }
+
+    if !is_fname_label(name_parts[0]) {
+        return Err(HubError::validation_failure(&format!(
+            "ensName \"{}\" doesn't match {}",
+            ens_name, FNAME_REGEX
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_twitter_username(username: &str) -> Result<(), HubError> {
+    if username.is_empty() {
+        return Err(HubError::validation_failure("username is missing"));
+    }
+
+    if js_length(username) > 15 {
+        return Err(HubError::validation_failure(&format!(
+            "username \"{}\" > 15 characters",
+            username
+        )));
+    }
+
+    let has_valid_chars = username
+        .bytes()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+    if !has_valid_chars {
+        return Err(HubError::validation_failure(&format!(
+            "username \"{}\" doesn't match {}",
+            username, TWITTER_REGEX
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_github_username(username: &str) -> Result<(), HubError> {
+    if username.is_empty() {
+        return Err(HubError::validation_failure("username is missing"));
+    }
+
+    if js_length(username) > 38 {
+        return Err(HubError::validation_failure(&format!(
+            "username \"{}\" > 38 characters",
+            username
+        )));
+    }
+
+    // Letters and digits, with single hyphens anywhere but at the start
+    let bytes = username.as_bytes();
+    let has_valid_chars = bytes[0].is_ascii_alphanumeric()
+        && bytes.iter().enumerate().skip(1).all(|(i, b)| {
+            b.is_ascii_alphanumeric() || (*b == b'-' && bytes.get(i + 1) != Some(&b'-'))
+        });
+    if !has_valid_chars {
+        return Err(HubError::validation_failure(&format!(
+            "username \"{}\" doesn't match {}",
+            username, GITHUB_REGEX
+        )));
+    }
+
+    Ok(())
+}
+
+/** Whether `value` is an optional minus, 1 to `max_digits` digits, a dot and 2 decimals */
+fn is_coordinate(value: &str, max_digits: usize) -> bool {
+    let value = value.strip_prefix('-').unwrap_or(value);
+    match value.split_once('.') {
+        Some((whole, decimals)) => {
+            (1..=max_digits).contains(&whole.len())
+                && whole.bytes().all(|b| b.is_ascii_digit())
+                && decimals.len() == 2
+                && decimals.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/** The expected format is geo:<lat>,<long>, with 2 decimals each. An empty location clears it */
+fn validate_user_location(location: &str) -> Result<(), HubError> {
+    if location.is_empty() {
+        return Ok(());
+    }
+
+    let (latitude, longitude) = match location
+        .strip_prefix("geo:")
+        .and_then(|coordinates| coordinates.split_once(','))
+    {
+        Some((latitude, longitude))
+            if is_coordinate(latitude, 2) && is_coordinate(longitude, 3) =>
+        {
+            (latitude, longitude)
+        }
+        _ => return Err(HubError::validation_failure("Invalid location string")),
+    };
+
+    // Both parse, they were checked above
+    let latitude = latitude.parse::<f64>().unwrap_or(f64::NAN);
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(HubError::validation_failure(
+            "Latitude value outside valid range",
+        ));
+    }
+
+    let longitude = longitude.parse::<f64>().unwrap_or(f64::NAN);
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(HubError::validation_failure(
+            "Longitude value outside valid range",
+        ));
+    }
+
+    Ok(())
+}
+
+/** Validate a message for the network `network`. Resolves if it is valid, and throws if not */
+pub fn js_validate_message(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let message_bytes = cx.argument::<JsBuffer>(0)?;
+    let message = Message::decode(message_bytes.as_slice(&cx))
+        .map_err(|e| HubError::validation_failure(&e.to_string()));
+
+    let network = cx.argument::<JsNumber>(1)?.value(&mut cx) as i32;
+    let network = match FarcasterNetwork::try_from(network) {
+        Ok(network) => network,
+        Err(_) => {
+            return hub_error_to_js_throw(&mut cx, HubError::invalid_parameter("invalid network"))
+        }
+    };
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    THREAD_POOL.lock().unwrap().execute(move || {
+        let result = message.and_then(|message| validate_message(&message, network));
+
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(()) => Ok(cx.undefined()),
+            Err(e) => hub_error_to_js_throw(&mut cx, e),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn make_message(body: Body, message_type: MessageType) -> Message {
+        let data = MessageData {
+            r#type: message_type as i32,
+            fid: 1234,
+            timestamp: get_farcaster_time().unwrap() as u32,
+            network: FarcasterNetwork::Devnet as i32,
+            body: Some(body),
+        };
+        sign(data)
+    }
+
+    fn sign(data: MessageData) -> Message {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let data_bytes = data.encode_to_vec();
+        let hash = blake3_20(&data_bytes);
+
+        Message {
+            data: Some(data),
+            signature: signing_key.sign(&hash).to_bytes().to_vec(),
+            hash,
+            hash_scheme: HashScheme::Blake3 as i32,
+            signature_scheme: SignatureScheme::Ed25519 as i32,
+            signer: signing_key.verifying_key().to_bytes().to_vec(),
+            data_bytes: Some(data_bytes),
+        }
+    }
+
+    fn cast_add(text: &str) -> Message {
+        make_message(
+            Body::CastAddBody(CastAddBody {
+                text: text.to_string(),
+                ..Default::default()
+            }),
+            MessageType::CastAdd,
+        )
+    }
+
+    fn user_data(data_type: UserDataType, value: &str) -> Message {
+        make_message(
+            Body::UserDataBody(UserDataBody {
+                r#type: data_type as i32,
+                value: value.to_string(),
+            }),
+            MessageType::UserDataAdd,
+        )
+    }
+
+    fn error(message: &Message) -> HubError {
+        validate_message(message, FarcasterNetwork::Devnet).unwrap_err()
+    }
+
+    #[test]
+    fn test_validate_message() {
+        let message = cast_add("hello");
+        assert_eq!(validate_message(&message, FarcasterNetwork::Devnet), Ok(()));
+
+        // The hash and signature are checked against the data_bytes, or the data without them
+        let mut without_data_bytes = message.clone();
+        without_data_bytes.data_bytes = None;
+        assert_eq!(
+            validate_message(&without_data_bytes, FarcasterNetwork::Devnet),
+            Ok(())
+        );
+
+        assert_eq!(
+            validate_message(&message, FarcasterNetwork::Mainnet)
+                .unwrap_err()
+                .message,
+            "incorrect network: 3 (expected: 1)"
+        );
+
+        let mut bad_hash = message.clone();
+        bad_hash.hash[0] ^= 1;
+        let e = error(&bad_hash);
+        assert_eq!(e.code, "bad_request.validation_failure");
+        assert!(e.message.starts_with("invalid hash. Expected="));
+
+        let mut bad_hash_scheme = message.clone();
+        bad_hash_scheme.hash_scheme = HashScheme::None as i32;
+        assert_eq!(error(&bad_hash_scheme).message, "invalid hashScheme");
+
+        let mut bad_signature = message.clone();
+        bad_signature.signature[0] ^= 1;
+        assert_eq!(error(&bad_signature).message, "invalid signature");
+
+        let mut bad_signer = message.clone();
+        bad_signer.signer = vec![];
+        assert_eq!(error(&bad_signer).message, "invalid signature");
+
+        let mut data = message.data.clone().unwrap();
+        data.timestamp += ALLOWED_CLOCK_SKEW_SECONDS as u32 + 60;
+        assert_eq!(
+            error(&sign(data)).message,
+            "timestamp more than 10 mins in the future"
+        );
+
+        let mut data = message.data.clone().unwrap();
+        data.fid = 0;
+        assert_eq!(error(&sign(data)).message, "fid is missing");
+
+        let mut data = message.data.clone().unwrap();
+        data.r#type = MessageType::LinkAdd as i32;
+        assert_eq!(
+            error(&sign(data)),
+            HubError::invalid_parameter("bodyType is invalid")
+        );
+    }
+
+    #[test]
+    fn test_validate_bodies() {
+        assert_eq!(
+            error(&cast_add(&"a".repeat(321))).message,
+            "text > 320 bytes"
+        );
+        assert_eq!(error(&cast_add("")).message, "cast is empty");
+
+        let message = make_message(
+            Body::CastAddBody(CastAddBody {
+                text: "hi".to_string(),
+                embeds: vec![protos::Embed {
+                    embed: Some(embed::Embed::CastId(CastId {
+                        fid: 0,
+                        hash: vec![1; 4],
+                    })),
+                }],
+                ..Default::default()
+            }),
+            MessageType::CastAdd,
+        );
+        assert_eq!(
+            error(&message).message,
+            "fid is missing, hash must be 20 bytes"
+        );
+
+        let message = make_message(
+            Body::LinkBody(LinkBody {
+                r#type: "following".to_string(),
+                display_timestamp: None,
+                target: Some(link_body::Target::TargetFid(1)),
+            }),
+            MessageType::LinkAdd,
+        );
+        assert_eq!(error(&message).message, "type must be between 1-8 bytes");
+
+        let message = make_message(
+            Body::ReactionBody(ReactionBody {
+                r#type: ReactionType::Like as i32,
+                target: None,
+            }),
+            MessageType::ReactionAdd,
+        );
+        assert_eq!(error(&message).message, "target is missing");
+
+        let message = make_message(
+            Body::VerificationAddAddressBody(VerificationAddAddressBody {
+                address: vec![1; 20],
+                block_hash: vec![2; 31],
+                protocol: Protocol::Ethereum as i32,
+                ..Default::default()
+            }),
+            MessageType::VerificationAddEthAddress,
+        );
+        assert_eq!(error(&message).message, "blockHash must be 32 bytes");
+
+        let message = make_message(
+            Body::VerificationAddAddressBody(VerificationAddAddressBody {
+                address: vec![1; 32],
+                block_hash: vec![2; 32],
+                claim_signature: vec![3; 65],
+                protocol: Protocol::Solana as i32,
+                ..Default::default()
+            }),
+            MessageType::VerificationAddEthAddress,
+        );
+        assert_eq!(error(&message).message, "claimSignature != 64 bytes");
+    }
+
+    #[test]
+    fn test_validate_user_data() {
+        let valid = [
+            (UserDataType::Display, "a".repeat(32)),
+            (UserDataType::Username, "".to_string()),
+            (UserDataType::Username, "farcaster".to_string()),
+            (UserDataType::Username, "farcaster.eth".to_string()),
+            (UserDataType::Location, "geo:-12.34,123.45".to_string()),
+            (UserDataType::Location, "".to_string()),
+            (UserDataType::Twitter, "far_caster".to_string()),
+            (UserDataType::Github, "Far-Caster-".to_string()),
+        ];
+        for (data_type, value) in valid {
+            let message = user_data(data_type, &value);
+            assert_eq!(validate_message(&message, FarcasterNetwork::Devnet), Ok(()));
+        }
+
+        let invalid = [
+            (UserDataType::Display, "a".repeat(33), "display value > 32"),
+            (
+                UserDataType::Username,
+                "Farcaster".to_string(),
+                "fname \"Farcaster\" doesn't match /^[a-z0-9][a-z0-9-]{0,15}$/",
+            ),
+            (
+                UserDataType::Username,
+                "farcasterfarcaster".to_string(),
+                "fname \"farcasterfarcaster\" > 16 characters",
+            ),
+            (
+                UserDataType::Location,
+                "geo:91.00,0.00".to_string(),
+                "Latitude value outside valid range",
+            ),
+            (
+                UserDataType::Location,
+                "geo:1.0,0.00".to_string(),
+                "Invalid location string",
+            ),
+            (
+                UserDataType::Twitter,
+                "Far".to_string(),
+                "username \"Far\" doesn't match /^[a-z0-9_]{0,15}$/",
+            ),
+            (
+                UserDataType::Github,
+                "far--caster".to_string(),
+                "username \"far--caster\" doesn't match /^[a-z\\d](?:[a-z\\d]|-(?!-)){0,38}$/i",
+            ),
+            (UserDataType::None, "".to_string(), "invalid user data type"),
+        ];
+        for (data_type, value, message) in invalid {
+            assert_eq!(error(&user_data(data_type, &value)).message, message);
+        }
+    }
+
+    #[test]
+    fn test_validate_ens_name() {
+        assert_eq!(validate_ens_name("farcaster.eth"), Ok(()));
+        assert_eq!(
+            validate_ens_name("far caster.eth").unwrap_err().message,
+            "ensName \"far caster.eth\" is not a valid ENS name"
+        );
+        assert_eq!(
+            validate_ens_name("fa--rcaster.eth").unwrap_err().message,
+            "ensName \"fa--rcaster.eth\" is not a valid ENS name"
+        );
+        assert_eq!(
+            validate_ens_name("farcaster.xyz").unwrap_err().message,
+            "ensName \"farcaster.xyz\" doesn't end with .eth"
+        );
+        assert_eq!(
+            validate_ens_name("www.farcaster.eth").unwrap_err().message,
+            "ensName \"www.farcaster.eth\" unsupported subdomain"
+        );
+        assert_eq!(
+            validate_ens_name("farcasterfarcaster.eth")
+                .unwrap_err()
+                .message,
+            "ensName \"farcasterfarcaster.eth\" > 20 characters"
+        );
+    }
+}
@@ -0,0 +1,448 @@
+use super::{
+    hub_error_to_js_throw, validate_block_hash, validate_eth_address, validate_sol_address,
+    HubError,
+};
+use crate::ed25519::ed25519_verify_hash;
+use crate::eip712::{self, Eip712Domain};
+use crate::protos::{FarcasterNetwork, Protocol, VerificationAddAddressBody};
+use crate::THREAD_POOL;
+use neon::context::{Context, FunctionContext};
+use neon::result::JsResult;
+use neon::types::buffer::TypedArray;
+use neon::types::{JsBuffer, JsNumber, JsPromise};
+use prost::Message as _;
+
+/** The EIP-712 domain eth verification claims are signed in */
+pub const VERIFICATION_CLAIM_DOMAIN_NAME: &str = "Farcaster Verify Ethereum Address";
+pub const VERIFICATION_CLAIM_DOMAIN_VERSION: &str = "2.0.0";
+pub const VERIFICATION_CLAIM_DOMAIN_SALT: [u8; 32] = [
+    0xf2, 0xd8, 0x57, 0xf4, 0xa3, 0xed, 0xcb, 0x9b, 0x78, 0xb4, 0xd5, 0x03, 0xbf, 0xe7, 0x33, 0xdb,
+    0x1e, 0x3f, 0x6c, 0xdc, 0x2b, 0x79, 0x71, 0xee, 0x73, 0x96, 0x26, 0xc9, 0x7e, 0x86, 0xa5, 0x58,
+];
+
+const VERIFICATION_CLAIM_TYPE: &str =
+    "VerificationClaim(uint256 fid,address address,bytes32 blockHash,uint8 network)";
+
+/** The chains contract wallet claims can be signed on, 0 being the chain id of EOA claims */
+pub const VERIFICATION_CLAIM_CHAIN_IDS: [u32; 5] = [1, 5, 10, 420, 0];
+
+/** How an eth claim is signed, see VerificationAddAddressBody.verification_type */
+const VERIFICATION_TYPE_EOA: u32 = 0;
+const VERIFICATION_TYPE_CONTRACT: u32 = 1;
+
+/** What verify_verification_claim found out about a valid claim */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClaimVerification {
+    /** The claim signature is valid */
+    Verified,
+    /**
+     * The claim is signed by a contract wallet on `chain_id`. Its signature can only be checked
+     * with an ERC-1271 call to the wallet, which has to be made by the caller
+     */
+    ContractWallet { chain_id: u32 },
+}
+
+impl ClaimVerification {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClaimVerification::Verified => "verified",
+            ClaimVerification::ContractWallet { .. } => "contract_wallet",
+        }
+    }
+}
+
+/** The digest an eth address signs for its verification claim, for the chain `chain_id` */
+pub fn eth_verification_claim_hash(
+    fid: u64,
+    address: &[u8; 20],
+    block_hash: &[u8; 32],
+    network: FarcasterNetwork,
+    chain_id: Option<u64>,
+) -> [u8; 32] {
+    let domain = Eip712Domain {
+        name: VERIFICATION_CLAIM_DOMAIN_NAME,
+        version: VERIFICATION_CLAIM_DOMAIN_VERSION,
+        chain_id,
+        verifying_contract: None,
+        salt: Some(VERIFICATION_CLAIM_DOMAIN_SALT),
+    };
+    let struct_hash = eip712::hash_struct(
+        VERIFICATION_CLAIM_TYPE,
+        &[
+            eip712::encode_uint(fid),
+            eip712::encode_address(address),
+            *block_hash,
+            eip712::encode_uint(network as u64),
+        ],
+    );
+
+    eip712::hash_typed_data(&domain, &struct_hash)
+}
+
+/**
+ * The message a solana address signs for its verification claim. It is plain text rather than
+ * an offchain message, because wallets support it better
+ */
+pub fn sol_verification_claim_message(
+    fid: u64,
+    address: &[u8],
+    block_hash: &[u8],
+    network: FarcasterNetwork,
+) -> String {
+    format!(
+        "fid: {} address: {} network: {} blockHash: {} protocol: {}",
+        fid,
+        bs58::encode(address).into_string(),
+        network as i32,
+        bs58::encode(block_hash).into_string(),
+        Protocol::Solana as i32
+    )
+}
+
+/**
+ * Verify that the address of a verification signed the claim that it belongs to `fid`, the way
+ * the JS validateVerificationAdd{Eth,Sol}AddressSignature do. Eth addresses sign an EIP-712
+ * claim, which is checked by recovering the signer, unless the address is a contract wallet.
+ * Solana addresses sign the claim message with ed25519. The errors have the same codes and
+ * messages as the JS ones.
+ */
+pub fn verify_verification_claim(
+    body: &VerificationAddAddressBody,
+    fid: u64,
+    network: FarcasterNetwork,
+) -> Result<ClaimVerification, HubError> {
+    match Protocol::try_from(body.protocol) {
+        Ok(Protocol::Ethereum) => verify_eth_claim(body, fid, network),
+        Ok(Protocol::Solana) => verify_sol_claim(body, fid, network),
+        Err(_) => Err(HubError::validation_failure(
+            "invalid verification protocol",
+        )),
+    }
+}
+
+fn verify_eth_claim(
+    body: &VerificationAddAddressBody,
+    fid: u64,
+    network: FarcasterNetwork,
+) -> Result<ClaimVerification, HubError> {
+    if body.claim_signature.len() > 2048 {
+        return Err(HubError::validation_failure("claimSignature > 2048 bytes"));
+    }
+
+    validate_eth_address(&body.address)?;
+    validate_block_hash(&body.block_hash)?;
+    let address: [u8; 20] = body.address.as_slice().try_into().unwrap();
+    let block_hash: [u8; 32] = body.block_hash.as_slice().try_into().unwrap();
+
+    if !VERIFICATION_CLAIM_CHAIN_IDS.contains(&body.chain_id) {
+        return Err(HubError::invalid_parameter("Invalid chain ID"));
+    }
+
+    match body.verification_type {
+        VERIFICATION_TYPE_EOA => {
+            if body.chain_id != 0 {
+                return Err(HubError::invalid_parameter("Invalid chain ID"));
+            }
+
+            let hash = eth_verification_claim_hash(fid, &address, &block_hash, network, None);
+            if eip712::recover_address(&hash, &body.claim_signature)? != address {
+                return Err(HubError::validation_failure("invalid claimSignature"));
+            }
+
+            Ok(ClaimVerification::Verified)
+        }
+        VERIFICATION_TYPE_CONTRACT => Ok(ClaimVerification::ContractWallet {
+            chain_id: body.chain_id,
+        }),
+        _ => Err(HubError::invalid_parameter("Invalid verification type")),
+    }
+}
+
+fn verify_sol_claim(
+    body: &VerificationAddAddressBody,
+    fid: u64,
+    network: FarcasterNetwork,
+) -> Result<ClaimVerification, HubError> {
+    if body.claim_signature.len() != 64 {
+        return Err(HubError::validation_failure("claimSignature != 64 bytes"));
+    }
+
+    validate_sol_address(&body.address)?;
+    validate_block_hash(&body.block_hash)?;
+
+    let message = sol_verification_claim_message(fid, &body.address, &body.block_hash, network);
+    if !ed25519_verify_hash(&body.claim_signature, message.as_bytes(), &body.address) {
+        return Err(HubError::validation_failure("invalid claimSignature"));
+    }
+
+    Ok(ClaimVerification::Verified)
+}
+
+/**
+ * Verify the claim of an encoded VerificationAddAddressBody for `fid` on `network`. Resolves to
+ * "verified", or to "contract_wallet" if the signature has to be checked with the wallet
+ */
+pub fn js_verify_verification_claim(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let body_bytes = cx.argument::<JsBuffer>(0)?;
+    let body = VerificationAddAddressBody::decode(body_bytes.as_slice(&cx))
+        .map_err(|e| HubError::validation_failure(&e.to_string()));
+
+    let fid = cx.argument::<JsNumber>(1)?.value(&mut cx) as u64;
+    let network = cx.argument::<JsNumber>(2)?.value(&mut cx) as i32;
+    let network = match FarcasterNetwork::try_from(network) {
+        Ok(network) => network,
+        Err(_) => {
+            return hub_error_to_js_throw(&mut cx, HubError::invalid_parameter("invalid network"))
+        }
+    };
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    THREAD_POOL.lock().unwrap().execute(move || {
+        let result = body.and_then(|body| verify_verification_claim(&body, fid, network));
+
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(verification) => Ok(cx.string(verification.name())),
+            Err(e) => hub_error_to_js_throw(&mut cx, e),
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const FID: u64 = 1234;
+    const NETWORK: FarcasterNetwork = FarcasterNetwork::Mainnet;
+
+    fn eth_body(seed: u8) -> VerificationAddAddressBody {
+        let signing_key = k256::ecdsa::SigningKey::from_bytes(&[seed; 32].into()).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let address: [u8; 20] = eip712::keccak256(&point.as_bytes()[1..])[12..]
+            .try_into()
+            .unwrap();
+        let block_hash = [2u8; 32];
+
+        let hash = eth_verification_claim_hash(FID, &address, &block_hash, NETWORK, None);
+        let (signature, recovery_id) = signing_key.sign_prehash_recoverable(&hash).unwrap();
+        let mut claim_signature = signature.to_bytes().to_vec();
+        claim_signature.push(27 + recovery_id.to_byte());
+
+        VerificationAddAddressBody {
+            address: address.to_vec(),
+            claim_signature,
+            block_hash: block_hash.to_vec(),
+            verification_type: VERIFICATION_TYPE_EOA,
+            chain_id: 0,
+            protocol: Protocol::Ethereum as i32,
+        }
+    }
+
+    fn sol_body(seed: u8) -> VerificationAddAddressBody {
+        let signing_key = SigningKey::from_bytes(&[seed; 32]);
+        let address = signing_key.verifying_key().to_bytes().to_vec();
+        let block_hash = vec![3u8; 32];
+
+        let message = sol_verification_claim_message(FID, &address, &block_hash, NETWORK);
+        VerificationAddAddressBody {
+            claim_signature: signing_key.sign(message.as_bytes()).to_bytes().to_vec(),
+            address,
+            block_hash,
+            protocol: Protocol::Solana as i32,
+            ..Default::default()
+        }
+    }
+
+    fn error(body: &VerificationAddAddressBody) -> HubError {
+        verify_verification_claim(body, FID, NETWORK).unwrap_err()
+    }
+
+    #[test]
+    fn test_eth_verification_claim() {
+        // The domain of the claims, with and without a chain id
+        let domain = |chain_id| Eip712Domain {
+            name: VERIFICATION_CLAIM_DOMAIN_NAME,
+            version: VERIFICATION_CLAIM_DOMAIN_VERSION,
+            chain_id,
+            verifying_contract: None,
+            salt: Some(VERIFICATION_CLAIM_DOMAIN_SALT),
+        };
+        assert_ne!(domain(None).separator(), domain(Some(1)).separator());
+
+        let body = eth_body(1);
+        assert_eq!(
+            verify_verification_claim(&body, FID, NETWORK),
+            Ok(ClaimVerification::Verified)
+        );
+
+        // The claim is for the fid and the network of the message
+        assert_eq!(
+            verify_verification_claim(&body, FID + 1, NETWORK)
+                .unwrap_err()
+                .message,
+            "invalid claimSignature"
+        );
+        assert!(verify_verification_claim(&body, FID, FarcasterNetwork::Testnet).is_err());
+
+        // A signature of another address
+        let mut signed_by_other = body.clone();
+        signed_by_other.claim_signature = eth_body(2).claim_signature;
+        assert_eq!(
+            error(&signed_by_other).code,
+            "bad_request.validation_failure"
+        );
+
+        let mut malformed = body.clone();
+        malformed.claim_signature = vec![1; 64];
+        assert_eq!(error(&malformed).code, "bad_request.validation_failure");
+        assert_eq!(error(&malformed).message, "invalid signature");
+
+        let mut too_long = body.clone();
+        too_long.claim_signature = vec![1; 2049];
+        assert_eq!(error(&too_long).message, "claimSignature > 2048 bytes");
+
+        let mut bad_block_hash = body.clone();
+        bad_block_hash.block_hash = vec![2; 31];
+        assert_eq!(error(&bad_block_hash).message, "blockHash must be 32 bytes");
+
+        // EOA claims have no chain id
+        let mut with_chain_id = body.clone();
+        with_chain_id.chain_id = 10;
+        assert_eq!(error(&with_chain_id).code, "bad_request.invalid_param");
+        assert_eq!(error(&with_chain_id).message, "Invalid chain ID");
+
+        // Contract wallet claims are left to the caller
+        let mut contract = body.clone();
+        contract.verification_type = VERIFICATION_TYPE_CONTRACT;
+        contract.chain_id = 10;
+        contract.claim_signature = vec![1; 200];
+        assert_eq!(
+            verify_verification_claim(&contract, FID, NETWORK),
+            Ok(ClaimVerification::ContractWallet { chain_id: 10 })
+        );
+        contract.chain_id = 137;
+        assert_eq!(error(&contract).message, "Invalid chain ID");
+
+        let mut unknown_type = body.clone();
+        unknown_type.verification_type = 2;
+        assert_eq!(error(&unknown_type).message, "Invalid verification type");
+    }
+
+    #[test]
+    fn test_eth_verification_claim_fixture() {
+        // Signed with alloy's EIP-712 signer by the first hardhat test account, for fid 1234 on
+        // mainnet
+        let address: [u8; 20] = hex::decode("f39fd6e51aad88f6f4ce6ab8827279cfffb92266")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let block_hash: [u8; 32] =
+            hex::decode("1d3b0456c920eb503450c7efdcf9b5cf1f5184bf04e5d8ecbcead188a0d02018")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert_eq!(
+            hex::encode(eth_verification_claim_hash(
+                1234,
+                &address,
+                &block_hash,
+                FarcasterNetwork::Mainnet,
+                None
+            )),
+            "3f4f088639077260fc5af1829b9a3f6148db8e34d12a24d4802f8f2efcc81e05"
+        );
+
+        let body = VerificationAddAddressBody {
+            address: address.to_vec(),
+            claim_signature: hex::decode(
+                "3322e6e52fe87a33d3f5bd47ea2da17709e8737ad6b723a47e45298d3e22a7e9\
+                 1b3317f64bf3acb82d3ca3af3d8257a5f33045ad8c03db479669d040acd42d041c",
+            )
+            .unwrap(),
+            block_hash: block_hash.to_vec(),
+            verification_type: VERIFICATION_TYPE_EOA,
+            chain_id: 0,
+            protocol: Protocol::Ethereum as i32,
+        };
+        assert_eq!(
+            verify_verification_claim(&body, 1234, FarcasterNetwork::Mainnet),
+            Ok(ClaimVerification::Verified)
+        );
+        assert!(verify_verification_claim(&body, 1234, FarcasterNetwork::Testnet).is_err());
+    }
+
+    #[test]
+    fn test_sol_verification_claim_fixture() {
+        // The externally generated claim of the engine tests, for fid 123 on testnet
+        let body = VerificationAddAddressBody {
+            address: bs58::decode("8WoeDTF9535N6tnmjyyMukwcAM1exHZr6tUsmbWefgYz")
+                .into_vec()
+                .unwrap(),
+            claim_signature: hex::decode(
+                "d1ffa68a4f4a6d1046ed827760e530eff85e76c6bfcb63ccedc45d293f004256\
+                 58f2825670bbfaf8304c3d715d456f521616705463039983713f8e4f9574be03",
+            )
+            .unwrap(),
+            block_hash: bs58::decode("7hAHBEYX84W4jzQ8P6UJioymYu6Rnhp1CLsBW5B7zpzU")
+                .into_vec()
+                .unwrap(),
+            protocol: Protocol::Solana as i32,
+            ..Default::default()
+        };
+        assert_eq!(
+            verify_verification_claim(&body, 123, FarcasterNetwork::Testnet),
+            Ok(ClaimVerification::Verified)
+        );
+        assert!(verify_verification_claim(&body, 123, FarcasterNetwork::Mainnet).is_err());
+    }
+
+    #[test]
+    fn test_sol_verification_claim() {
+        assert_eq!(
+            sol_verification_claim_message(FID, &[1; 32], &[3; 32], NETWORK),
+            "fid: 1234 address: 4vJ9JU1bJJE96FWSJKvHsmmFADCg4gpZQff4P3bkLKi network: 1 \
+             blockHash: CktRuQ2mttgRGkXJtyksdKHjUdc2C4TgDzyB98oEzy8 protocol: 1"
+        );
+
+        let body = sol_body(1);
+        assert_eq!(
+            verify_verification_claim(&body, FID, NETWORK),
+            Ok(ClaimVerification::Verified)
+        );
+        assert_eq!(
+            verify_verification_claim(&body, FID + 1, NETWORK)
+                .unwrap_err()
+                .message,
+            "invalid claimSignature"
+        );
+
+        let mut signed_by_other = body.clone();
+        signed_by_other.claim_signature = sol_body(2).claim_signature;
+        assert_eq!(error(&signed_by_other).message, "invalid claimSignature");
+
+        let mut short_signature = body.clone();
+        short_signature.claim_signature.pop();
+        assert_eq!(
+            error(&short_signature).message,
+            "claimSignature != 64 bytes"
+        );
+
+        let mut bad_address = body.clone();
+        bad_address.address = vec![1; 20];
+        assert_eq!(
+            error(&bad_address).message,
+            "solana address must be 32 bytes"
+        );
+
+        let mut bad_protocol = body.clone();
+        bad_protocol.protocol = 5;
+        assert_eq!(
+            error(&bad_protocol).message,
+            "invalid verification protocol"
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use super::{
-    get_message, hub_error_to_js_throw, make_fid_key, make_ts_hash, make_user_key, message_decode,
-    read_fid_key,
+    get_message_for_update, hub_error_to_js_throw, make_fid_key, make_ts_hash, make_user_key,
+    message_decode, read_fid_key,
     store::{Store, StoreDef},
     utils::{self, encode_messages_to_js_object, get_page_options, get_store},
+    verification_claim::verify_verification_claim,
     HubError, MessagesPage, PageOptions, RootPrefix, StoreEventHandler, UserPostfix, FID_BYTES,
     TS_HASH_LENGTH,
 };
@@ -11,14 +12,14 @@ use crate::{
     protos::{self, Message, MessageType},
 };
 use crate::{
-    protos::{message_data, Protocol},
+    protos::{message_data, FarcasterNetwork, Protocol},
     store::delete_message_transaction,
 };
 use neon::{
     context::{Context, FunctionContext},
     object::Object,
     result::JsResult,
-    types::{buffer::TypedArray, JsBox, JsBuffer, JsNumber, JsPromise},
+    types::{buffer::TypedArray, JsBoolean, JsBox, JsBuffer, JsNumber, JsPromise},
 };
 use prost::Message as _;
 use slog::info;
@@ -26,6 +27,8 @@ use std::{borrow::Borrow, sync::Arc};
 
 pub struct VerificationStoreDef {
     prune_size_limit: u32,
+    /** Whether the claims of verifications are verified before they are merged */
+    verify_claims: bool,
 }
 
 impl StoreDef for VerificationStoreDef {
@@ -81,9 +84,21 @@ impl StoreDef for VerificationStoreDef {
     fn find_merge_add_conflicts(
         &self,
         _db: &RocksDB,
-        _message: &protos::Message,
+        message: &protos::Message,
     ) -> Result<(), super::store::HubError> {
-        // For verifications, there will be no conflicts
+        // For verifications, there will be no conflicts, but the claim may have to be verified
+        if self.verify_claims {
+            let data = message.data.as_ref().unwrap();
+            if let Some(message_data::Body::VerificationAddAddressBody(body)) = &data.body {
+                let network = FarcasterNetwork::try_from(data.network)
+                    .map_err(|_| HubError::validation_failure("invalid network"))?;
+
+                // Contract wallet claims can only be checked with a call to the wallet, which the
+                // engine makes before the message gets here
+                verify_verification_claim(body, data.fid, network)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -225,11 +240,12 @@ impl StoreDef for VerificationStoreDef {
     fn get_merge_conflicts(
         &self,
         db: &RocksDB,
+        txn: &mut RocksDbTransactionBatch,
         message: &Message,
         ts_hash: &[u8; TS_HASH_LENGTH],
     ) -> Result<Vec<Message>, HubError> {
         // First, call the default implementation to get the default merge conflicts
-        let mut conflicts = Self::get_default_merge_conflicts(self, db, message, ts_hash)?;
+        let mut conflicts = Self::get_default_merge_conflicts(self, db, txn, message, ts_hash)?;
 
         if self.is_remove_type(message) {
             return Ok(conflicts);
@@ -247,7 +263,7 @@ impl StoreDef for VerificationStoreDef {
         };
 
         let by_address_key = Self::make_verification_by_address_key(address);
-        let fid_result = match db.get(&by_address_key) {
+        let fid_result = match db.get_for_update(txn, &by_address_key) {
             Ok(Some(fid)) => Ok(fid),
             _ => Err(HubError {
                 code: "not_found".to_string(),
@@ -260,7 +276,7 @@ impl StoreDef for VerificationStoreDef {
 
             if fid > 0 && fid != message.data.as_ref().unwrap().fid as u32 {
                 let existing_add_key = Self::make_verification_adds_key(fid, address);
-                if let Ok(Some(existing_ts_hash)) = db.get(&existing_add_key) {
+                if let Ok(Some(existing_ts_hash)) = db.get_for_update(txn, &existing_add_key) {
                     let ts_hash =
                         make_ts_hash(message.data.as_ref().unwrap().timestamp, &message.hash)?;
 
@@ -285,8 +301,9 @@ impl StoreDef for VerificationStoreDef {
                         });
                     }
 
-                    let existing_message = get_message(
+                    let existing_message = get_message_for_update(
                         db,
+                        txn,
                         fid,
                         self.postfix(),
                         &utils::vec_to_u8_24(&Some(existing_ts_hash))?,
@@ -335,11 +352,15 @@ impl VerificationStore {
         db: Arc<RocksDB>,
         store_event_handler: Arc<StoreEventHandler>,
         prune_size_limit: u32,
+        verify_claims: bool,
     ) -> Store {
         Store::new_with_store_def(
             db,
             store_event_handler,
-            Box::new(VerificationStoreDef { prune_size_limit }),
+            Box::new(VerificationStoreDef {
+                prune_size_limit,
+                verify_claims,
+            }),
         )
     }
 
@@ -498,6 +519,11 @@ impl VerificationStore {
         Ok(promise)
     }
 
+    /**
+     * Make verification addresses globally unique, for the JS migration to schema version 4 (see
+     * 4.uniqueVerifications.ts). It resolves duplicates with the verification Store, which the
+     * Rust migrations don't get, so it is not a Migration.
+     */
     pub fn migrate_verifications(store: &Store) -> Result<(u32, u32), HubError> {
         let mut verifications_count = 0;
         let mut duplicates_count = 0;
@@ -652,10 +678,19 @@ impl VerificationStore {
             .argument::<JsNumber>(2)
             .map(|n| n.value(&mut cx) as u32)?;
 
+        let verify_claims = match cx.argument_opt(3) {
+            Some(arg) => match arg.downcast::<JsBoolean, _>(&mut cx) {
+                Ok(v) => v.value(&mut cx),
+                _ => false,
+            },
+            None => false,
+        };
+
         Ok(cx.boxed(Arc::new(Self::new(
             db,
             store_event_handler,
             prune_size_limit,
+            verify_claims,
         ))))
     }
 }
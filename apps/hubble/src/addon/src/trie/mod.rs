@@ -1,5 +1,5 @@
 pub mod merkle_trie;
-mod trie_node;
+pub(crate) mod trie_node;
 
 #[cfg(test)]
 mod trie_node_tests;
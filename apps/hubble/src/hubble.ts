@@ -653,7 +653,10 @@ export class Hub implements HubInterface {
     // It is possible that we can't get a lock on the DB in prod, so we retry a few times.
     // This happens if the EFS volume is not mounted yet or is still attached to another instance.
     do {
-      dbResult = await ResultAsync.fromPromise(this.rocksDB.open(), (e) => e as Error);
+      dbResult = await ResultAsync.fromPromise(
+        this.rocksDB.open(this.options.statsdParams ? { enableStatistics: true } : undefined),
+        (e) => e as Error,
+      );
       if (dbResult.isErr()) {
         retryCount++;
         logger.error(
@@ -685,7 +688,7 @@ export class Hub implements HubInterface {
         log.info("skipping db reset as catchup sync with snapshot is enabled, which already cleared the db");
       } else {
         log.info("clearing rocksdb");
-        this.rocksDB.clear();
+        await this.rocksDB.clear();
       }
     } else {
       // Read if the Hub was cleanly shutdown last time
@@ -871,6 +874,12 @@ export class Hub implements HubInterface {
       //   lastHeapDumpTime = Date.now();
       // }
     }, 60 * 1000);
+
+    // Report the RocksDB internals of both DBs, if there is somewhere to report them to
+    if (this.options.statsdParams) {
+      this.rocksDB.startStatsReporter({ prefix: "rocksdb.main" });
+      this.syncEngine.trie.getDb().startStatsReporter({ prefix: "rocksdb.trie" });
+    }
   }
 
   /** Apply the new the network config. Will return true if the Hub should exit */
@@ -1,8 +1,25 @@
 import { blake3 } from "@noble/hashes/blake3";
 import { createEd25519PeerId } from "@libp2p/peer-id-factory";
 import { unmarshalPrivateKey } from "@libp2p/crypto/keys";
-import { rsBlake3Hash20, rsEd25519SignMessageHash, rsEd25519Verify } from "./rustfunctions.js";
-import { Factories, ed25519 } from "@farcaster/hub-nodejs";
+import {
+  rsBlake3Hash20,
+  rsEd25519SignMessageHash,
+  rsEd25519Verify,
+  rsEd25519VerifyBatch,
+  rsValidateMessage,
+  rsVerifyVerificationClaim,
+  rustErrorToHubError,
+} from "./rustfunctions.js";
+import {
+  Factories,
+  FarcasterNetwork,
+  HubError,
+  Message,
+  UserDataType,
+  VerificationAddAddressBody,
+  ed25519,
+  validations,
+} from "@farcaster/hub-nodejs";
 
 describe("blake3 tests", () => {
   test("hashes match rust", () => {
@@ -98,4 +115,132 @@ describe("ed25519 tests", () => {
     expect(await rsEd25519Verify(signature, empty, signerKey)).toBeFalsy();
     expect(await rsEd25519Verify(signature, hash, empty)).toBeFalsy();
   });
+
+  test("batch verification matches single verification", async () => {
+    const signatures: Uint8Array[] = [];
+    const hashes: Uint8Array[] = [];
+    const signers: Uint8Array[] = [];
+    for (let i = 0; i < 100; i++) {
+      const signer = Factories.Ed25519Signer.build();
+      const hash = Factories.Bytes.build({}, { transient: { length: 32 } });
+      signatures.push((await signer.signMessageHash(hash))._unsafeUnwrap());
+      hashes.push(hash);
+      signers.push((await signer.getSignerKey())._unsafeUnwrap());
+    }
+
+    expect(await rsEd25519VerifyBatch(signatures, hashes, signers)).toEqual(signatures.map(() => true));
+
+    // A wrong hash, a signature by another signer and a signature that's too short
+    hashes[10] = Factories.Bytes.build({}, { transient: { length: 32 } });
+    signers[20] = signers[21] as Uint8Array;
+    signatures[90] = new Uint8Array([]);
+
+    const results = await rsEd25519VerifyBatch(signatures, hashes, signers);
+    for (let i = 0; i < results.length; i++) {
+      const signature = signatures[i] as Uint8Array;
+      expect(results[i]).toEqual(rsEd25519Verify(signature, hashes[i] as Uint8Array, signers[i] as Uint8Array));
+      expect(results[i]).toEqual(![10, 20, 90].includes(i));
+    }
+
+    await expect(rsEd25519VerifyBatch(signatures, hashes, [])).rejects.toThrow();
+  });
+});
+
+describe("validateMessage tests", () => {
+  const network = FarcasterNetwork.TESTNET;
+
+  const rsValidate = async (message: Message): Promise<HubError | undefined> => {
+    try {
+      await rsValidateMessage(Message.encode(message).finish(), network);
+      return undefined;
+    } catch (e) {
+      return rustErrorToHubError(e);
+    }
+  };
+
+  const expectSameResult = async (message: Message) => {
+    const result = await validations.validateMessage(message);
+    const rsError = await rsValidate(message);
+
+    if (result.isOk()) {
+      expect(rsError).toBeUndefined();
+    } else {
+      expect(rsError?.errCode).toEqual(result.error.errCode);
+      expect(rsError?.message).toEqual(result.error.message);
+    }
+  };
+
+  test("valid messages pass", async () => {
+    await expectSameResult(await Factories.CastAddMessage.create({ data: { network } }));
+    await expectSameResult(await Factories.ReactionAddMessage.create({ data: { network } }));
+    await expectSameResult(await Factories.LinkAddMessage.create({ data: { network } }));
+  });
+
+  test("invalid messages fail with the JS errors", async () => {
+    await expectSameResult(
+      await Factories.CastAddMessage.create({ data: { network, castAddBody: { text: "a".repeat(321) } } }),
+    );
+    await expectSameResult(
+      await Factories.UserDataAddMessage.create({
+        data: { network, userDataBody: { type: UserDataType.USERNAME, value: "Not An Fname" } },
+      }),
+    );
+
+    const message = await Factories.CastAddMessage.create({ data: { network } });
+    await expectSameResult({ ...message, signature: Factories.Bytes.build({}, { transient: { length: 64 } }) });
+  });
+
+  test("fails on another network", async () => {
+    const message = await Factories.CastAddMessage.create({ data: { network: FarcasterNetwork.MAINNET } });
+    const error = await rsValidate(message);
+    expect(error?.errCode).toEqual("bad_request.validation_failure");
+    expect(error?.message).toEqual(`incorrect network: ${FarcasterNetwork.MAINNET} (expected: ${network})`);
+  });
+});
+
+describe("verifyVerificationClaim tests", () => {
+  const network = FarcasterNetwork.MAINNET;
+  const fid = Factories.Fid.build();
+
+  const rsVerify = async (body: VerificationAddAddressBody, claimFid = fid) => {
+    try {
+      return await rsVerifyVerificationClaim(body, claimFid, network);
+    } catch (e) {
+      return rustErrorToHubError(e);
+    }
+  };
+
+  test("verifies eth claims", async () => {
+    const message = await Factories.VerificationAddEthAddressMessage.create({ data: { fid, network } });
+    const body = message.data.verificationAddAddressBody;
+
+    const result = await validations.validateVerificationAddEthAddressSignature(body, fid, network);
+    expect(result.isOk()).toBeTruthy();
+    expect(await rsVerify(body)).toEqual("verified");
+
+    const otherFid = await rsVerify(body, fid + 1);
+    expect(otherFid).toEqual(new HubError("bad_request.validation_failure", "invalid claimSignature"));
+  });
+
+  test("leaves contract wallet claims to the caller", async () => {
+    const message = await Factories.VerificationAddEthAddressMessage.create({ data: { fid, network } });
+    const body = { ...message.data.verificationAddAddressBody, verificationType: 1, chainId: 10 };
+
+    expect(await rsVerify(body)).toEqual("contract_wallet");
+    const invalidChain = await rsVerify({ ...body, chainId: 137 });
+    expect(invalidChain).toEqual(new HubError("bad_request.invalid_param", "Invalid chain ID"));
+  });
+
+  test("verifies solana claims", async () => {
+    const message = await Factories.VerificationAddSolAddressMessage.create({ data: { fid, network } });
+    const body = message.data.verificationAddAddressBody;
+
+    const result = await validations.validateVerificationAddSolAddressSignature(body, fid, network);
+    expect(result.isOk()).toBeTruthy();
+    expect(await rsVerify(body)).toEqual("verified");
+
+    const invalidBody = { ...body, blockHash: Factories.BlockHash.build() };
+    const jsResult = await validations.validateVerificationAddSolAddressSignature(invalidBody, fid, network);
+    expect(await rsVerify(invalidBody)).toEqual(jsResult._unsafeUnwrapErr());
+  });
 });
@@ -7,7 +7,14 @@ import { createRequire } from "module";
 const require = createRequire(import.meta.url);
 const lib = require("./addon/index.node");
 
-import { HubError, HubErrorCode, HubResult, validations } from "@farcaster/hub-nodejs";
+import {
+  FarcasterNetwork,
+  HubError,
+  HubErrorCode,
+  HubResult,
+  VerificationAddAddressBody,
+  validations,
+} from "@farcaster/hub-nodejs";
 import { PAGE_SIZE_MAX, PageOptions } from "./storage/stores/types.js";
 import { UserMessagePostfix } from "./storage/db/types.js";
 import { DbKeyValue, RocksDbIteratorOptions } from "./storage/db/rocksdb.js";
@@ -38,6 +45,18 @@ export class RustDb {
   private [RustDbBrand]: never;
 }
 
+const RustReadSnapshotBrand = Symbol("RustReadSnapshot");
+export class RustReadSnapshot {
+  // @ts-ignore
+  private [RustReadSnapshotBrand]: never;
+}
+
+const RustBulkLoaderBrand = Symbol("RustBulkLoader");
+export class RustBulkLoader {
+  // @ts-ignore
+  private [RustBulkLoaderBrand]: never;
+}
+
 const RustStoreEventHandlerBrand = Symbol("RustStoreEventHandler");
 export class RustStoreEventHandler {
   // @ts-ignore
@@ -73,6 +92,20 @@ export function rsEd25519Verify(signature: Uint8Array, hash: Uint8Array, signer:
   return lib.ed25519_verify(sigBuf, hashBuf, signerBuf) === 1;
 }
 
+/**
+ * Verify signatures[i] of hashes[i] by signers[i] with batch verification on the rust thread pool. Resolves to
+ * whether each signature is valid
+ */
+export const rsEd25519VerifyBatch = async (
+  signatures: Uint8Array[],
+  hashes: Uint8Array[],
+  signers: Uint8Array[],
+): Promise<boolean[]> => {
+  const toBuffers = (items: Uint8Array[]) => items.map((item) => Buffer.from(item));
+
+  return await lib.ed25519_verifyBatch(toBuffers(signatures), toBuffers(hashes), toBuffers(signers));
+};
+
 /** Fast, native implementation of validation methods to improve perf */
 export const rsValidationMethods: validations.ValidationMethods = {
   ed25519_verify: async (s: Uint8Array, m: Uint8Array, p: Uint8Array) => rsEd25519Verify(s, m, p),
@@ -80,10 +113,34 @@ export const rsValidationMethods: validations.ValidationMethods = {
   blake3_20: (message: Uint8Array) => rsBlake3Hash20(message),
 };
 
+/**
+ * Validate a message natively, the way validations.validateMessage does for a hub on `network`. Rejects with the
+ * same error as the JS validator, but only checks the length of verification claim signatures, see
+ * rsVerifyVerificationClaim
+ */
+export const rsValidateMessage = async (messageBytes: Uint8Array, network: FarcasterNetwork): Promise<void> => {
+  return await lib.validateMessage(messageBytes, network);
+};
+
+/** "contract_wallet" claims are signed by a contract, and have to be verified with an ERC-1271 call to it */
+export type RustClaimVerification = "verified" | "contract_wallet";
+
+/**
+ * Verify the claim signature of a VerificationAddAddressBody for `fid` on `network`, with EIP-712 for eth addresses
+ * and ed25519 for solana addresses. Rejects with the same error as validateVerificationAdd{Eth,Sol}AddressSignature
+ */
+export const rsVerifyVerificationClaim = async (
+  body: VerificationAddAddressBody,
+  fid: number,
+  network: FarcasterNetwork,
+): Promise<RustClaimVerification> => {
+  return await lib.verifyVerificationClaim(Buffer.from(VerificationAddAddressBody.encode(body).finish()), fid, network);
+};
+
 export const rustErrorToHubError = (e: unknown) => {
-  // Split the error string by "/", the first part is the error code, the second part is the error message
-  const [errCode, errMsg] = (e as Error).message.split("/");
-  return new HubError(errCode as HubErrorCode, errMsg ?? "");
+  // Split the error string at the first "/", the first part is the error code, the rest is the error message
+  const [errCode, ...errMsg] = (e as Error).message.split("/");
+  return new HubError(errCode as HubErrorCode, errMsg.join("/"));
 };
 
 export const rsCreateStatsdClient = (host: string, port: number, prefix: string): void => {
@@ -108,16 +165,73 @@ export const rsCreateDb = (path: string): RustDb => {
   return db as RustDb;
 };
 
-export const rsDbOpen = (db: RustDb): void => {
-  lib.dbOpen.call(db);
+/** Tuning options for RocksDB. Any option that is not set keeps its default */
+export type RustDbOptions = {
+  blockCacheSize?: number;
+  writeBufferSize?: number;
+  maxWriteBufferNumber?: number;
+  maxBackgroundJobs?: number;
+  compressionPerLevel?: ("none" | "snappy" | "zlib" | "bz2" | "lz4" | "lz4hc" | "zstd")[];
+  bloomFilterBitsPerKey?: number;
+  rateLimitBytesPerSec?: number;
+  level0SlowdownWritesTrigger?: number;
+  level0StopWritesTrigger?: number;
+  /** Collect RocksDB statistics, which the stats reporter needs for the block cache hit rate and stall time */
+  enableStatistics?: boolean;
+  /**
+   * How long hub events are kept, in ms. Older events are dropped by RocksDB as it compacts them, so they may be
+   * read for a while after they expire. Events are kept forever if this is not set.
+   */
+  hubEventsRetentionMs?: number;
+  /**
+   * Open the DB as the primary (the default), or as a read-only or secondary instance next to a running primary.
+   * Writes to a read-only or secondary instance fail with "db.read_only". A secondary keeps its info logs in
+   * secondaryPath, which is required.
+   */
+  mode?: "primary" | "readOnly" | "secondary";
+  secondaryPath?: string;
+};
+
+export const rsDbOpen = (db: RustDb, options?: RustDbOptions): void => {
+  lib.dbOpen.call(db, options);
+};
+
+/** Apply the writes the primary has made since a secondary instance was opened, or last caught up */
+export const rsDbTryCatchUpWithPrimary = async (db: RustDb): Promise<void> => {
+  return await lib.dbTryCatchUpWithPrimary.call(db);
+};
+
+/**
+ * Report the RocksDB properties (key estimates, SST and memtable sizes, pending compactions, block cache usage) to
+ * statsd as gauges every intervalMs (default 60s), named "<prefix>.<column family>.<property>". Replaces the reporter
+ * that is already running for this DB.
+ */
+export const rsDbStartStatsReporter = (db: RustDb, options?: { intervalMs?: number; prefix?: string }): void => {
+  lib.dbStartStatsReporter.call(db, options?.intervalMs, options?.prefix);
+};
+
+/** The id of the oldest hub event that hasn't expired yet, or undefined if there are none */
+export const rsDbGetOldestEventId = (db: RustDb): number | undefined => {
+  return lib.dbGetOldestEventId.call(db);
+};
+
+/** Whether RocksDB is slowing down ("delayed") or stopping ("stopped") writes because compactions are behind */
+export type RustWriteStallState = "normal" | "delayed" | "stopped";
+
+export const rsDbGetWriteStallState = (db: RustDb): RustWriteStallState => {
+  return lib.dbGetWriteStallState.call(db);
 };
 
 export const rsApproximateSize = (db: RustDb): number => {
   return lib.dbApproximateSize.call(db);
 };
 
-export const rsDbClear = (db: RustDb) => {
-  return lib.dbClear.call(db);
+/**
+ * Delete every key in the DB with range deletes. Pass compact to reclaim the disk space right away.
+ * Resolves to RocksDB's estimate of the number of keys deleted.
+ */
+export const rsDbClear = async (db: RustDb, compact?: boolean): Promise<number> => {
+  return await lib.dbClear.call(db, compact);
 };
 
 export const rsDbClose = (db: RustDb) => {
@@ -136,12 +250,16 @@ export const rsDbKeysExist = async (db: RustDb, keys: Uint8Array[]): Promise<boo
   return await lib.dbKeysExist.call(db, keys);
 };
 
-export const rsDbGet = async (db: RustDb, key: Uint8Array): Promise<Buffer> => {
-  return await lib.dbGet.call(db, key);
+export const rsDbGet = async (db: RustDb, key: Uint8Array, snapshot?: RustReadSnapshot): Promise<Buffer> => {
+  return await lib.dbGet.call(db, key, snapshot);
 };
 
-export const rsDbGetMany = async (db: RustDb, keys: Uint8Array[]): Promise<(Buffer | undefined)[]> => {
-  const results = await lib.dbGetMany.call(db, keys);
+export const rsDbGetMany = async (
+  db: RustDb,
+  keys: Uint8Array[],
+  snapshot?: RustReadSnapshot,
+): Promise<(Buffer | undefined)[]> => {
+  const results = await lib.dbGetMany.call(db, keys, snapshot);
   // If a key was not found, it is set to an empty buffer. We want to return undefined in that case
   return results.map((result: Buffer) => (result.length === 0 ? undefined : result));
 };
@@ -154,12 +272,120 @@ export const rsDbDel = async (db: RustDb, key: Uint8Array): Promise<void> => {
   return await lib.dbDel.call(db, key);
 };
 
+/** The key values are written in order, so a later write to the same key wins */
 export const rsDbCommit = async (db: RustDb, keyValues: DbKeyValue[]): Promise<void> => {
   return await lib.dbCommit.call(db, keyValues);
 };
 
-export const rsDbSnapshotBackup = async (mainDb: RustDb, trieDb: RustDb, timestamp: number): Promise<string> => {
-  return await lib.dbSnapshotBackup(mainDb, trieDb, timestamp);
+/**
+ * Take a consistent point-in-time view of the DB. Pass it to the get and iterator functions to read the DB as it
+ * was when the snapshot was taken. Release it as soon as you're done, it keeps old data from being compacted away.
+ * If it isn't released, it is released after maxAgeMs (5 minutes by default), and reads from it fail.
+ */
+export const rsDbCreateReadSnapshot = (db: RustDb, maxAgeMs?: number): RustReadSnapshot => {
+  return lib.dbCreateReadSnapshot.call(db, maxAgeMs);
+};
+
+export const rsDbReleaseReadSnapshot = (snapshot: RustReadSnapshot): void => {
+  return lib.dbReleaseReadSnapshot(snapshot);
+};
+
+export type RustSnapshotProgress = {
+  phase: "copy" | "tar" | "cleanup";
+  db?: "main" | "trie";
+  keys: number;
+  chunk: number;
+  chunkBytes: number;
+};
+
+const RustSnapshotHandleBrand = Symbol("RustSnapshotHandle");
+export class RustSnapshotHandle {
+  // @ts-ignore
+  private [RustSnapshotHandleBrand]: never;
+}
+
+/**
+ * Create a handle to follow and cancel a rsDbSnapshotBackup. onProgress is called as keys are counted
+ * and chunks are written.
+ */
+export const rsDbCreateSnapshotHandle = (
+  onProgress?: (progress: RustSnapshotProgress) => void,
+): RustSnapshotHandle => {
+  return lib.dbCreateSnapshotHandle(onProgress);
+};
+
+/**
+ * Cancel the snapshot backup using this handle. The backup rejects with "db.snapshot_cancelled" and removes
+ * the partial backup.
+ */
+export const rsDbCancelSnapshot = (handle: RustSnapshotHandle): void => {
+  lib.dbCancelSnapshot(handle);
+};
+
+/**
+ * How the snapshot chunks are written. The default is gzip in 4GB chunks, which is the only codec the
+ * snapshot download from S3 can read. rsDbRestoreSnapshot reads all of them.
+ */
+export type RustSnapshotOptions = {
+  codec?: "gzip" | "zstd" | "none";
+  compressionLevel?: number; // zstd only, 1-22
+  numThreads?: number;
+  chunkSize?: number; // uncompressed bytes per chunk
+};
+
+export const rsDbSnapshotBackup = async (
+  mainDb: RustDb,
+  trieDb: RustDb,
+  timestamp: number,
+  handle?: RustSnapshotHandle,
+  options?: RustSnapshotOptions,
+): Promise<string> => {
+  return await lib.dbSnapshotBackup(mainDb, trieDb, timestamp, handle, options);
+};
+
+export type RustSnapshotManifest = {
+  version: number;
+  timestamp: number;
+  dbSchemaVersion: number;
+  network: number | null;
+  trieRootHash: string;
+  mainDbKeys: number;
+  trieDbKeys: number;
+  chunks: { name: string; size: number; blake3: string }[];
+};
+
+/**
+ * Check every chunk of a snapshot created by rsDbSnapshotBackup against its manifest.json.
+ * Rejects if a chunk is missing, truncated or corrupted.
+ */
+export const rsDbVerifySnapshot = async (archiveDir: string): Promise<RustSnapshotManifest> => {
+  const manifest = await lib.dbVerifySnapshot(archiveDir);
+  return JSON.parse(manifest) as RustSnapshotManifest;
+};
+
+/**
+ * Restore a snapshot created by rsDbSnapshotBackup into targetPath. The DBs at targetPath must be closed.
+ * Resolves to targetPath once the restored DB has been checked and moved into place.
+ */
+export const rsDbRestoreSnapshot = async (archiveDir: string, targetPath: string): Promise<string> => {
+  return await lib.dbRestoreSnapshot(archiveDir, targetPath);
+};
+
+/**
+ * Which local snapshots rsDbGcSnapshots keeps. A snapshot has to pass every limit that is set to be kept.
+ */
+export type RustSnapshotRetention = {
+  keepLast?: number;
+  maxAgeMs?: number;
+  maxBytes?: number;
+};
+
+/**
+ * Delete the local snapshots of mainDb that the retention policy doesn't keep, along with any leftover
+ * checkpoints. A snapshot that rsDbSnapshotBackup is still writing is never deleted. Resolves to the deleted paths.
+ */
+export const rsDbGcSnapshots = async (mainDb: RustDb, retention?: RustSnapshotRetention): Promise<string[]> => {
+  return await lib.dbGcSnapshots(mainDb, retention);
 };
 
 /**
@@ -193,6 +419,7 @@ export const rsDbForEachIteratorByPrefix = async (
   prefix: Uint8Array,
   pageOptions: PageOptions,
   cb: (key: Buffer, value: Buffer | undefined) => Promise<boolean> | boolean | Promise<void> | void,
+  snapshot?: RustReadSnapshot,
 ): Promise<boolean> => {
   let allFinished = false;
   let nextPageToken = undefined;
@@ -200,7 +427,7 @@ export const rsDbForEachIteratorByPrefix = async (
   let batchPageOptions = { ...pageOptions };
 
   do {
-    const result = await lib.dbFetchIteratorPageByPrefix.call(db, prefix, batchPageOptions);
+    const result = await lib.dbFetchIteratorPageByPrefix.call(db, prefix, batchPageOptions, snapshot);
     allFinished = result.allFinished;
     nextPageToken = result.nextPageToken;
 
@@ -227,6 +454,7 @@ export const rsDbForEachIteratorByOpts = async (
   iteratorOpts: RocksDbIteratorOptions,
   cb: (key: Buffer, value: Buffer | undefined) => Promise<boolean> | boolean | void,
   overridePageSize?: number, // Only for tests
+  snapshot?: RustReadSnapshot,
 ): Promise<boolean> => {
   let dbKeyValues: DbKeyValue[] = [];
   const batchPageSize = overridePageSize ?? PAGE_SIZE_MAX;
@@ -246,14 +474,19 @@ export const rsDbForEachIteratorByOpts = async (
       }
     }
 
-    allFinished = await lib.dbForEachIteratorByOpts.call(db, batchPageOptions, (key: Buffer, value: Buffer) => {
-      dbKeyValues.push({ key, value });
-      if (dbKeyValues.length >= batchPageSize) {
-        nextPageToken = new Uint8Array(key);
-        return true; // Stop the iteration
-      }
-      return false; // Continue the iteration
-    });
+    allFinished = await lib.dbForEachIteratorByOpts.call(
+      db,
+      batchPageOptions,
+      (key: Buffer, value: Buffer) => {
+        dbKeyValues.push({ key, value });
+        if (dbKeyValues.length >= batchPageSize) {
+          nextPageToken = new Uint8Array(key);
+          return true; // Stop the iteration
+        }
+        return false; // Continue the iteration
+      },
+      snapshot,
+    );
 
     for (const kv of dbKeyValues) {
       const shouldStop = await cb(kv.key, kv.value);
@@ -273,8 +506,76 @@ export const rsDbCountKeysAtPrefix = async (db: RustDb, prefix: Uint8Array): Pro
   return await lib.dbCountKeysAtPrefix.call(db, prefix);
 };
 
-export const rsDbDeleteAllKeysInRange = async (db: RustDb, iteratorOpts: RocksDbIteratorOptions): Promise<boolean> => {
-  return await lib.dbDeleteAllKeysInRange.call(db, iteratorOpts);
+/**
+ * Rebuild the per-(fid, postfix) key counters that rsDbCountKeysAtPrefix answers from. DBs created before the
+ * counters existed count by scanning until this has run once. Resolves to the number of counters written.
+ */
+export const rsDbRepairKeyCounters = async (db: RustDb): Promise<number> => {
+  return await lib.dbRepairKeyCounters.call(db);
+};
+
+/**
+ * Mark the DB as loading, which is required to bulk load it. The key counters are rebuilt by rsDbEndLoading, and
+ * until then keys are counted by scanning.
+ */
+export const rsDbBeginLoading = (db: RustDb): void => {
+  lib.dbBeginLoading.call(db);
+};
+
+/** Stop loading the DB and rebuild its key counters. Resolves to the number of counters written */
+export const rsDbEndLoading = async (db: RustDb): Promise<number> => {
+  return await lib.dbEndLoading.call(db);
+};
+
+/**
+ * Create a loader that writes keys to SST files and ingests them into the DB when it is finished, which is much
+ * faster than committing them. The DB has to be loading.
+ */
+export const rsDbCreateBulkLoader = (db: RustDb): RustBulkLoader => {
+  return lib.dbCreateBulkLoader.call(db);
+};
+
+/** Add keys[i] with values[i]. Keys must be sorted, and greater than all the keys added before */
+export const rsDbBulkLoaderAdd = async (loader: RustBulkLoader, keys: Buffer[], values: Buffer[]): Promise<void> => {
+  return await lib.dbBulkLoaderAdd(loader, keys, values);
+};
+
+/** Ingest all the keys that were added. Nothing is visible in the DB until this resolves */
+export const rsDbBulkLoaderFinish = async (
+  loader: RustBulkLoader,
+): Promise<{ keys: number; bytes: number; files: number }> => {
+  return await lib.dbBulkLoaderFinish(loader);
+};
+
+export type RustMigrationReport = {
+  version: number;
+  name: string;
+  batches: number;
+  writes: number;
+};
+
+/**
+ * Run the Rust DB migrations up to toVersion, or all of them. The schema version is bumped in the same commit as
+ * the last batch of each migration, and an interrupted migration resumes where it left off. With dryRun nothing is
+ * written, and the reports tell what would have been.
+ */
+export const rsDbRunMigrations = async (
+  db: RustDb,
+  options?: { toVersion?: number; dryRun?: boolean },
+): Promise<{ fromVersion: number; toVersion: number; migrations: RustMigrationReport[] }> => {
+  return await lib.dbRunMigrations.call(db, options);
+};
+
+/**
+ * Delete all the keys in the range with a single range delete, without reading them first. Pass compact to
+ * reclaim the disk space right away.
+ */
+export const rsDbDeleteAllKeysInRange = async (
+  db: RustDb,
+  iteratorOpts: RocksDbIteratorOptions,
+  compact?: boolean,
+): Promise<boolean> => {
+  return await lib.dbDeleteAllKeysInRange.call(db, iteratorOpts, compact);
 };
 
 export const rsCreateStoreEventHandler = (
@@ -369,19 +670,30 @@ export const rsMerge = async (store: RustDynStore, messageBytes: Uint8Array): Pr
   return await lib.merge.call(store, messageBytes);
 };
 
+/**
+ * Low priority merges fail with "db.write_stalled" while RocksDB stops writes, instead of waiting for the stall to
+ * clear. They are only admitted again once compactions catch up, so a caller that passes "low" has to retry them
+ * with backoff. High priority merges are always admitted, and while RocksDB only delays writes, so are low priority
+ * ones. Every merge is high priority for now, sync included, until the sync engine backs off on "db.write_stalled"
+ * rather than logging each rejected message and dropping it until the next sync.
+ */
+export type RustWritePriority = "high" | "low";
+
 export const rsMergeMany = async (
   store: RustDynStore,
   messagesBytes: Uint8Array[],
+  priority: RustWritePriority = "high",
 ): Promise<Map<number, HubResult<Buffer>>> => {
-  // Short-circuit if there are is just one message
-  if (messagesBytes.length === 1) {
+  // Short-circuit if there are is just one message. Merge runs on the main thread, so it only takes high priority
+  // merges, which are never rejected
+  if (messagesBytes.length === 1 && priority === "high") {
     const result = await ResultAsync.fromPromise(rsMerge(store, messagesBytes[0] as Uint8Array), rustErrorToHubError);
     return new Map([[0, result]]);
   }
 
   const mergeResults: Map<number, HubResult<Buffer>> = new Map();
 
-  const results = await lib.mergeMany.call(store, messagesBytes);
+  const results = await lib.mergeMany.call(store, messagesBytes, priority);
 
   // Parse the results
   for (let i = 0; i < results.length; i++) {
@@ -401,6 +713,19 @@ export const rsMergeMany = async (
   return mergeResults;
 };
 
+/** The encoded messages a merge would delete, and the encoded HubEvent it would be merged with */
+export type RustMergeSimulation = { mergeConflicts: Buffer[]; hubEvent: Buffer };
+
+/** Find out what merging a message would do without merging it. Rejects with the error the merge would fail with */
+export const rsSimulateMerge = async (store: RustDynStore, messageBytes: Uint8Array): Promise<RustMergeSimulation> => {
+  return await lib.simulateMerge.call(store, messageBytes);
+};
+
+/** Validate the messages merged into the store for `network`, or stop validating them with FarcasterNetwork.NONE */
+export const rsSetMergeValidation = (store: RustDynStore, network: FarcasterNetwork) => {
+  lib.setMergeValidation.call(store, network);
+};
+
 /** Revoke a message from the store */
 export const revoke = async (store: RustDynStore, messageBytes: Uint8Array): Promise<Buffer> => {
   return await lib.revoke.call(store, messageBytes);
@@ -512,12 +837,17 @@ export const rsMergeUserNameProof = async (store: RustDynStore, usernameProof: U
 };
 
 /** VerificationStore */
+/**
+ * Create a VerificationStore. With `verifyClaims`, the store verifies the claim signatures of verifications before
+ * merging them, except for contract wallet claims, which need an ERC-1271 call to the wallet
+ */
 export const rsCreateVerificationStore = (
   db: RustDb,
   eventHandler: RustStoreEventHandler,
   pruneSizeLimit: number,
+  verifyClaims = false,
 ): RustDynStore => {
-  const store = lib.createVerificationStore(db, eventHandler, pruneSizeLimit);
+  const store = lib.createVerificationStore(db, eventHandler, pruneSizeLimit, verifyClaims);
 
   return store as RustDynStore;
 };
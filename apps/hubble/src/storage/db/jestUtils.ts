@@ -9,7 +9,7 @@ export const jestRocksDB = (name: string) => {
   });
 
   afterEach(async () => {
-    db.clear();
+    await db.clear();
   });
 
   afterAll(async () => {
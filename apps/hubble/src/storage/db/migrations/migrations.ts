@@ -13,6 +13,7 @@ import { oldContractEvents } from "./6.oldContractEvents.js";
 import { clearAdminResets } from "./7.clearAdminResets.js";
 import { fnameUserNameProofByFidPrefix } from "./9.fnameUserNameProofByFidPrefix.js";
 import { fixFnameIndexLittleEndianToBigEndian } from "./11.fnameIndex.js";
+import { rsDbRunMigrations } from "../../../rustfunctions.js";
 
 type MigrationFunctionType = (db: RocksDB) => Promise<boolean>;
 const migrations = new Map<number, MigrationFunctionType>();
@@ -73,11 +74,22 @@ migrations.set(11, async (db: RocksDB) => {
   return await fixFnameIndexLittleEndianToBigEndian(db);
 });
 
+// Migrations from here on are written in Rust, see store/migrations.rs. They bump the schema version themselves.
+const FIRST_RUST_MIGRATION = 12;
+
+migrations.set(12, async (db: RocksDB) => {
+  return await runRustMigration(db, 12);
+});
+
 // To Add a new migration
 // migrations.set(<next number>, async (db: RocksDB) => {
 //   <call migration script>
 //   return true; // or false if migration failed
 // });
+// or, for a migration written in Rust
+// migrations.set(<next number>, async (db: RocksDB) => {
+//   return await runRustMigration(db, <next number>);
+// });
 
 // The latest code version of the DB schema. This is the version that the DB will be
 // migrated to if the DB has an older schema version.
@@ -96,7 +108,7 @@ export async function performDbMigrations(
     if (success.isErr() || success.value === false) {
       log.error({ error: success, i }, "DB migration failed");
       return false;
-    } else {
+    } else if (i < FIRST_RUST_MIGRATION) {
       const res = await setDbSchemaVersion(db, i);
       if (res.isErr()) {
         log.error({ error: res, i }, "Failed to set schema version");
@@ -108,6 +120,17 @@ export async function performDbMigrations(
   return true;
 }
 
+async function runRustMigration(db: RocksDB, version: number): Promise<boolean> {
+  const result = await ResultAsync.fromPromise(rsDbRunMigrations(db.rustDb, { toVersion: version }), (e) => e);
+  if (result.isErr()) {
+    log.error({ error: result.error, version }, "Rust DB migration failed");
+    return false;
+  }
+
+  log.info({ version, migrations: result.value.migrations }, "Rust DB migration finished");
+  return true;
+}
+
 export async function getDbSchemaVersion(db: RocksDB): Promise<number> {
   const dbResult = await ResultAsync.fromPromise(
     db.get(Buffer.from([RootPrefix.DBSchemaVersion])),
@@ -76,7 +76,7 @@ describe("clear", () => {
     const value = await db.get(Buffer.from("key"));
     expect(value).toEqual(Buffer.from("value"));
 
-    db.clear();
+    await db.clear();
     await expect(db.get(Buffer.from("key"))).rejects.toThrow(HubError);
     await db.destroy();
   });
@@ -15,11 +15,25 @@ import {
   rsDbLocation,
   rsDbOpen,
   rsDbPut,
+  rsDbTryCatchUpWithPrimary,
+  rsDbStartStatsReporter,
+  rsDbGetOldestEventId,
+  rsDbGetWriteStallState,
+  RustWriteStallState,
   RustDb,
+  RustDbOptions,
   rustErrorToHubError,
   rsDbCountKeysAtPrefix,
   rsDbDeleteAllKeysInRange,
+  rsDbRepairKeyCounters,
   rsDbKeysExist,
+  rsDbCreateReadSnapshot,
+  rsDbReleaseReadSnapshot,
+  RustReadSnapshot,
+  rsDbBeginLoading,
+  rsDbEndLoading,
+  rsDbCreateBulkLoader,
+  RustBulkLoader,
 } from "../../rustfunctions.js";
 import { PageOptions } from "storage/stores/types.js";
 import { HubAsyncResult } from "@farcaster/hub-nodejs";
@@ -120,8 +134,8 @@ class RocksDB {
     return await ResultAsync.fromPromise(rsDbKeysExist(this._db, keys), (e) => rustErrorToHubError(e));
   }
 
-  async get(key: Buffer): Promise<Buffer> {
-    const v = await ResultAsync.fromPromise(rsDbGet(this._db, key), (e) => rustErrorToHubError(e));
+  async get(key: Buffer, snapshot?: RustReadSnapshot): Promise<Buffer> {
+    const v = await ResultAsync.fromPromise(rsDbGet(this._db, key, snapshot), (e) => rustErrorToHubError(e));
     if (v.isErr()) {
       throw v.error;
     }
@@ -129,8 +143,8 @@ class RocksDB {
     return v.value;
   }
 
-  async getMany(keys: Buffer[]): Promise<(Buffer | undefined)[]> {
-    const v = await ResultAsync.fromPromise(rsDbGetMany(this._db, keys), (e) => rustErrorToHubError(e));
+  async getMany(keys: Buffer[], snapshot?: RustReadSnapshot): Promise<(Buffer | undefined)[]> {
+    const v = await ResultAsync.fromPromise(rsDbGetMany(this._db, keys, snapshot), (e) => rustErrorToHubError(e));
     if (v.isErr()) {
       throw v.error;
     }
@@ -147,7 +161,7 @@ class RocksDB {
     return v.value;
   }
 
-  open(): Promise<void> {
+  open(options?: RustDbOptions): Promise<void> {
     return new Promise((resolve, reject) => {
       if (this.status === "opening") {
         reject(new Error("db is opening"));
@@ -157,7 +171,7 @@ class RocksDB {
         resolve(undefined);
       } else {
         this._status = "opening";
-        rsDbOpen(this._db);
+        rsDbOpen(this._db, options);
         this._status = "open";
         resolve(undefined);
       }
@@ -169,8 +183,24 @@ class RocksDB {
     this._status = "closed";
   }
 
-  clear(): void {
-    rsDbClear(this._db);
+  async tryCatchUpWithPrimary(): Promise<void> {
+    return await rsDbTryCatchUpWithPrimary(this._db);
+  }
+
+  startStatsReporter(options?: { intervalMs?: number; prefix?: string }): void {
+    rsDbStartStatsReporter(this._db, options);
+  }
+
+  getOldestEventId(): number | undefined {
+    return rsDbGetOldestEventId(this._db);
+  }
+
+  getWriteStallState(): RustWriteStallState {
+    return rsDbGetWriteStallState(this._db);
+  }
+
+  async clear(compact?: boolean): Promise<void> {
+    await rsDbClear(this._db, compact);
   }
 
   async destroy(): Promise<void> {
@@ -196,8 +226,36 @@ class RocksDB {
     return await rsDbCountKeysAtPrefix(this._db, prefix);
   }
 
-  async deleteAllKeysInRange(options: RocksDbIteratorOptions): Promise<boolean> {
-    return await rsDbDeleteAllKeysInRange(this._db, options);
+  /**
+   * Take a read snapshot to pass to get, getMany and the iterators. Release it with releaseReadSnapshot.
+   */
+  createReadSnapshot(maxAgeMs?: number): RustReadSnapshot {
+    return rsDbCreateReadSnapshot(this._db, maxAgeMs);
+  }
+
+  releaseReadSnapshot(snapshot: RustReadSnapshot): void {
+    rsDbReleaseReadSnapshot(snapshot);
+  }
+
+  /** Allow bulk loading the DB until endLoading is called, see rsDbCreateBulkLoader */
+  beginLoading(): void {
+    rsDbBeginLoading(this._db);
+  }
+
+  async endLoading(): Promise<number> {
+    return await rsDbEndLoading(this._db);
+  }
+
+  createBulkLoader(): RustBulkLoader {
+    return rsDbCreateBulkLoader(this._db);
+  }
+
+  async repairKeyCounters(): Promise<number> {
+    return await rsDbRepairKeyCounters(this._db);
+  }
+
+  async deleteAllKeysInRange(options: RocksDbIteratorOptions, compact?: boolean): Promise<boolean> {
+    return await rsDbDeleteAllKeysInRange(this._db, options, compact);
   }
 
   /**
@@ -207,8 +265,9 @@ class RocksDB {
     prefix: Buffer,
     callback: (key: Buffer, value: Buffer | undefined) => Promise<boolean> | boolean | Promise<void> | void,
     pageOptions: PageOptions = {},
+    snapshot?: RustReadSnapshot,
   ): Promise<boolean> {
-    return await rsDbForEachIteratorByPrefix(this._db, prefix, pageOptions, callback);
+    return await rsDbForEachIteratorByPrefix(this._db, prefix, pageOptions, callback, snapshot);
   }
 
   /**
@@ -218,8 +277,9 @@ class RocksDB {
   async forEachIteratorByOpts(
     options: RocksDbIteratorOptions,
     callback: (key: Buffer | undefined, value: Buffer | undefined) => Promise<boolean> | boolean | void,
+    snapshot?: RustReadSnapshot,
   ): Promise<boolean> {
-    return await rsDbForEachIteratorByOpts(this._db, options, callback);
+    return await rsDbForEachIteratorByOpts(this._db, options, callback, undefined, snapshot);
   }
 
   async approximateSize(): Promise<number> {
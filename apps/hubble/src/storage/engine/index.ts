@@ -72,7 +72,7 @@ import { normalize } from "viem/ens";
 import UsernameProofStore from "../stores/usernameProofStore.js";
 import OnChainEventStore from "../stores/onChainEventStore.js";
 import { consumeRateLimitByKey, getRateLimiterForTotalMessages, isRateLimitedByKey } from "../../utils/rateLimits.js";
-import { rsValidationMethods } from "../../rustfunctions.js";
+import { RustWritePriority, rsValidationMethods } from "../../rustfunctions.js";
 import { RateLimiterAbstract, RateLimiterMemory } from "rate-limiter-flexible";
 import { TypedEmitter } from "tiny-typed-emitter";
 import { FNameRegistryEventsProvider } from "../../eth/fnameRegistryEventsProvider.js";
@@ -310,7 +310,11 @@ class Engine extends TypedEmitter<EngineEvents> {
     return ok({ i, fid, limiter, message });
   }
 
-  async mergeMessages(messages: Message[]): Promise<Map<number, HubResult<number>>> {
+  /** Low priority merges fail with "db.write_stalled" while RocksDB stops writes, see RustWritePriority */
+  async mergeMessages(
+    messages: Message[],
+    priority: RustWritePriority = "high",
+  ): Promise<Map<number, HubResult<number>>> {
     const mergeResults: Map<number, HubResult<number>> = new Map();
     const validatedMessages: IndexedMessage[] = [];
 
@@ -340,6 +344,7 @@ class Engine extends TypedEmitter<EngineEvents> {
 
     const results: Map<number, HubResult<number>> = await this.mergeMessagesToStore(
       validatedMessages.map((m) => m.message),
+      priority,
     );
 
     // Go over the results and update the results map
@@ -360,7 +365,10 @@ class Engine extends TypedEmitter<EngineEvents> {
     return result.get(0) ?? err(new HubError("unavailable", "missing result"));
   }
 
-  async mergeMessagesToStore(messages: Message[]): Promise<Map<number, HubResult<number>>> {
+  async mergeMessagesToStore(
+    messages: Message[],
+    priority: RustWritePriority = "high",
+  ): Promise<Map<number, HubResult<number>>> {
     const linkMessages: IndexedMessage[] = [];
     const reactionMessages: IndexedMessage[] = [];
     const castMessages: IndexedMessage[] = [];
@@ -436,6 +444,7 @@ class Engine extends TypedEmitter<EngineEvents> {
         const start = Date.now();
         const storeResults: Map<number, HubResult<number>> = await store.mergeMessages(
           storeMessages.map((m) => m.message),
+          priority,
         );
         const duration = Date.now() - start;
         statsd().timing("storage.merge_messages", duration, { store: store.postfix.toString() });
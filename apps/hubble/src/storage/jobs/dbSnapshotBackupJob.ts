@@ -2,7 +2,15 @@ import { FarcasterNetwork, HubAsyncResult, HubError } from "@farcaster/hub-nodej
 import { Result, ResultAsync, err, ok } from "neverthrow";
 import cron from "node-cron";
 import { logger } from "../../utils/logger.js";
-import { rsDbSnapshotBackup } from "../../rustfunctions.js";
+import {
+  RustSnapshotHandle,
+  RustSnapshotProgress,
+  rsDbCancelSnapshot,
+  rsDbCreateSnapshotHandle,
+  rsDbGcSnapshots,
+  rsDbSnapshotBackup,
+  rsDbVerifySnapshot,
+} from "../../rustfunctions.js";
 import RocksDB from "../../storage/db/rocksdb.js";
 import { uploadToS3 } from "../../utils/snapshot.js";
 import SyncEngine from "../../network/sync/syncEngine.js";
@@ -12,6 +20,9 @@ import { HubOptions, S3_REGION, SNAPSHOT_S3_UPLOAD_BUCKET } from "../../hubble.j
 
 export const DEFAULT_DB_SNAPSHOT_BACKUP_JOB_CRON = "15 2 * * *"; // 2:15 am everyday
 
+// Snapshots that failed to upload are kept locally, but only the most recent ones
+const LOCAL_SNAPSHOTS_TO_KEEP = 2;
+
 const log = logger.child({
   component: "DbSnapshotJob",
 });
@@ -21,6 +32,7 @@ type SchedulerStatus = "started" | "stopped";
 export class DbSnapshotBackupJobScheduler {
   private _cronTask?: cron.ScheduledTask;
   private _running = false;
+  private _snapshotHandle?: RustSnapshotHandle;
 
   private _mainDb: RocksDB;
   private _trieDb: RocksDB;
@@ -48,6 +60,11 @@ export class DbSnapshotBackupJobScheduler {
     if (this._cronTask) {
       this._cronTask.stop();
     }
+
+    // Don't leave a backup running in the background
+    if (this._snapshotHandle) {
+      rsDbCancelSnapshot(this._snapshotHandle);
+    }
   }
 
   status(): SchedulerStatus {
@@ -69,13 +86,31 @@ export class DbSnapshotBackupJobScheduler {
     log.info({}, "starting Db Snapshot Backup job");
     const startTimestampMs = Date.now();
 
+    let lastPhase: RustSnapshotProgress["phase"] | undefined;
+    this._snapshotHandle = rsDbCreateSnapshotHandle((progress) => {
+      if (progress.phase !== lastPhase) {
+        log.info({ phase: progress.phase }, "Db Snapshot Backup phase started");
+        lastPhase = progress.phase;
+      }
+      log.debug({ progress }, "Db Snapshot Backup progress");
+    });
+
     // Back up the DB before opening it
     const tarGzResult = await ResultAsync.fromPromise(
-      rsDbSnapshotBackup(this._mainDb.rustDb, this._trieDb.rustDb, startTimestampMs),
+      rsDbSnapshotBackup(this._mainDb.rustDb, this._trieDb.rustDb, startTimestampMs, this._snapshotHandle),
       (e) => e as Error,
     );
+    this._snapshotHandle = undefined;
 
     if (tarGzResult.isOk()) {
+      // Don't upload a snapshot that doesn't match its own manifest
+      const verifyResult = await ResultAsync.fromPromise(rsDbVerifySnapshot(tarGzResult.value), (e) => e as Error);
+      if (verifyResult.isErr()) {
+        log.error({ error: verifyResult.error }, "snapshot failed verification, not uploading");
+        this._running = false;
+        return ok(undefined);
+      }
+
       const messageCount = await this._syncEngine.trie.items();
 
       // If snapshot to S3 flag is explicitly set, we throw an error if message count is zero,
@@ -114,6 +149,16 @@ export class DbSnapshotBackupJobScheduler {
       log.error({ error: tarGzResult.error }, "failed to create tar.gz snapshot backup for S3");
     }
 
+    const gcResult = await ResultAsync.fromPromise(
+      rsDbGcSnapshots(this._mainDb.rustDb, { keepLast: LOCAL_SNAPSHOTS_TO_KEEP }),
+      (e) => e as Error,
+    );
+    if (gcResult.isOk()) {
+      log.info({ deleted: gcResult.value }, "deleted old local snapshots");
+    } else {
+      log.warn({ error: gcResult.error }, "failed to delete old local snapshots");
+    }
+
     log.info({ timeTakenMs: Date.now() - startTimestampMs }, "finished Db Snapshot Backup job");
     this._running = false;
 
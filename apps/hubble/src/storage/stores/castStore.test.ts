@@ -597,6 +597,32 @@ describe("merge", () => {
   });
 });
 
+describe("simulateMerge", () => {
+  test("reports the messages a merge would delete, without merging", async () => {
+    await store.merge(castAdd);
+
+    const result = await store.simulateMerge(castRemove);
+    const { mergeConflicts, hubEvent } = result._unsafeUnwrap();
+    expect(mergeConflicts).toEqual([castAdd]);
+    expect(hubEvent.id).toEqual(0);
+    expect(hubEvent.mergeMessageBody?.message).toEqual(castRemove);
+    expect(hubEvent.mergeMessageBody?.deletedMessages).toEqual([castAdd]);
+
+    // Nothing was merged
+    await expect(store.getCastAdd(fid, castAdd.hash)).resolves.toEqual(castAdd);
+    await expect(store.getCastRemove(fid, castAdd.hash)).rejects.toThrow(HubError);
+  });
+
+  test("fails like the merge would", async () => {
+    await store.merge(castRemove);
+
+    const result = await store.simulateMerge(castAdd);
+    expect(result._unsafeUnwrapErr().errCode).toEqual("bad_request.conflict");
+    const duplicate = await store.simulateMerge(castRemove);
+    expect(duplicate._unsafeUnwrapErr().errCode).toEqual("bad_request.duplicate");
+  });
+});
+
 describe("revoke", () => {
   let revokedMessages: Message[] = [];
 
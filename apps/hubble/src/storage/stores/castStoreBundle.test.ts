@@ -135,4 +135,23 @@ describe("Add Bundles", () => {
     await expect(store.getCastAdd(fid, add3.hash)).rejects.toBeTruthy();
     await expect(store.getCastAdd(fid, add5.hash)).rejects.toBeTruthy();
   });
+
+  test("Each message in a bundle gets its own event", async () => {
+    const time = getFarcasterTime()._unsafeUnwrap() - 10;
+    const add = await generateAddWithTimestamp(fid, time + 1);
+    const remove = await generateRemoveWithTimestamp(fid, time + 2, add);
+
+    const results = await store.mergeMessages([add, remove]);
+    const addEventId = results.get(0)?._unsafeUnwrap() as number;
+    const removeEventId = results.get(1)?._unsafeUnwrap() as number;
+    expect(removeEventId).toBeGreaterThan(addEventId);
+
+    // The add was merged and then removed in the same bundle
+    const addEvent = (await eventHandler.getEvent(addEventId))._unsafeUnwrap();
+    expect(addEvent.mergeMessageBody?.message?.hash).toEqual(add.hash);
+    const removeEvent = (await eventHandler.getEvent(removeEventId))._unsafeUnwrap();
+    expect(removeEvent.mergeMessageBody?.message?.hash).toEqual(remove.hash);
+    expect(removeEvent.mergeMessageBody?.deletedMessages.map((m) => m.hash)).toEqual([add.hash]);
+    await expect(store.getCastAdd(fid, add.hash)).rejects.toBeTruthy();
+  });
 });
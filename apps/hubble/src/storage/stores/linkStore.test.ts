@@ -23,10 +23,11 @@ import {
   makeUserKey,
   putMessageTransaction,
 } from "../db/message.js";
-import { UserPostfix } from "../db/types.js";
+import { RootPrefix, UserPostfix } from "../db/types.js";
 import LinkStore from "./linkStore.js";
 import StoreEventHandler from "./storeEventHandler.js";
 import { putOnChainEventTransaction } from "../db/onChainEvent.js";
+import { performDbMigrations } from "../db/migrations/migrations.js";
 
 const db = jestRocksDB("protobufs.linkStore.test");
 const eventHandler = new StoreEventHandler(db);
@@ -706,6 +707,14 @@ describe("merge", () => {
       const primaryKey = makeMessagePrimaryKeyFromMessage(message);
       await expect(db.keysExist([badAddKey, primaryKey])).resolves.toEqual(ok([exists, exists]));
     };
+    // The lookups only check the padded keys, so the incorrectly padded keys have to be migrated first
+    const migrateKeyPadding = async (message: LinkAddMessage | LinkRemoveMessage) => {
+      const version = Buffer.alloc(4);
+      version.writeUInt32BE(11, 0);
+      await db.put(Buffer.from([RootPrefix.DBSchemaVersion]), version);
+      await expect(performDbMigrations(db, 11, 12)).resolves.toBe(true);
+      await expect(db.keysExist([makeIncorrectKey(message)])).resolves.toEqual(ok([false]));
+    };
 
     test("duplicate link add with incorrect padding", async () => {
       const earlierLinkAddIncorrectPadding = await Factories.LinkAddMessage.create({
@@ -714,6 +723,7 @@ describe("merge", () => {
 
       await insertWithIncorrectPadding(earlierLinkAddIncorrectPadding);
       await assertIncorrectPaddingExists(earlierLinkAddIncorrectPadding, true);
+      await migrateKeyPadding(earlierLinkAddIncorrectPadding);
 
       await expect(set.merge(linkAdd)).resolves.toBeGreaterThan(0);
       await expect(
@@ -730,6 +740,7 @@ describe("merge", () => {
 
       await insertWithIncorrectPadding(earlierLinkRemoveIncorrectPadding);
       await assertIncorrectPaddingExists(earlierLinkRemoveIncorrectPadding, true);
+      await migrateKeyPadding(earlierLinkRemoveIncorrectPadding);
 
       await expect(set.merge(linkRemove)).resolves.toBeGreaterThan(0);
       await expect(
@@ -749,6 +760,7 @@ describe("merge", () => {
 
       await insertWithIncorrectPadding(linkAddIncorrectPadding);
       await assertIncorrectPaddingExists(linkAddIncorrectPadding, true);
+      await migrateKeyPadding(linkAddIncorrectPadding);
 
       await expect(set.merge(laterLinkRemove)).resolves.toBeGreaterThan(0);
       await expect(
@@ -768,6 +780,7 @@ describe("merge", () => {
 
       await insertWithIncorrectPadding(linkRemoveIncorrectPadding);
       await assertIncorrectPaddingExists(linkRemoveIncorrectPadding, true);
+      await migrateKeyPadding(linkRemoveIncorrectPadding);
 
       await expect(set.merge(laterLinkAdd)).resolves.toBeGreaterThan(0);
       await expect(
@@ -802,6 +815,7 @@ describe("merge", () => {
     test("getLinkAdd with incorrect padding", async () => {
       await insertWithIncorrectPadding(linkAdd);
       await assertIncorrectPaddingExists(linkAdd, true);
+      await migrateKeyPadding(linkAdd);
       await expect(
         set.getLinkAdd(fid, linkAdd.data.linkBody.type, linkAdd.data.linkBody.targetFid as number),
       ).resolves.toEqual(linkAdd);
@@ -810,6 +824,7 @@ describe("merge", () => {
     test("getLinkRemove with incorrect padding", async () => {
       await insertWithIncorrectPadding(linkRemove);
       await assertIncorrectPaddingExists(linkRemove, true);
+      await migrateKeyPadding(linkRemove);
       await expect(
         set.getLinkRemove(fid, linkRemove.data.linkBody.type, linkRemove.data.linkBody.targetFid as number),
       ).resolves.toEqual(linkRemove);
@@ -8,6 +8,8 @@ import {
   revoke,
   rustErrorToHubError,
   rsMergeMany,
+  rsSimulateMerge,
+  RustWritePriority,
 } from "../../rustfunctions.js";
 import StoreEventHandler from "./storeEventHandler.js";
 import { MessagesPage, PageOptions } from "./types.js";
@@ -71,7 +73,10 @@ export abstract class RustStoreBase<TAdd extends Message, TRemove extends Messag
     return this._postfix;
   }
 
-  async mergeMessages(messages: Message[]): Promise<Map<number, HubResult<number>>> {
+  async mergeMessages(
+    messages: Message[],
+    priority: RustWritePriority = "high",
+  ): Promise<Map<number, HubResult<number>>> {
     const mergeResults: Map<number, HubResult<number>> = new Map();
 
     // First, filter out any prunable messages
@@ -97,6 +102,7 @@ export abstract class RustStoreBase<TAdd extends Message, TRemove extends Messag
       rsMergeMany(
         this._rustStore,
         encodedMessages.map((m) => m.bytes),
+        priority,
       ),
       rustErrorToHubError,
     );
@@ -152,6 +158,35 @@ export abstract class RustStoreBase<TAdd extends Message, TRemove extends Messag
     return hubEvent.id;
   }
 
+  /**
+   * What merging the message would do: the messages it would delete, and the HubEvent it would be merged with, which
+   * has an id of 0. Fails with the error the merge would fail with.
+   */
+  async simulateMerge(message: Message): HubAsyncResult<{ mergeConflicts: Message[]; hubEvent: HubEvent }> {
+    const prunableResult = await this._eventHandler.isPrunable(
+      // biome-ignore lint/suspicious/noExplicitAny: legacy code, avoid using ignore for new code
+      message as any,
+      this._postfix,
+      this._pruneSizeLimit,
+    );
+    if (prunableResult.isErr()) {
+      return err(prunableResult.error);
+    } else if (prunableResult.value) {
+      return err(new HubError("bad_request.prunable", "message would be pruned"));
+    }
+
+    const messageBytes = Message.encode(message).finish();
+    const result = await ResultAsync.fromPromise(rsSimulateMerge(this._rustStore, messageBytes), rustErrorToHubError);
+    if (result.isErr()) {
+      return err(result.error);
+    }
+
+    return ok({
+      mergeConflicts: result.value.mergeConflicts.map((bytes) => Message.decode(new Uint8Array(bytes))),
+      hubEvent: HubEvent.decode(new Uint8Array(result.value.hubEvent)),
+    });
+  }
+
   async revoke(message: Message): HubAsyncResult<number> {
     const messageBytes = Message.encode(message).finish();
     const result = await ResultAsync.fromPromise(revoke(this._rustStore, messageBytes), rustErrorToHubError);
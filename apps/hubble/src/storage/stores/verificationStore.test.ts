@@ -291,6 +291,30 @@ describe("merge", () => {
     });
   });
 
+  describe("with claim verification", () => {
+    const verifyingStore = new VerificationStore(db, eventHandler, { verifyClaims: true });
+
+    test("succeeds with a valid claim", async () => {
+      await expect(verifyingStore.merge(verificationAdd)).resolves.toBeGreaterThan(0);
+      await assertVerificationAddWins(verificationAdd);
+    });
+
+    test("fails when the claim is for another block hash", async () => {
+      const body = { ...verificationAdd.data.verificationAddAddressBody, blockHash: Factories.BlockHash.build() };
+      const invalidClaim = await Factories.VerificationAddEthAddressMessage.create({
+        data: { ...verificationAdd.data, verificationAddAddressBody: body },
+      });
+
+      await expect(verifyingStore.merge(invalidClaim)).rejects.toEqual(
+        new HubError("bad_request.validation_failure", "invalid claimSignature"),
+      );
+      await assertVerificationDoesNotExist(invalidClaim);
+
+      // Stores that don't verify claims leave it to the engine
+      await expect(set.merge(invalidClaim)).resolves.toBeGreaterThan(0);
+    });
+  });
+
   describe("VerificationRemove", () => {
     test("succeeds", async () => {
       await expect(set.merge(verificationRemove)).resolves.toBeGreaterThan(0);
@@ -16,13 +16,18 @@ import RocksDB from "storage/db/rocksdb.js";
 import { RustStoreBase } from "./rustStoreBase.js";
 import { messageDecode } from "../../storage/db/message.js";
 
+export type VerificationStoreOptions = StorePruneOptions & {
+  verifyClaims?: boolean; // Verify the claim signatures of verifications before they are merged
+};
+
 class VerificationStore extends RustStoreBase<VerificationAddAddressMessage, VerificationRemoveMessage> {
-  constructor(db: RocksDB, eventHandler: StoreEventHandler, options: StorePruneOptions = {}) {
+  constructor(db: RocksDB, eventHandler: StoreEventHandler, options: VerificationStoreOptions = {}) {
     const pruneSizeLimit = options.pruneSizeLimit ?? 0;
     const rustVerificationStore = rsCreateVerificationStore(
       db.rustDb,
       eventHandler.getRustStoreEventHandler(),
       pruneSizeLimit,
+      options.verifyClaims ?? false,
     );
 
     super(db, rustVerificationStore, UserPostfix.VerificationMessage, eventHandler, pruneSizeLimit);
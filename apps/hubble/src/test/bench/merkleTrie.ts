@@ -108,6 +108,6 @@ export const benchMerkleTrie = async ({
     await yieldToEventLoop();
   }
 
-  db.clear();
+  await db.clear();
   process.stderr.write("finished\n");
 };
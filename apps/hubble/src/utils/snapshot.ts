@@ -15,6 +15,9 @@ export type SnapshotMetadata = Partial<DbStats> & {
   serverDate: string;
 };
 
+// Written by the snapshot backup next to the chunks, it is uploaded but is not a chunk itself
+export const SNAPSHOT_MANIFEST_FILE = "manifest.json";
+
 export const isValidSnapshotMetadata = (data: Record<string, unknown>): data is SnapshotMetadata => {
   return data["keyBase"] !== undefined && data["timestamp"] !== undefined && data["serverDate"] !== undefined;
 };
@@ -134,7 +137,7 @@ export const uploadToS3 = async (
 
   const metadata: SnapshotMetadata = {
     keyBase,
-    chunks: files,
+    chunks: files.filter((file) => file !== SNAPSHOT_MANIFEST_FILE),
     timestamp: startTimestamp,
     serverDate: new Date(startTimestamp).toISOString(),
     ...(messageCount && { numMessages: messageCount }),